@@ -0,0 +1,39 @@
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_8};
+
+// text in whatever encoding to utf-8. in order: a bom, the xml prolog or
+// html meta charset, valid utf-8, and finally a guess from the bytes
+pub fn decode(bytes: &[u8]) -> String {
+    let encoding = Encoding::for_bom(bytes)
+        .map(|(e, _)| e)
+        .or_else(|| {
+            // some books lie about being utf-8
+            declared(bytes).filter(|&e| e != UTF_8 || std::str::from_utf8(bytes).is_ok())
+        })
+        .unwrap_or_else(|| {
+            if std::str::from_utf8(bytes).is_ok() {
+                return UTF_8;
+            }
+            let mut detector = EncodingDetector::new();
+            detector.feed(bytes, true);
+            detector.guess(None, true)
+        });
+    // strips the bom
+    encoding.decode_with_bom_removal(bytes).0.into_owned()
+}
+
+fn declared(bytes: &[u8]) -> Option<&'static Encoding> {
+    // declarations are ascii, and near the start
+    let head = &bytes[..bytes.len().min(1024)];
+    let head = String::from_utf8_lossy(head).to_lowercase();
+    ["encoding=", "charset="].iter().find_map(|key| {
+        let i = head.find(key)? + key.len();
+        let label: String = head[i..]
+            .trim_start_matches(['"', '\''])
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric() || "-_:.".contains(*c))
+            .collect();
+        // a readable declaration can't be utf-16
+        Encoding::for_label(label.as_bytes()).map(Encoding::output_encoding)
+    })
+}
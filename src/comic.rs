@@ -0,0 +1,177 @@
+use anyhow::{anyhow, Result};
+use roxmltree::Document;
+use std::{cmp::Ordering, fs::File, io::Read, path::Path, process::Command};
+
+use crate::{
+    epub::{Archive, Chapter},
+    Book,
+};
+
+const IMAGES: &[&str] = &[".gif", ".jpeg", ".jpg", ".png"];
+
+// a page for each image, in name order
+pub fn open(path: &str, meta: bool) -> Result<Book> {
+    let mut magic = [0; 4];
+    File::open(path)?.read_exact(&mut magic)?;
+    // plenty of cbr files are zips
+    let mut archive: Box<dyn Archive> = if &magic == b"PK\x03\x04" {
+        Box::new(zip::ZipArchive::new(File::open(path)?)?)
+    } else {
+        Box::new(Rar(path.to_string()))
+    };
+
+    let mut book = Book::default();
+    let mut names = archive.names()?;
+    if let Some(name) = names
+        .iter()
+        .find(|n| n.eq_ignore_ascii_case("ComicInfo.xml"))
+    {
+        let xml = String::from_utf8_lossy(&archive.read(name)?).into_owned();
+        comic_info(&xml, &mut book.meta);
+    }
+    if book.meta.is_empty() {
+        let stem = Path::new(path).file_stem().unwrap();
+        book.meta = format!("title: {}\n", stem.to_string_lossy());
+    }
+    names.retain(|n| {
+        let lower = n.to_lowercase();
+        IMAGES.iter().any(|ext| lower.ends_with(ext))
+            && !lower.starts_with("__macosx/")
+            && !lower.rsplit('/').next().unwrap().starts_with('.')
+    });
+    if names.is_empty() {
+        return Err(anyhow!("no images"));
+    }
+    book.meta.push_str(&format!("pages: {}\n", names.len()));
+    if meta {
+        return Ok(book);
+    }
+
+    names.sort_by(|a, b| natural(a, b));
+    for (i, name) in names.iter().enumerate() {
+        let mut c = Chapter::new(format!("page {}", i + 1));
+        c.text.push_str(&format!("[image: {}]", name));
+        c.images.push((0, book.images.len()));
+        // read when the page is shown
+        book.images.push(Vec::new());
+        book.chapters.push(c);
+    }
+    book.pages = Some(Pages {
+        archive,
+        names,
+        last: None,
+    });
+    Ok(book)
+}
+
+// the images of a comic by index, read from the archive as they're shown
+// rather than all of them on opening, which for cbr is an unrar each
+pub struct Pages {
+    archive: Box<dyn Archive>,
+    names: Vec<String>,
+    // the page read last, as it's drawn again on every key
+    last: Option<(usize, Vec<u8>)>,
+}
+
+impl Pages {
+    pub fn read(&mut self, i: usize) -> Result<Vec<u8>> {
+        match &self.last {
+            Some((n, data)) if *n == i => Ok(data.clone()),
+            _ => {
+                let data = self.archive.read(&self.names[i])?;
+                self.last = Some((i, data.clone()));
+                Ok(data)
+            }
+        }
+    }
+}
+
+// rar has no pure rust decoder, so use the unrar program
+struct Rar(String);
+
+impl Rar {
+    fn unrar(&self, args: &[&str], files: &[&str]) -> Result<Vec<u8>> {
+        let out = Command::new("unrar")
+            .args(args)
+            .arg(&self.0)
+            .args(files)
+            .output()
+            .map_err(|e| anyhow!("cbr needs unrar: {}", e))?;
+        if !out.status.success() {
+            return Err(anyhow!("unrar failed"));
+        }
+        Ok(out.stdout)
+    }
+}
+
+impl Archive for Rar {
+    fn names(&mut self) -> Result<Vec<String>> {
+        let out = self.unrar(&["lb", "-p-"], &[])?;
+        Ok(String::from_utf8_lossy(&out)
+            .lines()
+            .map(String::from)
+            .collect())
+    }
+    fn read(&mut self, name: &str) -> Result<Vec<u8>> {
+        self.unrar(&["p", "-inul", "-p-"], &[name])
+    }
+}
+
+// https://anansi-project.github.io/docs/comicinfo/intro
+fn comic_info(xml: &str, meta: &mut String) {
+    let doc = match Document::parse(xml) {
+        Ok(doc) => doc,
+        Err(_) => return,
+    };
+    for n in doc.root_element().children().filter(|n| n.is_element()) {
+        let name = match n.tag_name().name() {
+            "Title" => "title",
+            "Series" => "series",
+            "Number" => "number",
+            "Writer" => "creator",
+            "Publisher" => "publisher",
+            "Year" => "date",
+            "LanguageISO" => "language",
+            "Summary" => "description",
+            _ => continue,
+        };
+        if let Some(text) = n.text() {
+            meta.push_str(&format!("{}: {}\n", name, text.trim()));
+        }
+    }
+}
+
+// compare runs of digits by value, so page2 comes before page10
+fn natural(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+    loop {
+        match (a.peek(), b.peek()) {
+            (None, None) => return Ordering::Equal,
+            (None, _) => return Ordering::Less,
+            (_, None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let number = |s: &mut std::iter::Peekable<std::str::Chars>| {
+                    let mut n = String::new();
+                    while let Some(c) = s.next_if(char::is_ascii_digit) {
+                        n.push(c);
+                    }
+                    n.trim_start_matches('0').to_string()
+                };
+                let (x, y) = (number(&mut a), number(&mut b));
+                let ord = x.len().cmp(&y.len()).then(x.cmp(&y));
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let ord = x.cmp(y);
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
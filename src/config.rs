@@ -0,0 +1,113 @@
+use anyhow::Result;
+use serde::Deserialize;
+use std::{collections::HashMap, env, fs, io::ErrorKind, path::PathBuf};
+
+use crate::keys::{Action, Mode};
+
+// settings from bk.toml, named as the flags and :set options are. flags
+// given override them
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub width: u16,
+    pub justify: bool,
+    pub header: bool,
+    pub columns: bool,
+    pub line_height: f32,
+    pub overlap: usize,
+    pub regex: bool,
+    pub compact: bool,
+    pub indent: usize,
+    pub theme: String,
+    pub ruby: String,
+    pub punct: String,
+    pub no_css: bool,
+    pub style: Vec<String>,
+    pub front_matter: bool,
+    // where positions, bookmarks and such are saved
+    pub save: Option<String>,
+    pub hlsearch: bool,
+    pub word: bool,
+    pub wrapscan: bool,
+    #[serde(rename = "chaptersearch")]
+    pub chapter_search: bool,
+    pub fold: bool,
+    pub results: bool,
+    // actions of each view bound to other keys, eg next-page = ["Space",
+    // "n"] under [keys.page]
+    pub keys: HashMap<Mode, HashMap<Action, Vec<String>>>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            width: 75,
+            justify: false,
+            header: false,
+            columns: false,
+            line_height: 1.0,
+            overlap: 1,
+            regex: false,
+            compact: false,
+            indent: 0,
+            theme: String::from("default"),
+            ruby: String::from("inline"),
+            punct: String::new(),
+            no_css: false,
+            style: Vec::new(),
+            front_matter: false,
+            save: None,
+            hlsearch: true,
+            word: false,
+            wrapscan: false,
+            chapter_search: false,
+            fold: false,
+            results: false,
+            keys: HashMap::new(),
+        }
+    }
+}
+
+// $XDG_CONFIG_HOME/bk/bk.toml on any unix, macos included, as terminal
+// programs keep them
+#[cfg(unix)]
+fn path() -> Option<PathBuf> {
+    let dir = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| dirs::home_dir().map(|home| home.join(".config")))?;
+    Some(dir.join("bk").join("bk.toml"))
+}
+
+// beside the save file, in %APPDATA%
+#[cfg(not(unix))]
+fn path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("bk.toml"))
+}
+
+// where positions, bookmarks and such are saved without --state-file or
+// save in the config
+pub fn save_path() -> Result<PathBuf> {
+    let dir =
+        dirs::data_dir().ok_or_else(|| anyhow::anyhow!("no data directory, see --state-file"))?;
+    let path = dir.join("bk");
+    // saves from before, which were here on any unix
+    let old = dirs::home_dir().map(|home| home.join(".local/share/bk"));
+    match old {
+        Some(old) if !path.exists() && old.exists() => Ok(old),
+        _ => Ok(path),
+    }
+}
+
+// the defaults without a config file, or a directory for one
+pub fn load() -> Result<Config> {
+    let path = match path() {
+        Some(path) => path,
+        None => return Ok(Config::default()),
+    };
+    match fs::read_to_string(&path) {
+        Ok(s) => toml::from_str(&s).map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Config::default()),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -0,0 +1,233 @@
+use roxmltree::Node;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Align {
+    // back to normal inside a centered block
+    Left,
+    Center,
+    Right,
+}
+
+// the little of a book's css that's honored
+#[derive(Clone, Copy, Default)]
+pub struct Decl {
+    pub align: Option<Align>,
+    pub italic: Option<bool>,
+    pub bold: Option<bool>,
+    // display: none
+    pub hidden: Option<bool>,
+}
+
+impl Decl {
+    // from a block or style attribute, eg "text-align: center; font-weight: bold"
+    fn parse(block: &str) -> Decl {
+        let mut decl = Decl::default();
+        for (name, value) in block.split(';').filter_map(|d| d.split_once(':')) {
+            let value = value.trim_end().trim_end_matches("!important");
+            let value = value.trim().to_ascii_lowercase();
+            let value = value.as_str();
+            match name.trim().to_ascii_lowercase().as_str() {
+                "text-align" => {
+                    decl.align = match value {
+                        "center" => Some(Align::Center),
+                        "right" | "end" => Some(Align::Right),
+                        "left" | "start" | "justify" => Some(Align::Left),
+                        _ => None,
+                    }
+                }
+                "font-style" => {
+                    decl.italic = match value {
+                        "italic" | "oblique" => Some(true),
+                        "normal" => Some(false),
+                        _ => None,
+                    }
+                }
+                "font-weight" => {
+                    decl.bold = match value {
+                        "bold" | "bolder" => Some(true),
+                        "normal" | "lighter" => Some(false),
+                        n => n.parse::<u16>().ok().map(|n| n >= 600),
+                    }
+                }
+                "display" => decl.hidden = Some(value == "none"),
+                _ => (),
+            }
+        }
+        decl
+    }
+    // later declarations win
+    fn merge(&mut self, other: Decl) {
+        self.align = other.align.or(self.align);
+        self.italic = other.italic.or(self.italic);
+        self.bold = other.bold.or(self.bold);
+        self.hidden = other.hidden.or(self.hidden);
+    }
+}
+
+// tag.class#id, any part left out
+#[derive(Default)]
+struct Compound {
+    tag: Option<String>,
+    id: Option<String>,
+    classes: Vec<String>,
+}
+
+impl Compound {
+    // None for what isn't supported, like pseudo classes and attributes
+    fn parse(s: &str) -> Option<Compound> {
+        let mut compound = Compound::default();
+        let name = |c: char| c.is_alphanumeric() || c == '-' || c == '_';
+        let end = s.find(|c| !name(c)).unwrap_or(s.len());
+        if end > 0 {
+            compound.tag = Some(s[..end].to_ascii_lowercase());
+        }
+        let mut rest = s[end..].strip_prefix('*').unwrap_or(&s[end..]);
+        while !rest.is_empty() {
+            let kind = rest.chars().next().unwrap();
+            let rest_name = &rest[1..];
+            let end = rest_name.find(|c| !name(c)).unwrap_or(rest_name.len());
+            if end == 0 {
+                return None;
+            }
+            let value = rest_name[..end].to_string();
+            match kind {
+                '.' => compound.classes.push(value),
+                '#' => compound.id = Some(value),
+                _ => return None,
+            }
+            rest = &rest_name[end..];
+        }
+        Some(compound)
+    }
+    fn matches(&self, n: Node) -> bool {
+        let classes = n.attribute("class").unwrap_or_default();
+        self.tag
+            .as_ref()
+            .is_none_or(|tag| n.tag_name().name().eq_ignore_ascii_case(tag))
+            && self
+                .id
+                .as_deref()
+                .is_none_or(|id| n.attribute("id") == Some(id))
+            && self
+                .classes
+                .iter()
+                .all(|class| classes.split_ascii_whitespace().any(|c| c == class))
+    }
+}
+
+// compounds from the outermost, each with whether it's a child (>) of the one
+// before, rather than any descendant
+struct Selector(Vec<(Compound, bool)>);
+
+impl Selector {
+    fn parse(s: &str) -> Option<Selector> {
+        let s = s.replace('>', " > ");
+        let mut parts = Vec::new();
+        let mut child = false;
+        for token in s.split_whitespace() {
+            if token == ">" {
+                child = true;
+                continue;
+            }
+            parts.push((Compound::parse(token)?, child));
+            child = false;
+        }
+        if parts.is_empty() {
+            return None;
+        }
+        Some(Selector(parts))
+    }
+    // ids, classes and tags
+    fn specificity(&self) -> (usize, usize, usize) {
+        self.0.iter().fold((0, 0, 0), |(a, b, c), (part, _)| {
+            (
+                a + part.id.is_some() as usize,
+                b + part.classes.len(),
+                c + part.tag.is_some() as usize,
+            )
+        })
+    }
+    fn matches(&self, n: Node) -> bool {
+        let (last, rest) = self.0.split_last().unwrap();
+        if !last.0.matches(n) {
+            return false;
+        }
+        let mut child = last.1;
+        let mut node = n;
+        for (part, part_child) in rest.iter().rev() {
+            let found = if child {
+                node.parent_element().filter(|p| part.matches(*p))
+            } else {
+                node.ancestors().skip(1).find(|a| part.matches(*a))
+            };
+            match found {
+                Some(found) => node = found,
+                None => return false,
+            }
+            child = *part_child;
+        }
+        true
+    }
+}
+
+#[derive(Default)]
+pub struct Css {
+    // in order of specificity, then of the source
+    rules: Vec<(Selector, Decl)>,
+}
+
+impl Css {
+    // add a stylesheet's rules. at-rules like @media are skipped
+    pub fn parse(&mut self, sheet: &str) {
+        let mut sheet = sheet.to_string();
+        while let Some(start) = sheet.find("/*") {
+            let end = sheet[start..]
+                .find("*/")
+                .map_or(sheet.len(), |e| start + e + 2);
+            sheet.replace_range(start..end, " ");
+        }
+        let mut rest = sheet.as_str();
+        while let Some(open) = rest.find('{') {
+            let prelude = rest[..open].trim();
+            if prelude.starts_with('@') {
+                // @import url(a.css);
+                if let Some(semi) = prelude.find(';') {
+                    rest = &rest[rest.find('@').unwrap() + semi + 1..];
+                    continue;
+                }
+                let mut depth = 0;
+                let end = rest[open..].find(|c| {
+                    match c {
+                        '{' => depth += 1,
+                        '}' => depth -= 1,
+                        _ => (),
+                    }
+                    depth == 0
+                });
+                rest = end.map_or("", |end| &rest[open + end + 1..]);
+                continue;
+            }
+            let close = rest[open..].find('}').map_or(rest.len(), |c| open + c);
+            let decl = Decl::parse(&rest[open + 1..close]);
+            for selector in prelude.split(',').filter_map(Selector::parse) {
+                self.rules.push((selector, decl));
+            }
+            rest = rest.get(close + 1..).unwrap_or_default();
+        }
+        self.rules.sort_by_key(|rule| rule.0.specificity());
+    }
+    // what applies to an element, with its align and style attributes last
+    pub fn style(&self, n: Node) -> Decl {
+        let mut decl = Decl::default();
+        for (_, rule) in self.rules.iter().filter(|r| r.0.matches(n)) {
+            decl.merge(*rule);
+        }
+        if let Some(align) = n.attribute("align") {
+            decl.merge(Decl::parse(&format!("text-align: {}", align)));
+        }
+        if let Some(style) = n.attribute("style") {
+            decl.merge(Decl::parse(style));
+        }
+        decl
+    }
+}
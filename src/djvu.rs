@@ -0,0 +1,170 @@
+use anyhow::{anyhow, Result};
+use std::{collections::HashMap, path::Path, process::Command};
+
+use crate::{epub::Chapter, pdf::reflow, Book};
+
+// the hidden text layer, through the djvulibre tools. a chapter for each top
+// level bookmark, or for each page without any
+pub fn open(path: &str, meta: bool) -> Result<Book> {
+    let mut book = Book::default();
+    // lines of `key "value"`
+    let info = parse(&djvused(path, "print-meta")?);
+    for pair in info.windows(2) {
+        if let [Exp::Atom(key), Exp::Str(value)] = pair {
+            let key = match key.as_str() {
+                "title" | "Title" => "title",
+                "author" | "Author" => "creator",
+                "subject" | "Subject" => "subject",
+                "year" | "CreationDate" => "date",
+                _ => continue,
+            };
+            book.meta.push_str(&format!("{}: {}\n", key, value));
+        }
+    }
+    if !book.meta.starts_with("title:") {
+        let stem = Path::new(path).file_stem().unwrap();
+        let title = format!("title: {}\n", stem.to_string_lossy());
+        book.meta.insert_str(0, &title);
+    }
+    let pages = djvused(path, "n")?.trim().parse::<usize>()?;
+    book.meta.push_str(&format!("pages: {}\n", pages));
+    if meta {
+        return Ok(book);
+    }
+
+    // (bookmarks ("title" "#page" children..) ..)
+    let mut outline = HashMap::new();
+    let bookmarks = parse(&djvused(path, "print-outline")?);
+    for exp in bookmarks.iter().flat_map(Exp::list).skip(1) {
+        if let [Exp::Str(title), Exp::Str(url), ..] = exp.list() {
+            if let Some(n) = url.strip_prefix('#').and_then(|n| n.parse::<usize>().ok()) {
+                outline.entry(n).or_insert_with(|| title.clone());
+            }
+        }
+    }
+
+    // (page x0 y0 x1 y1 "text")
+    let out = Command::new("djvutxt")
+        .args(["--detail=page", path])
+        .output()
+        .map_err(|e| anyhow!("djvu needs djvutxt: {}", e))?;
+    let text = String::from_utf8_lossy(&out.stdout);
+    let by_page = outline.is_empty();
+    let mut c = Chapter::new(String::from("front"));
+    for (i, exp) in parse(&text).iter().enumerate() {
+        let n = i + 1;
+        if by_page || outline.contains_key(&n) {
+            let title = outline.remove(&n).unwrap_or_else(|| format!("page {}", n));
+            if !c.text.trim().is_empty() {
+                book.chapters.push(c);
+            }
+            c = Chapter::new(title);
+        }
+        if let Some(Exp::Str(text)) = exp.list().last() {
+            // paragraph and region separators
+            let text = text.replace(['\x1f', '\x1d', '\x0b'], "\n\n");
+            c.text.push_str(&reflow(&text));
+        }
+    }
+    if !c.text.trim().is_empty() {
+        book.chapters.push(c);
+    }
+
+    if book.chapters.is_empty() {
+        return Err(anyhow!("no text layer"));
+    }
+    Ok(book)
+}
+
+fn djvused(path: &str, script: &str) -> Result<String> {
+    let out = Command::new("djvused")
+        .args(["-u", "-e", script, path])
+        .output()
+        .map_err(|e| anyhow!("djvu needs djvused: {}", e))?;
+    if !out.status.success() {
+        return Err(anyhow!("{}", String::from_utf8_lossy(&out.stderr).trim()));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+enum Exp {
+    Atom(String),
+    Str(String),
+    List(Vec<Exp>),
+}
+
+impl Exp {
+    fn list(&self) -> &[Exp] {
+        match self {
+            Exp::List(v) => v,
+            _ => &[],
+        }
+    }
+}
+
+// the s-expressions djvulibre prints
+fn parse(s: &str) -> Vec<Exp> {
+    let mut stack = vec![Vec::new()];
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' => stack.push(Vec::new()),
+            ')' => {
+                if stack.len() > 1 {
+                    let list = stack.pop().unwrap();
+                    stack.last_mut().unwrap().push(Exp::List(list));
+                }
+            }
+            '"' => {
+                // octal escapes are bytes of utf-8
+                let mut bytes = Vec::new();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => match chars.next() {
+                            Some('n') => bytes.push(b'\n'),
+                            Some('t') => bytes.push(b'\t'),
+                            Some(d @ '0'..='7') => {
+                                let mut n = d.to_digit(8).unwrap();
+                                for _ in 0..2 {
+                                    match chars.peek().and_then(|d| d.to_digit(8)) {
+                                        Some(d) => {
+                                            n = n * 8 + d;
+                                            chars.next();
+                                        }
+                                        None => break,
+                                    }
+                                }
+                                bytes.push(n as u8);
+                            }
+                            Some(c) => {
+                                let mut buf = [0; 4];
+                                bytes.extend(c.encode_utf8(&mut buf).as_bytes());
+                            }
+                            None => break,
+                        },
+                        c => {
+                            let mut buf = [0; 4];
+                            bytes.extend(c.encode_utf8(&mut buf).as_bytes());
+                        }
+                    }
+                }
+                let s = String::from_utf8_lossy(&bytes).into_owned();
+                stack.last_mut().unwrap().push(Exp::Str(s));
+            }
+            c if c.is_whitespace() => (),
+            c => {
+                let mut atom = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == '(' || c == ')' || c == '"' {
+                        break;
+                    }
+                    atom.push(c);
+                    chars.next();
+                }
+                stack.last_mut().unwrap().push(Exp::Atom(atom));
+            }
+        }
+    }
+    stack.swap_remove(0)
+}
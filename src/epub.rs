@@ -1,7 +1,208 @@
-use anyhow::Result;
-use crossterm::style::{Attribute, Attributes};
+use anyhow::{anyhow, Result};
+use crossterm::{
+    cursor::MoveRight,
+    style::{Attribute, Attributes},
+};
+use hypher::Lang;
 use roxmltree::{Document, Node};
-use std::{collections::HashMap, fs::File, io::Read};
+use std::{
+    cmp::min,
+    collections::HashMap,
+    fs::{self, File},
+    io::Read,
+    iter,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use unicode_bidi::{bidi_class, BidiClass, Level, ParagraphBidiInfo};
+use unicode_segmentation::UnicodeSegmentation;
+
+#[cfg(feature = "highlight")]
+use crate::highlight::highlight;
+#[cfg(feature = "svg")]
+use crate::svg::rasterize;
+use crate::{
+    charset, cjk,
+    css::{Align, Css, Decl},
+    html, hyphenated, img, math, mobi, text_width, wrap, Book, TocEntry, SHY,
+};
+
+// cols for a tab, in tables too wide to draw
+pub const TAB: usize = 4;
+const BULLETS: [char; 3] = ['•', '◦', '▪'];
+const XLINK: &str = "http://www.w3.org/1999/xlink";
+const SVG: &str = "http://www.w3.org/2000/svg";
+const OPS: &str = "http://www.idpf.org/2007/ops";
+// drawn for <hr> and scene breaks
+const SEPARATOR: &str = "* * *";
+// by heading level: underlined, centered, newlines before and after
+pub const HEADINGS: [(bool, bool, usize, usize); 6] = [
+    (true, true, 2, 1),
+    (false, true, 1, 1),
+    (false, false, 1, 1),
+    (false, false, 1, 1),
+    (false, false, 1, 1),
+    (false, false, 1, 1),
+];
+// unicode has super and subscripts for these
+const SCRIPTS: [(&str, &str, &str); 2] = [
+    (
+        "0123456789+-=()abcdefghijklmnoprstuvwxyz",
+        "⁰¹²³⁴⁵⁶⁷⁸⁹⁺⁻⁼⁽⁾ᵃᵇᶜᵈᵉᶠᵍʰⁱʲᵏˡᵐⁿᵒᵖʳˢᵗᵘᵛʷˣʸᶻ",
+        "^",
+    ),
+    (
+        "0123456789+-=()aehijklmnoprstuvx",
+        "₀₁₂₃₄₅₆₇₈₉₊₋₌₍₎ₐₑₕᵢⱼₖₗₘₙₒₚᵣₛₜᵤᵥₓ",
+        "_",
+    ),
+];
+// font obfuscation, which only mangles the first bytes of fonts
+const OBFUSCATION: &[&str] = &[
+    "http://www.idpf.org/2008/embedding",
+    "http://ns.adobe.com/pdf/enc#RC",
+];
+
+// a foreground color
+pub type Rgb = (u8, u8, u8);
+
+// choices made while reading the markup
+#[derive(Clone, Copy)]
+pub struct Layout {
+    // a blank line between paragraphs
+    pub blank: bool,
+    // cols to indent their first line
+    pub indent: usize,
+    pub ruby: Ruby,
+    pub punct: Punct,
+    // honor the book's css
+    pub css: bool,
+}
+
+// where furigana go: in parentheses after their base, or on a line above
+#[derive(Clone, Copy, PartialEq)]
+pub enum Ruby {
+    Inline,
+    Above,
+}
+
+impl FromStr for Ruby {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "inline" => Ok(Ruby::Inline),
+            "above" => Ok(Ruby::Above),
+            _ => Err(String::from("expected inline or above")),
+        }
+    }
+}
+
+// fixes for badly made books, each opt in
+#[derive(Clone, Copy, Default)]
+pub struct Punct {
+    // Some(true) curls quotes, Some(false) straightens them
+    quotes: Option<bool>,
+    // -- and --- to an em dash
+    dashes: bool,
+    // runs of spaces to one
+    spaces: bool,
+    // zero width spaces and joiners, but not those changing how letters join
+    zero_width: bool,
+}
+
+impl FromStr for Punct {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut punct = Punct::default();
+        for rule in s.split(',').map(str::trim).filter(|r| !r.is_empty()) {
+            match rule {
+                "curly" => punct.quotes = Some(true),
+                "straight" => punct.quotes = Some(false),
+                "dashes" => punct.dashes = true,
+                "spaces" => punct.spaces = true,
+                "zero-width" => punct.zero_width = true,
+                _ => {
+                    return Err(format!(
+                        "expected curly, straight, dashes, spaces or zero-width, not {}",
+                        rule
+                    ))
+                }
+            }
+        }
+        Ok(punct)
+    }
+}
+
+impl Punct {
+    // text following the char prev
+    fn apply(&self, text: &str, mut prev: Option<char>) -> String {
+        // a quote after these opens
+        let opens =
+            |prev: Option<char>| prev.is_none_or(|p| p.is_whitespace() || "([{<—–-‘“«".contains(p));
+        let mut out = String::with_capacity(text.len());
+        let mut chars = text.chars().peekable();
+        while let Some(ch) = chars.next() {
+            let ch = match (ch, self.quotes) {
+                ('\u{200b}' | '\u{2060}' | '\u{feff}', _) if self.zero_width => continue,
+                ('-', _) if self.dashes && chars.peek() == Some(&'-') => {
+                    while chars.next_if_eq(&'-').is_some() {}
+                    '—'
+                }
+                (ch, _)
+                    if self.spaces
+                        && ch != '\n'
+                        && ch.is_whitespace()
+                        && prev.is_some_and(|p| p != '\n' && p.is_whitespace()) =>
+                {
+                    continue
+                }
+                ('"', Some(true)) if opens(prev) => '“',
+                ('"', Some(true)) => '”',
+                ('\'', Some(true)) if opens(prev) => '‘',
+                ('\'', Some(true)) => '’',
+                ('“' | '”' | '„', Some(false)) => '"',
+                ('‘' | '’' | '‚', Some(false)) => '\'',
+                (ch, _) => ch,
+            };
+            out.push(ch);
+            prev = Some(ch);
+        }
+        out
+    }
+}
+
+// the first of starts after byte, or the last before it
+fn near(mut starts: impl Iterator<Item = usize>, byte: usize, forward: bool) -> Option<usize> {
+    if forward {
+        starts.find(|&i| i > byte)
+    } else {
+        starts.take_while(|&i| i < byte).last()
+    }
+}
+
+// a chapter's markup, left in the book rather than kept for :source
+pub enum Source {
+    // the epub's path, and the file of it
+    Epub(String, String),
+    // the mobi's path, and the part of its text
+    Mobi(String, usize),
+}
+
+impl Source {
+    pub fn read(&self) -> Result<String> {
+        match self {
+            Source::Epub(path, name) => {
+                let text = charset::decode(&container(path)?.read(name)?);
+                // cleaned up as it was for rendering, so the offsets hold
+                match Document::parse(&text) {
+                    Ok(_) => Ok(text),
+                    Err(_) => Ok(html::xhtml(&text)),
+                }
+            }
+            Source::Mobi(path, part) => mobi::source(path, *part),
+        }
+    }
+}
 
 pub struct Chapter {
     pub title: String,
@@ -11,64 +212,307 @@ pub struct Chapter {
     // crossterm gives us a bitset but doesn't let us diff it, so store the state transition
     pub attrs: Vec<(usize, Attribute, Attributes)>,
     pub links: Vec<(usize, usize, String)>,
-    frag: Vec<(String, usize)>,
+    // start of each link the markup calls a footnote reference
+    pub notes: Vec<usize>,
+    pub frag: Vec<(String, usize)>,
+    // start, end, level
+    pub headings: Vec<(usize, usize, u8)>,
+    // byte, index into Book.images
+    pub images: Vec<(usize, usize)>,
+    // byte and src of images, for the reader to load into images
+    pub srcs: Vec<(usize, String)>,
+    // false for linear="no" spine items, out of the reading order
+    pub linear: bool,
+    // right to left paragraphs, with lines aligned right
+    pub rtl: bool,
+    // a title page or cover, its lines centered on the screen
+    pub title_page: bool,
+    // hyphenation patterns
+    pub lang: Option<Lang>,
+    pub layout: Layout,
+    // start, end, cols, and the column of a quote bar
+    pub indents: Vec<(usize, usize, usize, Option<usize>)>,
+    // start, cols and bar of the indents being rendered
+    open: Vec<(usize, usize, Option<usize>)>,
+    // the next number of ordered lists, None for bullets
+    lists: Vec<Option<u64>>,
+    // end of the last list marker or paragraph indent, so the block after it
+    // stays on its line
+    marker: Option<usize>,
+    // start, end, column widths. a line per row with tabs between cells, and
+    // empty lines for the borders
+    pub tables: Vec<(usize, usize, Vec<usize>)>,
+    // start, end of preformatted text, which keeps its lines
+    pub pres: Vec<(usize, usize)>,
+    // start, end of verse, which keeps its lines
+    pub verses: Vec<(usize, usize)>,
+    // start, end of base text and its ruby, drawn on a line above
+    pub rubies: Vec<(usize, usize, String)>,
+    // lines drawing the ruby of the next line
+    pub ruby_lines: Vec<usize>,
+    // start of each scene break line, drawn centered
+    pub breaks: Vec<usize>,
+    // foreground color changes, None for the default
+    pub colors: Vec<(usize, Option<Rgb>)>,
+    // start, end and text-align of blocks the css aligns, inner ones first
+    pub aligns: Vec<(usize, usize, Align)>,
+    // the stylesheets of the chapter being rendered
+    pub css: Css,
+    // where :source reads the markup the chapter was rendered from again
+    pub source: Option<Source>,
+    // start of each text node, and where it is in the source
+    pub offsets: Vec<(usize, usize)>,
+    // start of the table and cell being rendered
+    table: usize,
+    cell: usize,
+    // inside sup or sub, an index into SCRIPTS
+    script: Option<usize>,
+    // start of the pre being rendered
+    pre: Option<usize>,
+    // and its language, if it can be highlighted
+    code: Option<String>,
+    // start of the verse being rendered
+    verse: Option<usize>,
     state: Attributes,
 }
 
-pub struct Epub {
-    container: zip::ZipArchive<File>,
+pub trait Archive {
+    fn names(&mut self) -> Result<Vec<String>>;
+    fn read(&mut self, name: &str) -> Result<Vec<u8>>;
+}
+
+impl Archive for zip::ZipArchive<File> {
+    fn names(&mut self) -> Result<Vec<String>> {
+        Ok(self.file_names().map(String::from).collect())
+    }
+    fn read(&mut self, name: &str) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        self.by_name(name)?.read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+// an unzipped epub
+struct Dir(PathBuf);
+
+impl Archive for Dir {
+    fn names(&mut self) -> Result<Vec<String>> {
+        let mut names = Vec::new();
+        let mut dirs = vec![self.0.clone()];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(dir)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    dirs.push(path);
+                } else if let Ok(name) = path.strip_prefix(&self.0) {
+                    let parts: Vec<_> = name.iter().map(|p| p.to_string_lossy()).collect();
+                    names.push(parts.join("/"));
+                }
+            }
+        }
+        Ok(names)
+    }
+    fn read(&mut self, name: &str) -> Result<Vec<u8>> {
+        Ok(fs::read(self.0.join(name))?)
+    }
+}
+
+struct Epub {
+    path: String,
+    container: Box<dyn Archive>,
     rootdir: String,
-    pub chapters: Vec<Chapter>,
-    pub links: HashMap<String, (usize, usize)>,
-    pub meta: String,
+    chapters: Vec<Chapter>,
+    links: HashMap<String, (usize, usize)>,
+    meta: String,
+    // level, title, path, fragment
+    toc: Vec<(usize, String, String, Option<String>)>,
+    // path -> chapter
+    files: HashMap<String, usize>,
+    images: Vec<Vec<u8>>,
+    // path -> index into images
+    image_files: HashMap<String, usize>,
+    // page-progression-direction="rtl"
+    rtl: bool,
+    layout: Layout,
+    // manifest href of the cover image
+    cover: Option<String>,
+    // type, title, path, fragment
+    landmarks: Vec<(String, String, String, Option<String>)>,
+    // label, path, fragment of the print edition's pages
+    page_list: Vec<(String, String, Option<String>)>,
 }
 
-impl Epub {
-    pub fn new(path: &str, meta: bool) -> Result<Self> {
-        let file = File::open(path)?;
-        let mut epub = Epub {
-            container: zip::ZipArchive::new(file)?,
-            rootdir: String::new(),
-            chapters: Vec::new(),
-            links: HashMap::new(),
-            meta: String::new(),
-        };
-        let chapters = epub.get_spine()?;
-        if !meta {
-            epub.get_chapters(chapters);
+// an unpacked epub, or the zip
+fn container(path: &str) -> Result<Box<dyn Archive>> {
+    if Path::new(path).is_dir() {
+        Ok(Box::new(Dir(PathBuf::from(path))))
+    } else {
+        Ok(Box::new(zip::ZipArchive::new(File::open(path)?)?))
+    }
+}
+
+pub fn open(path: &str, meta: bool, layout: Layout) -> Result<Book> {
+    let mut epub = Epub {
+        path: path.to_string(),
+        container: container(path)?,
+        rootdir: String::new(),
+        chapters: Vec::new(),
+        links: HashMap::new(),
+        meta: String::new(),
+        toc: Vec::new(),
+        files: HashMap::new(),
+        images: Vec::new(),
+        image_files: HashMap::new(),
+        rtl: false,
+        layout,
+        cover: None,
+        landmarks: Vec::new(),
+        page_list: Vec::new(),
+    };
+    let chapters = epub.get_spine()?;
+    if !meta {
+        epub.check_encryption()?;
+        epub.get_chapters(chapters);
+        if epub.chapters.is_empty() {
+            return Err(anyhow!("no readable text"));
         }
-        Ok(epub)
     }
-    fn get_text(&mut self, name: &str) -> String {
-        let mut text = String::new();
-        self.container
-            .by_name(name)
-            .unwrap()
-            .read_to_string(&mut text)
-            .unwrap();
-        text
+    let cover = match epub.cover.take() {
+        Some(path) if !meta => epub.image(&path),
+        _ => None,
+    };
+    let files = epub.files;
+    let links = &epub.links;
+    // chapter and byte of a path and fragment
+    let locate = |path: &str, frag: Option<String>| {
+        let &chapter = files.get(path)?;
+        // links are keyed by file name
+        let name = path.rsplit('/').next().unwrap();
+        let byte = frag
+            .and_then(|id| links.get(&format!("{}#{}", name, id)))
+            .filter(|&&(c, _)| c == chapter)
+            .map_or(0, |&(_, byte)| byte);
+        Some((chapter, byte))
+    };
+    // epub3 calls it bodymatter, the epub2 guide text
+    let start = epub
+        .landmarks
+        .iter()
+        .filter(|(kind, ..)| {
+            kind.split_whitespace()
+                .any(|k| k == "bodymatter" || k == "text")
+        })
+        .find_map(|(_, _, path, frag)| locate(path, frag.clone()));
+    let landmarks = epub
+        .landmarks
+        .into_iter()
+        .filter_map(|(kind, title, path, frag)| {
+            let (chapter, byte) = locate(&path, frag)?;
+            let title = if title.is_empty() { kind } else { title };
+            Some((title, chapter, byte))
+        })
+        .collect();
+    let page_list = epub
+        .page_list
+        .into_iter()
+        .filter_map(|(label, path, frag)| {
+            let (chapter, byte) = locate(&path, frag)?;
+            Some((label, chapter, byte))
+        })
+        .collect();
+    let toc = epub
+        .toc
+        .into_iter()
+        .filter_map(|(level, title, path, frag)| {
+            let (chapter, byte) = locate(&path, frag)?;
+            Some(TocEntry {
+                title,
+                level,
+                chapter,
+                byte,
+                synthetic: false,
+            })
+        })
+        .collect();
+    Ok(Book {
+        chapters: epub.chapters,
+        links: epub.links,
+        meta: epub.meta,
+        images: epub.images,
+        toc,
+        cover,
+        start,
+        landmarks,
+        page_list,
+        ..Default::default()
+    })
+}
+
+impl Epub {
+    fn get_text(&mut self, name: &str) -> Result<String> {
+        let bytes = self.container.read(name)?;
+        Ok(charset::decode(&bytes))
     }
-    fn get_chapters(&mut self, spine: Vec<(String, String)>) {
-        for (title, path) in spine {
-            let xml = self.get_text(&format!("{}{}", self.rootdir, path));
+    fn get_chapters(&mut self, spine: Vec<(Option<String>, String, bool)>) {
+        for (title, path, linear) in spine {
+            let name = format!("{}{}", self.rootdir, path);
+            // a spine item missing from the book
+            let text = match self.get_text(&name) {
+                Ok(text) => text,
+                Err(_) => continue,
+            };
             // https://github.com/RazrFalcon/roxmltree/issues/12
-            // UnknownEntityReference for HTML entities
-            let doc = Document::parse(&xml).unwrap();
-            let body = doc.root_element().last_element_child().unwrap();
-            let state = Attributes::default();
-            let mut c = Chapter {
-                title,
-                text: String::new(),
-                lines: Vec::new(),
-                attrs: vec![(0, Attribute::Reset, state)],
-                state,
-                links: Vec::new(),
-                frag: Vec::new(),
+            // UnknownEntityReference for HTML entities, so clean up what
+            // doesn't parse as the html backend does
+            let xml;
+            let doc = match Document::parse(&text) {
+                Ok(doc) => doc,
+                Err(_) => {
+                    xml = html::xhtml(&text);
+                    match Document::parse(&xml) {
+                        Ok(doc) => doc,
+                        Err(_) => continue,
+                    }
+                }
             };
+            let body = match doc.root_element().last_element_child() {
+                Some(body) => body,
+                None => continue,
+            };
+            let mut c = Chapter::new(title.unwrap_or_default());
+            c.linear = linear;
+            c.layout = self.layout;
+            c.rtl = [body, doc.root_element()]
+                .iter()
+                .find_map(|n| n.attribute("dir"))
+                .map_or(self.rtl, |dir| dir == "rtl");
+            c.title_page = [doc.root_element(), body]
+                .iter()
+                .copied()
+                .chain(body.children().filter(Node::is_element))
+                .any(|n| has_class(n, &["titlepage", "title-page", "halftitlepage", "cover"]));
+            if self.layout.css {
+                c.css = self.css(&doc, dirname(&path));
+            }
             render(body, &mut c);
+            c.css = Css::default();
             if c.text.trim().is_empty() {
                 continue;
             }
+            c.source = Some(Source::Epub(self.path.clone(), name));
+            if c.title.is_empty() {
+                c.title = label(&c, &path);
+            }
+            for (byte, src) in c.srcs.drain(..) {
+                let i = match src.strip_prefix("data:") {
+                    Some(url) => self.data_image(url),
+                    None => self.image(&resolve(dirname(&path), &src)),
+                };
+                if let Some(i) = i {
+                    c.images.push((byte, i));
+                }
+            }
+            self.files.insert(resolve("", &path), self.chapters.len());
             let relative = path.rsplit('/').next().unwrap();
             self.links
                 .insert(relative.to_string(), (self.chapters.len(), 0));
@@ -84,8 +528,90 @@ impl Epub {
             self.chapters.push(c);
         }
     }
-    fn get_spine(&mut self) -> Result<Vec<(String, String)>> {
-        let xml = self.get_text("META-INF/container.xml");
+    // linked stylesheets and style elements
+    fn css(&mut self, doc: &Document, dir: &str) -> Css {
+        let mut css = Css::default();
+        for n in doc.descendants() {
+            if n.has_tag_name("style") {
+                css.parse(n.text().unwrap_or_default());
+            }
+            if !n.has_tag_name("link") || n.attribute("rel") != Some("stylesheet") {
+                continue;
+            }
+            let path = match n.attribute("href") {
+                Some(href) => format!("{}{}", self.rootdir, resolve(dir, href)),
+                None => continue,
+            };
+            if let Ok(bytes) = self.container.read(&path) {
+                css.parse(&charset::decode(&bytes));
+            }
+        }
+        css
+    }
+    fn image(&mut self, path: &str) -> Option<usize> {
+        if let Some(&i) = self.image_files.get(path) {
+            return Some(i);
+        }
+        let mut data = self
+            .container
+            .read(&format!("{}{}", self.rootdir, path))
+            .ok()?;
+        if path.ends_with(".svg") {
+            data = rasterize(&data).unwrap_or(data);
+        }
+        self.images.push(data);
+        self.image_files
+            .insert(path.to_string(), self.images.len() - 1);
+        Some(self.images.len() - 1)
+    }
+    // the part of a data url after data:, like inline svgs are passed
+    fn data_image(&mut self, url: &str) -> Option<usize> {
+        let (mime, data) = url.split_once(',')?;
+        let mut data = match mime.strip_suffix(";base64") {
+            Some(_) => base64::decode(data).ok()?,
+            None => data.as_bytes().to_vec(),
+        };
+        if mime.starts_with("image/svg") {
+            data = rasterize(&data).unwrap_or(data);
+        }
+        self.images.push(data);
+        Some(self.images.len() - 1)
+    }
+    // obfuscated fonts are fine, since they aren't used
+    fn check_encryption(&mut self) -> Result<()> {
+        let xml = match self.container.read("META-INF/encryption.xml") {
+            Ok(xml) => String::from_utf8_lossy(&xml).into_owned(),
+            Err(_) => return Ok(()),
+        };
+        let doc = Document::parse(&xml)?;
+        let drm = doc
+            .descendants()
+            .filter(|n| n.has_tag_name("EncryptedData"))
+            .any(|n| {
+                let method = n
+                    .descendants()
+                    .find(|n| n.has_tag_name("EncryptionMethod"))
+                    .and_then(|n| n.attribute("Algorithm"))
+                    .unwrap_or_default();
+                let uri = n
+                    .descendants()
+                    .find(|n| n.has_tag_name("CipherReference"))
+                    .and_then(|n| n.attribute("URI"))
+                    .unwrap_or_default()
+                    .to_lowercase();
+                let font = [".otf", ".ttf", ".woff", ".woff2"]
+                    .iter()
+                    .any(|ext| uri.ends_with(ext));
+                !OBFUSCATION.contains(&method) && !font
+            });
+        if drm {
+            return Err(anyhow!("this book is DRM-protected"));
+        }
+        Ok(())
+    }
+    // label, path, linear
+    fn get_spine(&mut self) -> Result<Vec<(Option<String>, String, bool)>> {
+        let xml = self.get_text("META-INF/container.xml")?;
         let doc = Document::parse(&xml)?;
         let path = doc
             .descendants()
@@ -93,7 +619,7 @@ impl Epub {
             .unwrap()
             .attribute("full-path")
             .unwrap();
-        let xml = self.get_text(path);
+        let xml = self.get_text(path)?;
         let doc = Document::parse(&xml)?;
 
         // zip expects unix path even on windows
@@ -109,59 +635,798 @@ impl Epub {
         let manifest_node = children.next().unwrap();
         let spine_node = children.next().unwrap();
 
-        meta_node.children().filter(Node::is_element).for_each(|n| {
-            let name = n.tag_name().name();
-            let text = n.text();
-            if text.is_some() && name != "meta" {
-                self.meta
-                    .push_str(&format!("{}: {}\n", name, text.unwrap()));
-            }
-        });
+        self.meta = metadata(meta_node);
+        self.rtl = spine_node.attribute("page-progression-direction") == Some("rtl");
         manifest_node
             .children()
             .filter(Node::is_element)
             .for_each(|n| {
                 manifest.insert(n.attribute("id").unwrap(), n.attribute("href").unwrap());
             });
-        if doc.root_element().attribute("version") == Some("3.0") {
-            let path = manifest_node
-                .children()
-                .find(|n| n.attribute("properties") == Some("nav"))
-                .unwrap()
-                .attribute("href")
-                .unwrap();
-            let xml = self.get_text(&format!("{}{}", self.rootdir, path));
+        // epub3 marks the item, epub2 names it in the metadata
+        self.cover = manifest_node
+            .children()
+            .find(|n| {
+                n.attribute("properties")
+                    .is_some_and(|p| p.split_whitespace().any(|p| p == "cover-image"))
+            })
+            .and_then(|n| n.attribute("href"))
+            .or_else(|| {
+                let id = meta_node
+                    .children()
+                    .find(|n| n.has_tag_name("meta") && n.attribute("name") == Some("cover"))?
+                    .attribute("content")?;
+                manifest.get(id).copied()
+            })
+            .map(|href| resolve("", href));
+        let epub3 = doc
+            .root_element()
+            .attribute("version")
+            .is_some_and(|v| v.starts_with('3'));
+        let nav_path = manifest_node
+            .children()
+            .find(|n| {
+                n.attribute("properties")
+                    .is_some_and(|p| p.split_ascii_whitespace().any(|p| p == "nav"))
+            })
+            .and_then(|n| n.attribute("href"));
+        if let (true, Some(path)) = (epub3, nav_path) {
+            let xml = self.get_text(&format!("{}{}", self.rootdir, path))?;
             let doc = Document::parse(&xml)?;
-            epub3(doc, &mut nav);
-        } else {
+            landmarks_nav(&doc, dirname(path), &mut self.landmarks);
+            page_list_nav(&doc, dirname(path), &mut self.page_list);
+            toc_nav(doc, dirname(path), &mut self.toc);
+        }
+        // the epub2 guide, which epub3 books may also keep
+        if self.landmarks.is_empty() {
+            if let Some(guide) = children.find(|n| n.has_tag_name("guide")) {
+                for n in guide.children().filter(|n| n.has_tag_name("reference")) {
+                    if let (Some(kind), Some(href)) = (n.attribute("type"), n.attribute("href")) {
+                        let title = n.attribute("title").unwrap_or(kind).to_string();
+                        let path = resolve("", href);
+                        self.landmarks
+                            .push((kind.to_string(), title, path, fragment(href)));
+                    }
+                }
+            }
+        }
+        // epub3 books may still carry an ncx for older readers
+        if self.toc.is_empty() {
             let id = spine_node.attribute("toc").unwrap_or("ncx");
-            let path = manifest.get(id).unwrap();
-            let xml = self.get_text(&format!("{}{}", self.rootdir, path));
-            let doc = Document::parse(&xml)?;
-            epub2(doc, &mut nav);
+            if let Some(path) = manifest.get(id) {
+                let xml = self.get_text(&format!("{}{}", self.rootdir, path))?;
+                let doc = Document::parse(&xml)?;
+                if self.page_list.is_empty() {
+                    ncx_pages(&doc, dirname(path), &mut self.page_list);
+                }
+                ncx(doc, dirname(path), &mut self.toc);
+            }
+        }
+        for (_, title, path, _) in &self.toc {
+            nav.entry(path.clone()).or_insert_with(|| title.clone());
         }
         Ok(spine_node
             .children()
             .filter(Node::is_element)
-            .enumerate()
-            .map(|(i, n)| {
+            .map(|n| {
                 let id = n.attribute("idref").unwrap();
                 let path = manifest.remove(id).unwrap();
-                let label = nav.remove(path).unwrap_or_else(|| i.to_string());
-                (label, path.to_string())
+                let label = nav.remove(&resolve("", path));
+                let linear = n.attribute("linear") != Some("no");
+                (label, path.to_string(), linear)
             })
             .collect())
     }
 }
 
 impl Chapter {
+    pub fn new(title: String) -> Self {
+        let state = Attributes::default();
+        Chapter {
+            title,
+            text: String::new(),
+            lines: Vec::new(),
+            attrs: vec![(0, Attribute::Reset, state)],
+            state,
+            links: Vec::new(),
+            frag: Vec::new(),
+            headings: Vec::new(),
+            images: Vec::new(),
+            srcs: Vec::new(),
+            linear: true,
+            rtl: false,
+            title_page: false,
+            lang: None,
+            layout: Layout {
+                blank: true,
+                indent: 0,
+                ruby: Ruby::Inline,
+                punct: Punct::default(),
+                css: true,
+            },
+            indents: Vec::new(),
+            open: Vec::new(),
+            lists: Vec::new(),
+            marker: None,
+            tables: Vec::new(),
+            table: 0,
+            cell: 0,
+            script: None,
+            pre: None,
+            code: None,
+            pres: Vec::new(),
+            verse: None,
+            verses: Vec::new(),
+            breaks: Vec::new(),
+            notes: Vec::new(),
+            rubies: Vec::new(),
+            ruby_lines: Vec::new(),
+            colors: Vec::new(),
+            aligns: Vec::new(),
+            css: Css::default(),
+            source: None,
+            offsets: Vec::new(),
+        }
+    }
+    // move everything from byte `at` on into a new chapter
+    pub fn split_off(&mut self, at: usize) -> Chapter {
+        let mut c = Chapter::new(String::new());
+        c.rtl = self.rtl;
+        c.title_page = self.title_page;
+        c.layout = self.layout;
+        c.text = self.text.split_off(at);
+        let n = self.attrs.iter().take_while(|a| a.0 < at).count().max(1);
+        c.attrs[0].2 = self.attrs[n - 1].2;
+        c.state = self.state;
+        c.attrs
+            .extend(self.attrs.drain(n..).map(|(pos, a, s)| (pos - at, a, s)));
+        self.state = c.attrs[0].2;
+        let n = self.links.iter().take_while(|l| l.0 < at).count();
+        c.links = self
+            .links
+            .drain(n..)
+            .map(|(start, end, url)| (start - at, end - at, url))
+            .collect();
+        self.frag.retain(|(id, pos)| {
+            if *pos >= at {
+                c.frag.push((id.clone(), pos - at));
+            }
+            *pos < at
+        });
+        let n = self.headings.iter().take_while(|h| h.0 < at).count();
+        c.headings = self
+            .headings
+            .drain(n..)
+            .map(|(start, end, level)| (start - at, end - at, level))
+            .collect();
+        for &(start, end, cols, bar) in &self.indents {
+            if end > at {
+                c.indents
+                    .push((start.saturating_sub(at), end - at, cols, bar));
+            }
+        }
+        self.indents.retain(|i| i.0 < at);
+        for i in &mut self.indents {
+            i.1 = min(i.1, at);
+        }
+        for &(start, end, align) in &self.aligns {
+            if end > at {
+                c.aligns.push((start.saturating_sub(at), end - at, align));
+            }
+        }
+        self.aligns.retain(|a| a.0 < at);
+        for a in &mut self.aligns {
+            a.1 = min(a.1, at);
+        }
+        let n = self.tables.iter().take_while(|t| t.0 < at).count();
+        c.tables = self
+            .tables
+            .drain(n..)
+            .map(|(start, end, cols)| (start - at, end - at, cols))
+            .collect();
+        let n = self.pres.iter().take_while(|p| p.0 < at).count();
+        c.pres = self
+            .pres
+            .drain(n..)
+            .map(|(start, end)| (start - at, end - at))
+            .collect();
+        let n = self.verses.iter().take_while(|v| v.0 < at).count();
+        c.verses = self
+            .verses
+            .drain(n..)
+            .map(|(start, end)| (start - at, end - at))
+            .collect();
+        let n = self.rubies.iter().take_while(|r| r.0 < at).count();
+        c.rubies = self
+            .rubies
+            .drain(n..)
+            .map(|(start, end, ruby)| (start - at, end - at, ruby))
+            .collect();
+        let n = self.breaks.iter().take_while(|&&b| b < at).count();
+        c.breaks = self.breaks.drain(n..).map(|b| b - at).collect();
+        let n = self.notes.iter().take_while(|&&b| b < at).count();
+        c.notes = self.notes.drain(n..).map(|b| b - at).collect();
+        let n = self.colors.iter().take_while(|p| p.0 < at).count();
+        c.colors = self
+            .colors
+            .drain(n..)
+            .map(|(pos, rgb)| (pos - at, rgb))
+            .collect();
+        let n = self.srcs.iter().take_while(|s| s.0 < at).count();
+        c.srcs = self
+            .srcs
+            .drain(n..)
+            .map(|(pos, src)| (pos - at, src))
+            .collect();
+        let n = self.images.iter().take_while(|i| i.0 < at).count();
+        c.images = self
+            .images
+            .drain(n..)
+            .map(|(pos, i)| (pos - at, i))
+            .collect();
+        c
+    }
+    // indent what's rendered until indent_end, with a bar for quotes
+    pub fn indent_start(&mut self, cols: usize, bar: bool) {
+        let outer = self.open.last().map_or(0, |o| o.1);
+        let bar = if bar { Some(outer) } else { None };
+        self.open.push((self.text.len(), outer + cols, bar));
+    }
+    pub fn indent_end(&mut self) {
+        let (start, cols, bar) = self.open.pop().unwrap();
+        // blank lines before the content stay outside
+        let rest = &self.text[start..];
+        let start = start + rest.len() - rest.trim_start_matches('\n').len();
+        if start < self.text.len() {
+            self.indents.push((start, self.text.len(), cols, bar));
+        }
+    }
+    fn containing(
+        &self,
+        byte: usize,
+    ) -> impl Iterator<Item = &(usize, usize, usize, Option<usize>)> {
+        self.indents
+            .iter()
+            .filter(move |i| i.0 <= byte && byte < i.1)
+    }
+    // cols of the line starting at byte, leaving at least half the width.
+    // wrapped lines of verse hang
+    pub fn indent_at(&self, byte: usize, width: usize) -> usize {
+        let cols = self.containing(byte).map(|i| i.2).max().unwrap_or(0);
+        let hanging = self.in_verse(byte) && !self.text[..byte].ends_with('\n');
+        min(cols + 2 * hanging as usize, width / 2)
+    }
+    // columns to skip before a line
+    pub fn indent(&self, line: usize, width: usize) -> usize {
+        let (start, end) = self.lines[line];
+        let indent = self.indent_at(start, width);
+        let centered = self.headings.iter().any(|&(a, b, level)| {
+            a <= start
+                && start < b
+                && HEADINGS
+                    .get((level as usize).saturating_sub(1))
+                    .is_some_and(|h| h.1)
+        }) || self.breaks.binary_search(&start).is_ok()
+            || self.title_page;
+        let free = (width - indent).saturating_sub(text_width(&self.text[start..end]));
+        match self.align(start) {
+            Some(Align::Center) => return indent + free / 2,
+            Some(Align::Right) => return indent + free,
+            Some(Align::Left) => return indent,
+            None => (),
+        }
+        if centered {
+            indent + free / 2
+        } else if self.rtl
+            && self.image_at(start).is_none()
+            && !self.in_table(start)
+            && !self.in_pre(start)
+        {
+            indent + free
+        } else {
+            indent
+        }
+    }
+    // nothing to draw, unlike the rows of an image
+    pub fn blank(&self, line: usize) -> bool {
+        let (start, end) = self.lines[line];
+        let image = line > 0 && start == end && self.lines[line - 1].1 == start;
+        !image && self.text[start..end].trim().is_empty()
+    }
+    // how the css aligns the line starting at byte
+    fn align(&self, byte: usize) -> Option<Align> {
+        self.aligns
+            .iter()
+            .find(|a| a.0 <= byte && byte < a.1)
+            .map(|a| a.2)
+    }
+    // extra cols for each space to fill out a line. none for the last line of
+    // a paragraph, or lines laid out some other way
+    pub fn justify(&self, line: usize, width: usize) -> Vec<usize> {
+        let (start, end) = self.lines[line];
+        let text = &self.text[start..end];
+        let last = self.text[end..].chars().next().is_none_or(|c| c == '\n');
+        let heading = self.headings.iter().any(|h| h.0 <= start && start < h.1);
+        let spaces = text.matches(' ').count();
+        if last
+            || heading
+            || spaces == 0
+            || self.rtl
+            || self.in_table(start)
+            || self.in_pre(start)
+            || self.in_verse(start)
+            || self.title_page
+            || self.image_at(start).is_some()
+            || self.align(start).is_some_and(|a| a != Align::Left)
+        {
+            return Vec::new();
+        }
+        let used = self.indent_at(start, width) + text_width(text) + self.hyphen(line) as usize;
+        let free = width.saturating_sub(used);
+        (0..spaces)
+            .map(|i| free / spaces + (i < free % spaces) as usize)
+            .collect()
+    }
+    pub fn hyphen(&self, line: usize) -> bool {
+        let end = self.lines[line].1;
+        let soft = self.text[..end].ends_with(SHY);
+        !self.in_pre(end) && (soft || self.lang.is_some() && hyphenated(&self.text, end))
+    }
+    // drawn after a line: a hyphen, or an arrow for a broken line of
+    // preformatted text
+    pub fn end_mark(&self, line: usize) -> Option<char> {
+        let end = self.lines[line].1;
+        if self.in_pre(end) && !self.text[end..].starts_with('\n') {
+            Some('↩')
+        } else if self.hyphen(line) {
+            Some('-')
+        } else {
+            None
+        }
+    }
+    // byte ranges of a line in display order, and whether each runs right to
+    // left. None for lines without right to left text
+    pub fn visual(&self, line: usize) -> Option<Vec<(usize, usize, bool)>> {
+        let (start, end) = self.lines[line];
+        let rtl = self.text[start..end]
+            .chars()
+            .any(|c| matches!(bidi_class(c), BidiClass::R | BidiClass::AL));
+        if start == end || !(rtl || self.rtl) || self.in_table(start) {
+            return None;
+        }
+        let para_start = self.text[..start].rfind('\n').map_or(0, |n| n + 1);
+        let para_end = self.text[end..]
+            .find('\n')
+            .map_or(self.text.len(), |n| end + n);
+        let level = if self.rtl { Some(Level::rtl()) } else { None };
+        let info = ParagraphBidiInfo::new(&self.text[para_start..para_end], level);
+        let (levels, runs) = info.visual_runs(start - para_start..end - para_start);
+        let runs = runs
+            .into_iter()
+            .map(|r| {
+                (
+                    para_start + r.start,
+                    para_start + r.end,
+                    levels[r.start].is_rtl(),
+                )
+            })
+            .collect();
+        Some(runs)
+    }
+    // cursor movement over the indent, drawing quote bars
+    pub fn margin(&self, line: usize, width: usize) -> String {
+        let indent = self.indent(line, width);
+        let mut bars: Vec<_> = self
+            .containing(self.lines[line].0)
+            .filter_map(|i| i.3)
+            .filter(|&bar| bar < indent)
+            .collect();
+        bars.sort_unstable();
+        bars.dedup();
+        let mut s = String::new();
+        let mut col = 0;
+        for bar in bars.into_iter().chain(iter::once(indent)) {
+            if bar > col {
+                s.push_str(&MoveRight((bar - col) as u16).to_string());
+            }
+            if bar < indent {
+                s.push('│');
+            }
+            col = bar + 1;
+        }
+        s
+    }
     fn render(&mut self, n: Node, open: Attribute, close: Attribute) {
+        // nested, eg <b><strong>, closing the inner one would end both
+        if self.state.has(open) {
+            return self.render_text(n);
+        }
+        self.set(open);
+        self.render_text(n);
+        self.unset(open, close);
+    }
+    pub fn set(&mut self, open: Attribute) {
         self.state.set(open);
         self.attrs.push((self.text.len(), open, self.state));
-        self.render_text(n);
+    }
+    pub fn unset(&mut self, open: Attribute, close: Attribute) {
         self.state.unset(open);
         self.attrs.push((self.text.len(), close, self.state));
     }
+    // text with the punctuation fixes asked for
+    pub fn push_text(&mut self, text: &str) {
+        let text = self.layout.punct.apply(text, self.text.chars().next_back());
+        self.text.push_str(&text);
+    }
+    // start a block
+    pub fn newline(&mut self) {
+        if self.marker != Some(self.text.len()) {
+            self.text.push('\n');
+        }
+    }
+    // start a block, after a blank line unless compact, or after verse and
+    // scene breaks. paragraphs indent their first line
+    pub fn paragraph_start(&mut self, paragraph: bool) {
+        let len = self.text.len();
+        let verse = self.verses.last().is_some_and(|v| v.1 == len);
+        let scene = self
+            .breaks
+            .last()
+            .is_some_and(|&b| b + SEPARATOR.len() + 1 == len);
+        if self.layout.blank || verse || scene {
+            self.newline();
+        } else if self.marker != Some(self.text.len()) {
+            self.line_start();
+        }
+        if paragraph && self.layout.indent > 0 && self.marker != Some(self.text.len()) {
+            // non-breaking, so wrap and justify leave them be
+            self.text
+                .extend(iter::repeat_n('\u{a0}', self.layout.indent));
+            self.marker = Some(self.text.len());
+        }
+    }
+    // code in a language syntect knows is colored, the rest dimmed
+    pub fn pre_start(&mut self, lang: Option<&str>) {
+        self.newline();
+        self.line_start();
+        self.pre = Some(self.text.len());
+        self.code = lang
+            .filter(|&lang| highlight("", lang).is_some())
+            .map(String::from);
+        if self.code.is_none() {
+            self.set(Attribute::Dim);
+        }
+    }
+    // text as is, with tabs expanded
+    pub fn pre_text(&mut self, text: &str) {
+        let text = text.replace("\r\n", "\n");
+        // a newline right after <pre> isn't content
+        let text = if self.pre == Some(self.text.len()) {
+            text.strip_prefix('\n').unwrap_or(&text)
+        } else {
+            &text
+        };
+        for c in text.chars() {
+            if c == '\t' {
+                let line = self.text.rfind('\n').map_or(0, |n| n + 1);
+                let col = text_width(&self.text[line..]);
+                self.text.extend(iter::repeat_n(' ', TAB - col % TAB));
+            } else {
+                self.text.push(c);
+            }
+        }
+    }
+    pub fn pre_end(&mut self) {
+        if self.text.ends_with('\n') {
+            self.text.pop();
+        }
+        let start = self.pre.take().unwrap_or(self.text.len());
+        match self.code.take() {
+            Some(lang) => {
+                let colors = highlight(&self.text[start..], &lang).unwrap_or_default();
+                self.colors.extend(
+                    colors
+                        .into_iter()
+                        .map(|(pos, rgb)| (start + pos, Some(rgb))),
+                );
+                self.colors.push((self.text.len(), None));
+            }
+            None => self.unset(Attribute::Dim, Attribute::NormalIntensity),
+        }
+        self.pres.push((start, self.text.len()));
+        self.text.push('\n');
+    }
+    pub fn color_at(&self, byte: usize) -> Option<Rgb> {
+        let n = self.colors.partition_point(|c| c.0 <= byte);
+        self.colors.get(n.checked_sub(1)?)?.1
+    }
+    pub fn in_pre(&self, byte: usize) -> bool {
+        self.pres.iter().any(|p| p.0 <= byte && byte < p.1)
+    }
+    pub fn in_quote(&self, byte: usize) -> bool {
+        self.containing(byte).any(|i| i.3.is_some())
+    }
+    // the start of the paragraph after or before byte
+    pub fn paragraph(&self, byte: usize, forward: bool) -> Option<usize> {
+        let text = &self.text;
+        let starts = iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .filter(|&i| i < text.len() && !text[i..].starts_with('\n'));
+        near(starts, byte, forward)
+    }
+    // the start of the sentence after or before byte
+    pub fn sentence(&self, byte: usize, forward: bool) -> Option<usize> {
+        let starts = self
+            .text
+            .split_sentence_bound_indices()
+            .filter(|(_, s)| !s.trim().is_empty())
+            .map(|(i, _)| i);
+        near(starts, byte, forward)
+    }
+    pub fn scene_break(&mut self) {
+        self.line_start();
+        self.newline();
+        self.breaks.push(self.text.len());
+        self.text.push_str(SEPARATOR);
+        self.text.push('\n');
+    }
+    pub fn verse_start(&mut self) {
+        self.line_start();
+        self.verse = Some(self.text.len());
+    }
+    // stanzas get a blank line even when compact
+    pub fn stanza_start(&mut self) {
+        self.line_start();
+        if !self.text.is_empty() && !self.text.ends_with("\n\n") {
+            self.text.push('\n');
+        }
+    }
+    pub fn verse_end(&mut self) {
+        self.line_start();
+        let start = self.verse.take().unwrap_or(self.text.len());
+        self.verses.push((start, self.text.len()));
+    }
+    fn in_verse(&self, byte: usize) -> bool {
+        self.verses.iter().any(|v| v.0 <= byte && byte < v.1)
+    }
+    fn line_start(&mut self) {
+        if !self.text.is_empty() && !self.text.ends_with('\n') {
+            self.text.push('\n');
+        }
+    }
+    pub fn list_start(&mut self, start: Option<u64>) {
+        self.newline();
+        self.lists.push(start);
+        self.indent_start(2, false);
+    }
+    pub fn list_end(&mut self) {
+        self.indent_end();
+        self.lists.pop();
+    }
+    // the marker hangs, wrapped lines line up with the text after it
+    pub fn item_start(&mut self) {
+        self.line_start();
+        let marker = match self.lists.last_mut() {
+            Some(Some(n)) => {
+                *n += 1;
+                format!("{}. ", *n - 1)
+            }
+            _ => {
+                let depth = self.lists.len().saturating_sub(1);
+                format!("{} ", BULLETS[depth % BULLETS.len()])
+            }
+        };
+        self.text.push_str(&marker);
+        self.marker = Some(self.text.len());
+        self.indent_start(text_width(&marker), false);
+    }
+    pub fn item_end(&mut self) {
+        self.indent_end();
+        self.line_start();
+    }
+    pub fn table_start(&mut self) {
+        self.newline();
+        self.line_start();
+        self.table = self.text.len();
+        // top border
+        self.text.push('\n');
+    }
+    pub fn cell_start(&mut self) {
+        if !self.text.ends_with('\n') {
+            self.text.push('\t');
+        }
+        self.cell = self.text.len();
+        self.marker = Some(self.cell);
+    }
+    // cells stay on one line
+    pub fn cell_end(&mut self) {
+        while self.text.len() > self.cell && self.text.ends_with(char::is_whitespace) {
+            self.text.pop();
+        }
+        let len = self.text.len();
+        for a in &mut self.attrs {
+            a.0 = min(a.0, len);
+        }
+        for l in &mut self.links {
+            l.1 = min(l.1, len);
+        }
+        for f in &mut self.frag {
+            f.1 = min(f.1, len);
+        }
+        let cell = self.text[self.cell..].replace(['\n', '\t'], " ");
+        self.text.replace_range(self.cell.., &cell);
+    }
+    pub fn row_end(&mut self, header: bool) {
+        // an empty line would be a border
+        if self.text.ends_with('\n') {
+            self.text.push(' ');
+        }
+        self.text.push('\n');
+        if header {
+            self.text.push('\n');
+        }
+    }
+    pub fn table_end(&mut self) {
+        let start = self.table;
+        if self.text.len() == start + 1 {
+            return;
+        }
+        // bottom border
+        self.text.push('\n');
+        let mut cols = Vec::new();
+        for row in self.text[start..].lines() {
+            for (i, cell) in row.split('\t').enumerate() {
+                if i == cols.len() {
+                    cols.push(0);
+                }
+                cols[i] = cols[i].max(text_width(cell));
+            }
+        }
+        self.tables.push((start, self.text.len(), cols));
+    }
+    // the table at byte, if it fits in the width
+    fn in_table(&self, byte: usize) -> bool {
+        self.tables.iter().any(|t| t.0 <= byte && byte < t.1)
+    }
+    fn boxed(&self, byte: usize, width: usize) -> Option<&(usize, usize, Vec<usize>)> {
+        self.tables.iter().find(|&&(start, end, ref cols)| {
+            let total = cols.iter().sum::<usize>() + 3 * cols.len() + 1;
+            start <= byte && byte < end && self.indent_at(start, width) + total <= width
+        })
+    }
+    // like crate::wrap, but rows of tables that fit aren't broken
+    // sizes are the cells of each image, which get that many lines
+    pub fn wrap(&mut self, width: usize, sizes: &[Option<(u16, u16)>]) {
+        let mut lines = Vec::new();
+        let mut pos = 0;
+        // false for tables, true for pres
+        let mut fixed: Vec<_> = self
+            .tables
+            .iter()
+            .filter(|t| self.boxed(t.0, width).is_some())
+            .map(|t| (t.0, t.1, false))
+            .chain(self.pres.iter().map(|p| (p.0, p.1, true)))
+            .collect();
+        fixed.sort_unstable();
+        for (start, end, pre) in fixed {
+            // a pre in a table
+            if start < pos {
+                continue;
+            }
+            let text = &self.text[pos..start];
+            let wrapped = wrap(text, width, self.lang, |b| self.indent_at(pos + b, width));
+            lines.extend(wrapped.into_iter().map(|(a, b)| (pos + a, pos + b)));
+            let mut a = start;
+            for row in self.text[start..end].split_terminator('\n') {
+                let cols = width - self.indent_at(a, width);
+                if pre && text_width(row) > cols {
+                    // leave a col for the arrow
+                    let mut line = a;
+                    let mut used = 0;
+                    for (i, g) in row.grapheme_indices(true) {
+                        let w = text_width(g);
+                        if used + w > cols - 1 {
+                            lines.push((line, a + i));
+                            line = a + i;
+                            used = 0;
+                        }
+                        used += w;
+                    }
+                    lines.push((line, a + row.len()));
+                } else {
+                    lines.push((a, a + row.len()));
+                }
+                a += row.len() + 1;
+            }
+            // and the newline ending a pre's last line
+            pos = end + (pre && self.text[end..].starts_with('\n')) as usize;
+        }
+        let wrapped = wrap(&self.text[pos..], width, self.lang, |b| {
+            self.indent_at(pos + b, width)
+        });
+        lines.extend(wrapped.into_iter().map(|(a, b)| (pos + a, pos + b)));
+
+        // drawable images get a line for each row, the first covering the
+        // whole placeholder. lines with ruby get an empty one above
+        self.lines = Vec::with_capacity(lines.len());
+        self.ruby_lines.clear();
+        let mut lines = lines.into_iter().peekable();
+        while let Some((start, mut end)) = lines.next() {
+            if self.rubies.iter().any(|r| start <= r.0 && r.0 < end) {
+                self.ruby_lines.push(self.lines.len());
+                self.lines.push((start, start));
+            }
+            let size = self.image_at(start).and_then(|i| sizes[i]);
+            if size.is_some() {
+                end = start
+                    + self.text[start..]
+                        .find('\n')
+                        .unwrap_or(self.text.len() - start);
+                while lines.next_if(|l| l.0 < end).is_some() {}
+            }
+            self.lines.push((start, end));
+            let rows = size.map_or(1, |size| self.image_size(size, start, width).1);
+            for _ in 1..rows {
+                self.lines.push((end, end));
+            }
+        }
+    }
+    // a placeholder for an image, shown if it can't be drawn. the alt text
+    // goes between image_start and image_end
+    pub fn image_start(&mut self, src: Option<&str>) {
+        self.text.push('\n');
+        if let Some(src) = src {
+            self.srcs.push((self.text.len(), src.to_string()));
+        }
+        self.set(Attribute::Dim);
+        self.text.push_str("[image: ");
+    }
+    pub fn image_end(&mut self) {
+        if self.text.ends_with("[image: ") {
+            self.text.truncate(self.text.len() - 2);
+        }
+        self.text.push(']');
+        self.unset(Attribute::Dim, Attribute::NormalIntensity);
+        self.text.push('\n');
+    }
+    // the image on the line starting at byte
+    pub fn image_at(&self, byte: usize) -> Option<usize> {
+        let n = self.images.binary_search_by_key(&byte, |i| i.0).ok()?;
+        Some(self.images[n].1)
+    }
+    // cols and rows of an image at byte, at most its own size
+    pub fn image_size(&self, size: (u16, u16), byte: usize, width: usize) -> (u16, u16) {
+        let cols = width - self.indent_at(byte, width);
+        img::fit(size, cols as u16, u16::MAX)
+    }
+    // draw the borders of a table line, s being the styled text
+    pub fn table_line(&self, line: usize, width: usize, s: String) -> String {
+        let (start, end) = self.lines[line];
+        let (first, last, cols) = match self.boxed(start, width) {
+            Some(t) => t,
+            None => return s.replace('\t', &" ".repeat(TAB)),
+        };
+        if start == end {
+            let (left, mid, right) = if start == *first {
+                ('┌', "┬", '┐')
+            } else if end + 1 == *last {
+                ('└', "┴", '┘')
+            } else {
+                ('├', "┼", '┤')
+            };
+            let bars: Vec<_> = cols.iter().map(|&w| "─".repeat(w + 2)).collect();
+            return format!("{}{}{}", left, bars.join(mid), right);
+        }
+        let cells: Vec<_> = self.text[start..end].split('\t').collect();
+        let mut parts = s.split('\t');
+        let mut out = String::from("│");
+        for (i, &w) in cols.iter().enumerate() {
+            let part = parts.next().unwrap_or_default();
+            let pad = w - cells.get(i).map_or(0, |c| text_width(c));
+            out.push_str(&format!(" {}{} │", part, " ".repeat(pad)));
+        }
+        out
+    }
     fn render_text(&mut self, n: Node) {
         for child in n.children() {
             render(child, self);
@@ -169,111 +1434,672 @@ impl Chapter {
     }
 }
 
-fn render(n: Node, c: &mut Chapter) {
+pub fn render(n: Node, c: &mut Chapter) {
     if n.is_text() {
+        c.offsets.push((c.text.len(), n.range().start));
         let text = n.text().unwrap();
+        if c.pre.is_some() {
+            return c.pre_text(text);
+        }
         let content: Vec<_> = text.split_ascii_whitespace().collect();
 
-        if text.starts_with(char::is_whitespace) {
+        // no leading space on a line
+        let start = c.text.is_empty() || c.text.ends_with('\n') || c.marker == Some(c.text.len());
+        if content.is_empty() {
+            if !text.is_empty() && !start {
+                c.text.push(' ');
+            }
+            return;
+        }
+        if c.verse.is_some() && start {
+            let cols = verse_indent(n, text);
+            c.text.extend(iter::repeat_n('\u{a0}', cols));
+        }
+        if text.starts_with(char::is_whitespace) && !start {
             c.text.push(' ');
         }
-        c.text.push_str(&content.join(" "));
+        let content = content.join(" ");
+        match c.script {
+            Some(i) => c.text.push_str(&script(&content, i)),
+            None => c.push_text(&content),
+        }
         if text.ends_with(char::is_whitespace) {
             c.text.push(' ');
         }
         return;
     }
 
-    if let Some(id) = n.attribute("id") {
+    let style = if c.layout.css {
+        c.css.style(n)
+    } else {
+        Decl::default()
+    };
+    if style.hidden == Some(true) {
+        return;
+    }
+
+    let verse = c.verse.is_none() && c.pre.is_none() && is_verse(n);
+    if verse {
+        c.verse_start();
+    }
+
+    // turn italic and bold on, or off inside of them
+    let start = c.text.len();
+    let mut toggled = Vec::new();
+    for (on, attr, close) in [
+        (style.italic, Attribute::Italic, Attribute::NoItalic),
+        (style.bold, Attribute::Bold, Attribute::NormalIntensity),
+    ] {
+        match on {
+            Some(on) if on != c.state.has(attr) => {
+                if on {
+                    c.set(attr);
+                } else {
+                    c.unset(attr, close);
+                }
+                toggled.push((on, attr, close));
+            }
+            _ => (),
+        }
+    }
+
+    // kepubs wrap every sentence in an id'd span
+    let kobo = n.attribute("class") == Some("koboSpan");
+    if let Some(id) = n.attribute("id").filter(|_| !kobo) {
         c.frag.push((id.to_string(), c.text.len()));
     }
 
+    // fictionbook names are mixed in here, they don't clash with xhtml
     match n.tag_name().name() {
-        "br" => c.text.push('\n'),
-        "hr" => c.text.push_str("\n* * *\n"),
-        "img" => c.text.push_str("\n[IMG]\n"),
-        "a" => {
-            match n.attribute("href") {
-                // TODO open external urls in browser
-                Some(url) if !url.starts_with("http") => {
-                    let start = c.text.len();
-                    c.render(n, Attribute::Underlined, Attribute::NoUnderline);
-                    c.links.push((start, c.text.len(), url.to_string()));
+        "br" | "empty-line" => c.text.push('\n'),
+        "hr" => c.scene_break(),
+        "img" | "image" => {
+            let src = n
+                .attribute("src")
+                .or_else(|| n.attribute((XLINK, "href")))
+                .or_else(|| n.attribute("href"));
+            c.image_start(src);
+            let alt = n.attribute("alt").map(String::from).or_else(|| caption(n));
+            let alt: Vec<_> = alt.iter().flat_map(|a| a.split_whitespace()).collect();
+            c.text.push_str(&alt.join(" "));
+            c.image_end();
+        }
+        "a" => match n.attribute("href").or_else(|| n.attribute((XLINK, "href"))) {
+            Some(url) => {
+                let start = c.text.len();
+                // epub3, dpub-aria and fictionbook
+                let note = has_class(n, &["noteref"])
+                    || n.attribute("role") == Some("doc-noteref")
+                    || n.attribute("type") == Some("note");
+                if note {
+                    c.notes.push(start);
                 }
-                _ => c.render_text(n),
+                c.render(n, Attribute::Underlined, Attribute::NoUnderline);
+                c.links.push((start, c.text.len(), url.to_string()));
             }
+            None => c.render_text(n),
+        },
+        // covers wrap an <image> in an svg, drawn as any image
+        "svg" if !n.descendants().any(|d| d.has_tag_name("image")) => {
+            let src = base64::encode(svg_source(n));
+            c.image_start(Some(&format!("data:image/svg+xml;base64,{}", src)));
+            c.text.push_str(&svg_alt(n));
+            c.image_end();
         }
-        "em" => c.render(n, Attribute::Italic, Attribute::NoItalic),
-        "strong" => c.render(n, Attribute::Bold, Attribute::NoBold),
-        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
-            c.text.push('\n');
-            c.render(n, Attribute::Bold, Attribute::NoBold);
-            c.text.push('\n');
+        "ruby" => ruby(n, c),
+        "math" => {
+            let text = math::math(n);
+            if n.attribute("display") == Some("block") {
+                c.line_start();
+                c.text.push_str(&text);
+                c.text.push('\n');
+            } else {
+                c.text.push_str(&text);
+            }
         }
-        "blockquote" | "div" | "p" | "tr" => {
-            // TODO compress newlines
-            c.text.push('\n');
+        "em" | "emphasis" | "i" => c.render(n, Attribute::Italic, Attribute::NoItalic),
+        // NoBold is double underline in many terminals
+        "strong" | "b" => c.render(n, Attribute::Bold, Attribute::NormalIntensity),
+        "u" => c.render(n, Attribute::Underlined, Attribute::NoUnderline),
+        name @ ("sup" | "sub") => {
+            let outer = c.script.replace((name == "sub") as usize);
+            c.render_text(n);
+            c.script = outer;
+        }
+        name @ ("h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "title" | "subtitle") => {
+            let level = match name {
+                "title" => 1,
+                "subtitle" => 3,
+                _ => name.as_bytes()[1] - b'0',
+            };
+            let (underline, _, before, after) = HEADINGS[level as usize - 1];
+            // no gap at the top of a chapter
+            let before = if c.text.trim().is_empty() { 1 } else { before };
+            c.text.push_str(&"\n".repeat(before));
+            let start = c.text.len();
+            if underline {
+                c.set(Attribute::Underlined);
+            }
+            c.render(n, Attribute::Bold, Attribute::NormalIntensity);
+            if underline {
+                c.unset(Attribute::Underlined, Attribute::NoUnderline);
+            }
+            c.headings.push((start, c.text.len(), level));
+            c.text.push_str(&"\n".repeat(after));
+        }
+        "pre" if c.pre.is_none() => {
+            // <pre><code class="language-rust">
+            let lang = n
+                .descendants()
+                .filter_map(|d| d.attribute("class"))
+                .flat_map(str::split_ascii_whitespace)
+                .find_map(|class| {
+                    class
+                        .strip_prefix("language-")
+                        .or_else(|| class.strip_prefix("lang-"))
+                });
+            c.pre_start(lang);
+            c.render_text(n);
+            c.pre_end();
+        }
+        "blockquote" => {
+            c.newline();
+            c.indent_start(2, true);
             c.render_text(n);
+            c.indent_end();
             c.text.push('\n');
         }
+        "p" | "div" if is_scene_break(n) => c.scene_break(),
+        name @ ("div" | "p" | "tr" | "cite" | "epigraph" | "poem" | "stanza" | "v"
+        | "text-author") => {
+            if c.verse.is_some() {
+                if is_stanza(n) {
+                    c.stanza_start();
+                } else {
+                    c.line_start();
+                }
+                c.render_text(n);
+                c.line_start();
+            } else {
+                // TODO compress newlines
+                c.paragraph_start(name == "p");
+                c.render_text(n);
+                c.text.push('\n');
+            }
+        }
+        name @ ("ul" | "ol") => {
+            let start = match name {
+                "ol" => Some(
+                    n.attribute("start")
+                        .and_then(|s| s.parse().ok())
+                        .unwrap_or(1),
+                ),
+                _ => None,
+            };
+            c.list_start(start);
+            c.render_text(n);
+            c.list_end();
+        }
+        "table" if !n.ancestors().skip(1).any(|a| a.has_tag_name("table")) => table(n, c),
         "li" => {
-            c.text.push_str("\n- ");
+            c.item_start();
             c.render_text(n);
-            c.text.push('\n');
+            c.item_end();
         }
         _ => c.render_text(n),
     }
+    for (on, attr, close) in toggled.into_iter().rev() {
+        if on {
+            c.unset(attr, close);
+        } else {
+            c.set(attr);
+        }
+    }
+    // blocks only, a span can't center its line
+    if let Some(align) = style.align.filter(|_| c.text[start..].contains('\n')) {
+        c.aligns.push((start, c.text.len(), align));
+    }
+    if verse {
+        c.verse_end();
+    }
+}
+
+fn classes<'a>(n: Node<'a, '_>) -> impl Iterator<Item = &'a str> {
+    let class = n.attribute("class").unwrap_or_default();
+    let epub_type = n.attribute((OPS, "type")).unwrap_or_default();
+    class
+        .split_ascii_whitespace()
+        .chain(epub_type.split_ascii_whitespace())
+}
+
+// by class or epub:type, without a prefix like z3998: or a plural s
+fn has_class(n: Node, names: &[&str]) -> bool {
+    classes(n)
+        .map(|class| class.rsplit(':').next().unwrap())
+        .any(|word| names.contains(&word.to_ascii_lowercase().trim_end_matches('s')))
+}
+
+fn is_verse(n: Node) -> bool {
+    n.has_tag_name("poem") || has_class(n, &["poem", "poetry", "verse", "stanza"])
 }
 
-fn epub2(doc: Document, nav: &mut HashMap<String, String>) {
-    doc.descendants()
-        .find(|n| n.has_tag_name("navMap"))
-        .unwrap()
+// a paragraph of just asterisks or the like, or an empty one with a class
+// like scene-break
+fn is_scene_break(n: Node) -> bool {
+    let mut chars = n
         .descendants()
-        .filter(|n| n.has_tag_name("navPoint"))
-        .for_each(|n| {
-            let path = n
-                .descendants()
-                .find(|n| n.has_tag_name("content"))
-                .unwrap()
-                .attribute("src")
-                .unwrap()
-                .split('#')
-                .next()
-                .unwrap()
-                .to_string();
-            let text = n
-                .descendants()
-                .find(|n| n.has_tag_name("text"))
-                .unwrap()
-                .text()
-                .unwrap()
-                .to_string();
-            nav.insert(path, text);
-        });
+        .filter(|d| d.is_text())
+        .filter_map(|d| d.text())
+        .flat_map(str::chars)
+        .filter(|c| !c.is_whitespace())
+        .peekable();
+    if chars.peek().is_some() {
+        return chars.all(|c| "*⁂✻✽❦~•·#-—".contains(c));
+    }
+    let names = [
+        "scene",
+        "aster",
+        "dinkus",
+        "ornament",
+        "separator",
+        "transition",
+        "sectionbreak",
+        "spacebreak",
+    ];
+    classes(n).any(|class| {
+        let class = class.to_ascii_lowercase().replace(['-', '_'], "");
+        names.iter().any(|&name| class.contains(name))
+    })
+}
+
+// a block of lines, rather than a line
+fn is_stanza(n: Node) -> bool {
+    n.has_tag_name("stanza")
+        || has_class(n, &["stanza"])
+        || n.descendants()
+            .skip(1)
+            .any(|d| matches!(d.tag_name().name(), "br" | "p" | "div" | "v" | "empty-line"))
+}
+
+// cols to indent a line of verse, from classes like i2 or indent2, or the
+// spaces after a <br>
+fn verse_indent(n: Node, text: &str) -> usize {
+    let level = n
+        .ancestors()
+        .take_while(|a| !is_verse(*a))
+        .flat_map(classes)
+        .filter_map(|class| {
+            class
+                .strip_prefix("indent")
+                .or_else(|| class.strip_prefix('i'))?
+                .parse::<usize>()
+                .ok()
+        })
+        .max()
+        .unwrap_or(0);
+    // a single space is likely just markup formatting
+    let space = text.len() - text.trim_start_matches(' ').len();
+    2 * level + if space > 1 { space } else { 0 }
+}
+
+// base text and <rt> annotations, <rp> being the parentheses for readers
+// without ruby
+fn ruby(n: Node, c: &mut Chapter) {
+    let mut base = c.text.len();
+    for child in n.children() {
+        match child.tag_name().name() {
+            "rp" => (),
+            "rt" | "rtc" => {
+                let text: String = child
+                    .descendants()
+                    .filter(|d| d.is_text())
+                    .filter_map(|d| d.text())
+                    .collect();
+                let text = text.trim();
+                if text.is_empty() {
+                    continue;
+                }
+                match c.layout.ruby {
+                    Ruby::Above if base < c.text.len() => {
+                        c.rubies.push((base, c.text.len(), text.to_string()));
+                    }
+                    _ if text.graphemes(true).next().is_some_and(cjk) => {
+                        c.text.push_str(&format!("（{}）", text));
+                    }
+                    _ => c.text.push_str(&format!("({})", text)),
+                }
+                base = c.text.len();
+            }
+            // whitespace between the parts
+            _ if child.is_text() && child.text().unwrap().trim().is_empty() => (),
+            _ => render(child, c),
+        }
+    }
+}
+
+// text as super or subscript characters, or ^{text} if some have none
+pub fn script(text: &str, i: usize) -> String {
+    let (from, to, mark) = SCRIPTS[i];
+    let mapped: Option<String> = text
+        .chars()
+        .map(|c| match c {
+            ' ' => Some(' '),
+            c => from
+                .chars()
+                .position(|f| f == c)
+                .and_then(|n| to.chars().nth(n)),
+        })
+        .collect();
+    mapped.unwrap_or_else(|| format!("{}{{{}}}", mark, text))
+}
+
+#[cfg(not(feature = "svg"))]
+fn rasterize(_: &[u8]) -> Option<Vec<u8>> {
+    None
+}
+
+#[cfg(not(feature = "highlight"))]
+fn highlight(_: &str, _: &str) -> Option<Vec<(usize, Rgb)>> {
+    None
+}
+
+// the caption of the figure an image is in
+fn caption(n: Node) -> Option<String> {
+    let figure = n.ancestors().find(|a| a.has_tag_name("figure"))?;
+    let caption = figure
+        .descendants()
+        .find(|d| d.has_tag_name("figcaption"))?;
+    let text: String = caption
+        .descendants()
+        .filter(Node::is_text)
+        .map(|n| n.text().unwrap())
+        .collect();
+    Some(text)
 }
-fn epub3(doc: Document, nav: &mut HashMap<String, String>) {
-    doc.descendants()
-        .find(|n| n.has_tag_name("nav"))
-        .unwrap()
+
+// the title, description and text of an svg
+fn svg_alt(n: Node) -> String {
+    let text = |n: Node| -> String {
+        let text: String = n
+            .descendants()
+            .filter(Node::is_text)
+            .map(|n| n.text().unwrap())
+            .collect();
+        text.split_whitespace().collect::<Vec<_>>().join(" ")
+    };
+    let about = n
         .children()
-        .find(|n| n.has_tag_name("ol"))
-        .unwrap()
+        .filter(|c| c.has_tag_name("title") || c.has_tag_name("desc"))
+        .map(text);
+    let labels: Vec<_> = n
+        .descendants()
+        .filter(|d| d.has_tag_name("text"))
+        .map(text)
+        .filter(|t| !t.is_empty())
+        .collect();
+    about
+        .chain(iter::once(labels.join(", ")))
+        .filter(|t| !t.is_empty())
+        .collect::<Vec<_>>()
+        .join(". ")
+}
+
+// an inline svg as a file of its own, with the namespaces the book declared
+// further up
+fn svg_source(n: Node) -> String {
+    let mut svg = n.document().input_text()[n.range()].to_string();
+    if svg.starts_with("<svg") && !svg.starts_with("<svg:") {
+        for (attr, ns) in [("xmlns", SVG), ("xmlns:xlink", XLINK)] {
+            if !svg.contains(&format!("{}=", attr)) {
+                svg.insert_str(4, &format!(" {}=\"{}\"", attr, ns));
+            }
+        }
+    }
+    svg
+}
+
+fn table(n: Node, c: &mut Chapter) {
+    c.table_start();
+    let rows = n
+        .descendants()
+        .filter(|r| r.has_tag_name("tr"))
+        .filter(|r| r.ancestors().find(|a| a.has_tag_name("table")) == Some(n));
+    for row in rows {
+        let cells: Vec<_> = row
+            .children()
+            .filter(|n| n.has_tag_name("td") || n.has_tag_name("th"))
+            .collect();
+        for &cell in &cells {
+            c.cell_start();
+            if cell.has_tag_name("th") {
+                c.render(cell, Attribute::Bold, Attribute::NormalIntensity);
+            } else {
+                c.render_text(cell);
+            }
+            c.cell_end();
+        }
+        let header = row.parent().is_some_and(|p| p.has_tag_name("thead"))
+            || (!cells.is_empty() && cells.iter().all(|n| n.has_tag_name("th")));
+        c.row_end(header);
+    }
+    c.table_end();
+}
+
+// dublin core, plus the series from calibre or epub3 collections
+fn metadata(node: Node) -> String {
+    let mut meta = String::new();
+    let mut series = None;
+    let mut index = None;
+    for n in node.children().filter(Node::is_element) {
+        let name = n.tag_name().name();
+        if name == "meta" {
+            let (key, value) = match (n.attribute("name"), n.attribute("property")) {
+                (Some(name), _) => (name, n.attribute("content")),
+                (_, Some(property)) => (property, n.text()),
+                _ => continue,
+            };
+            match key {
+                "calibre:series" | "belongs-to-collection" => series = series.or(value),
+                "calibre:series_index" | "group-position" => index = index.or(value),
+                _ => (),
+            }
+            continue;
+        }
+        let text: String = n
+            .descendants()
+            .filter(Node::is_text)
+            .map(|n| n.text().unwrap())
+            .collect();
+        // descriptions are often escaped html
+        let text = match name {
+            "description" => strip_tags(&text),
+            _ => text.split_ascii_whitespace().collect::<Vec<_>>().join(" "),
+        };
+        if !text.is_empty() {
+            meta.push_str(&format!("{}: {}\n", name, text));
+        }
+    }
+    if let Some(series) = series {
+        match index {
+            Some(index) => {
+                // calibre writes 2.0
+                let index = index.trim().trim_end_matches(".0");
+                meta.push_str(&format!("series: {} #{}\n", series, index));
+            }
+            None => meta.push_str(&format!("series: {}\n", series)),
+        }
+    }
+    meta
+}
+
+fn strip_tags(html: &str) -> String {
+    let mut text = String::new();
+    let mut rest = html;
+    while let Some(lt) = rest.find('<') {
+        text.push_str(&rest[..lt]);
+        text.push(' ');
+        rest = rest[lt..].find('>').map_or("", |gt| &rest[lt + gt + 1..]);
+    }
+    text.push_str(rest);
+    let text = text.replace("&amp;", "&").replace("&nbsp;", " ");
+    text.split_ascii_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn dirname(path: &str) -> &str {
+    match path.rfind('/') {
+        Some(n) => &path[..=n],
+        None => "",
+    }
+}
+
+// resolve a relative href against a directory, dropping any fragment
+fn resolve(dir: &str, href: &str) -> String {
+    let href = href.split('#').next().unwrap();
+    let mut parts: Vec<&str> = dir.split('/').filter(|p| !p.is_empty()).collect();
+    for part in href.split('/') {
+        match part {
+            "" | "." => (),
+            ".." => {
+                parts.pop();
+            }
+            _ => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+// for chapters missing from the toc: the first heading, else the file name
+fn label(c: &Chapter, path: &str) -> String {
+    let heading = c.headings.first().map(|&(start, end, _)| {
+        let words: Vec<_> = c.text[start..end].split_whitespace().collect();
+        words.join(" ")
+    });
+    match heading {
+        Some(h) if !h.is_empty() => h,
+        _ => {
+            let name = path.rsplit('/').next().unwrap();
+            name.rsplit_once('.')
+                .map_or(name, |(stem, _)| stem)
+                .to_string()
+        }
+    }
+}
+
+fn fragment(href: &str) -> Option<String> {
+    href.split_once('#').map(|(_, id)| id.to_string())
+}
+
+fn ncx(doc: Document, dir: &str, toc: &mut Vec<(usize, String, String, Option<String>)>) {
+    let map = match doc.descendants().find(|n| n.has_tag_name("navMap")) {
+        Some(map) => map,
+        None => return,
+    };
+    for n in map.descendants().filter(|n| n.has_tag_name("navPoint")) {
+        let src = n
+            .children()
+            .find(|n| n.has_tag_name("content"))
+            .and_then(|n| n.attribute("src"));
+        let text = n
+            .descendants()
+            .find(|n| n.has_tag_name("text"))
+            .and_then(|n| n.text());
+        if let (Some(src), Some(text)) = (src, text) {
+            let level = n.ancestors().filter(|n| n.has_tag_name("navPoint")).count() - 1;
+            let title = text.trim().to_string();
+            toc.push((level, title, resolve(dir, src), fragment(src)));
+        }
+    }
+}
+
+// the page targets of the ncx's pageList
+fn ncx_pages(doc: &Document, dir: &str, pages: &mut Vec<(String, String, Option<String>)>) {
+    for n in doc.descendants().filter(|n| n.has_tag_name("pageTarget")) {
+        let src = n
+            .children()
+            .find(|n| n.has_tag_name("content"))
+            .and_then(|n| n.attribute("src"));
+        let text = n
+            .descendants()
+            .find(|n| n.has_tag_name("text"))
+            .and_then(|n| n.text());
+        if let (Some(src), Some(text)) = (src, text) {
+            pages.push((text.trim().to_string(), resolve(dir, src), fragment(src)));
+        }
+    }
+}
+
+// the links of the nav element with epub:type="page-list"
+fn page_list_nav(doc: &Document, dir: &str, pages: &mut Vec<(String, String, Option<String>)>) {
+    let nav = doc
         .descendants()
+        .find(|n| n.has_tag_name("nav") && n.attribute((OPS, "type")) == Some("page-list"));
+    for n in nav
+        .iter()
+        .flat_map(|n| n.descendants())
         .filter(|n| n.has_tag_name("a"))
-        .for_each(|n| {
-            let path = n
-                .attribute("href")
-                .unwrap()
-                .split('#')
-                .next()
-                .unwrap()
-                .to_string();
-            let text = n
+    {
+        if let Some(href) = n.attribute("href") {
+            let text: String = n
                 .descendants()
                 .filter(Node::is_text)
                 .map(|n| n.text().unwrap())
                 .collect();
-            nav.insert(path, text);
-        });
+            pages.push((text.trim().to_string(), resolve(dir, href), fragment(href)));
+        }
+    }
+}
+
+// the links of the nav element with epub:type="landmarks", typed like their targets
+fn landmarks_nav(
+    doc: &Document,
+    dir: &str,
+    landmarks: &mut Vec<(String, String, String, Option<String>)>,
+) {
+    let nav = doc
+        .descendants()
+        .find(|n| n.has_tag_name("nav") && n.attribute((OPS, "type")) == Some("landmarks"));
+    for n in nav
+        .iter()
+        .flat_map(|n| n.descendants())
+        .filter(|n| n.has_tag_name("a"))
+    {
+        if let (Some(kind), Some(href)) = (n.attribute((OPS, "type")), n.attribute("href")) {
+            let text: String = n
+                .descendants()
+                .filter(Node::is_text)
+                .map(|n| n.text().unwrap())
+                .collect();
+            let title = text.trim().to_string();
+            landmarks.push((kind.to_string(), title, resolve(dir, href), fragment(href)));
+        }
+    }
+}
+
+// the nav element with epub:type="toc", or the first one
+fn toc_nav(doc: Document, dir: &str, toc: &mut Vec<(usize, String, String, Option<String>)>) {
+    let mut navs = doc.descendants().filter(|n| n.has_tag_name("nav"));
+    let nav = navs
+        .clone()
+        .find(|n| n.attribute((OPS, "type")) == Some("toc"))
+        .or_else(|| navs.next());
+    let list = match nav.and_then(|n| n.descendants().find(|n| n.has_tag_name("ol"))) {
+        Some(list) => list,
+        None => return,
+    };
+    for n in list.descendants().filter(|n| n.has_tag_name("a")) {
+        if let Some(href) = n.attribute("href") {
+            let text: String = n
+                .descendants()
+                .filter(Node::is_text)
+                .map(|n| n.text().unwrap())
+                .collect();
+            let level = n
+                .ancestors()
+                .take_while(|&a| a != list)
+                .filter(|n| n.has_tag_name("ol"))
+                .count();
+            let title = text.trim().to_string();
+            toc.push((level, title, resolve(dir, href), fragment(href)));
+        }
+    }
 }
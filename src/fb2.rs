@@ -0,0 +1,110 @@
+use anyhow::{anyhow, Result};
+use roxmltree::{Document, Node};
+use std::{fs, fs::File, io::Read};
+
+use crate::{
+    charset,
+    epub::{render, Chapter, Layout},
+    Book,
+};
+
+pub fn open(path: &str, meta: bool, layout: Layout) -> Result<Book> {
+    let mut magic = [0; 4];
+    File::open(path)?.read_exact(&mut magic)?;
+    let xml = if &magic == b"PK\x03\x04" {
+        let mut zip = zip::ZipArchive::new(File::open(path)?)?;
+        let name = zip
+            .file_names()
+            .find(|name| name.to_lowercase().ends_with(".fb2"))
+            .ok_or_else(|| anyhow!("no fb2 file in zip"))?
+            .to_string();
+        let mut xml = Vec::new();
+        zip.by_name(&name)?.read_to_end(&mut xml)?;
+        charset::decode(&xml)
+    } else {
+        charset::decode(&fs::read(path)?)
+    };
+    let doc = Document::parse(&xml)?;
+    let root = doc.root_element();
+
+    let mut book = Book::default();
+    if let Some(info) = root.descendants().find(|n| n.has_tag_name("title-info")) {
+        get_meta(info, &mut book.meta);
+    }
+    if let Some(info) = root.descendants().find(|n| n.has_tag_name("publish-info")) {
+        get_meta(info, &mut book.meta);
+    }
+    if meta {
+        return Ok(book);
+    }
+
+    for body in root.children().filter(|n| n.has_tag_name("body")) {
+        let mut sections = body
+            .children()
+            .filter(|n| n.has_tag_name("section"))
+            .peekable();
+        // a body without sections is a single chapter
+        let sections: Vec<_> = if sections.peek().is_none() {
+            vec![body]
+        } else {
+            sections.collect()
+        };
+        for section in sections {
+            let title = section
+                .children()
+                .find(|n| n.has_tag_name("title"))
+                .map(text)
+                .or_else(|| body.attribute("name").map(String::from))
+                .unwrap_or_else(|| book.chapters.len().to_string());
+            let mut c = Chapter::new(title);
+            c.layout = layout;
+            render(section, &mut c);
+            if c.text.trim().is_empty() {
+                continue;
+            }
+            // ids are unique across the whole file
+            for (id, pos) in c.frag.drain(..) {
+                book.links
+                    .insert(format!("#{}", id), (book.chapters.len(), pos));
+            }
+            book.chapters.push(c);
+        }
+    }
+    if book.chapters.is_empty() {
+        return Err(anyhow!("no readable text"));
+    }
+    Ok(book)
+}
+
+fn get_meta(info: Node, meta: &mut String) {
+    for n in info.children().filter(Node::is_element) {
+        let name = match n.tag_name().name() {
+            "book-title" | "book-name" => "title",
+            "author" => "creator",
+            "genre" => "subject",
+            "lang" => "language",
+            "annotation" => "description",
+            "date" | "year" => "date",
+            "publisher" => "publisher",
+            "sequence" => {
+                if let Some(name) = n.attribute("name") {
+                    meta.push_str(&format!("series: {}\n", name));
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        let text = text(n);
+        if !text.is_empty() {
+            meta.push_str(&format!("{}: {}\n", name, text));
+        }
+    }
+}
+
+fn text(n: Node) -> String {
+    n.descendants()
+        .filter(Node::is_text)
+        .flat_map(|n| n.text().unwrap().split_whitespace())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
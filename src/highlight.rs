@@ -0,0 +1,36 @@
+use std::sync::OnceLock;
+use syntect::{
+    easy::HighlightLines, highlighting::ThemeSet, parsing::SyntaxSet, util::LinesWithEndings,
+};
+
+use crate::epub::Rgb;
+
+// loading the defaults is slow, so only for books with code
+fn sets() -> &'static (SyntaxSet, ThemeSet) {
+    static SETS: OnceLock<(SyntaxSet, ThemeSet)> = OnceLock::new();
+    SETS.get_or_init(|| {
+        (
+            SyntaxSet::load_defaults_newlines(),
+            ThemeSet::load_defaults(),
+        )
+    })
+}
+
+// foreground colors by byte, None if syntect doesn't know the language
+pub fn highlight(code: &str, lang: &str) -> Option<Vec<(usize, Rgb)>> {
+    let (syntaxes, themes) = sets();
+    let syntax = syntaxes.find_syntax_by_token(lang)?;
+    let mut h = HighlightLines::new(syntax, &themes.themes["base16-ocean.dark"]);
+    let mut colors: Vec<(usize, Rgb)> = Vec::new();
+    let mut pos = 0;
+    for line in LinesWithEndings::from(code) {
+        for (style, text) in h.highlight_line(line, syntaxes).ok()? {
+            let c = style.foreground;
+            if colors.last().map(|l| l.1) != Some((c.r, c.g, c.b)) {
+                colors.push((pos, (c.r, c.g, c.b)));
+            }
+            pos += text.len();
+        }
+    }
+    Some(colors)
+}
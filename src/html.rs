@@ -0,0 +1,403 @@
+use anyhow::{anyhow, Result};
+use roxmltree::Document;
+use std::{fs, path::Path};
+
+use crate::{
+    charset,
+    epub::{render, Chapter, Layout},
+    Book,
+};
+
+pub fn open(path: &str, meta: bool, layout: Layout) -> Result<Book> {
+    let text = charset::decode(&fs::read(path)?);
+    // try as xhtml first, so nothing is lost to the cleanup
+    let xml;
+    let doc = match Document::parse(&text) {
+        Ok(doc) => doc,
+        Err(_) => {
+            xml = xhtml(&text);
+            Document::parse(&xml)?
+        }
+    };
+
+    let mut book = Book::default();
+    // read the head from the source, the cleanup drops it
+    let lower = text.to_ascii_lowercase();
+    let title = lower
+        .find("<title")
+        .and_then(|start| {
+            let start = start + text[start..].find('>')? + 1;
+            let end = start + lower[start..].find("</title")?;
+            Some(text[start..end].trim())
+        })
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .unwrap_or_else(|| {
+            let stem = Path::new(path).file_stem().unwrap();
+            stem.to_string_lossy().into_owned()
+        });
+    book.meta.push_str(&format!("title: {}\n", title));
+    let html = tags(&text, "html").next();
+    if let Some(lang) = html.and_then(|tag| attr(tag, "lang")) {
+        book.meta.push_str(&format!("language: {}\n", lang));
+    }
+    for tag in tags(&text, "meta") {
+        let name = match attr(tag, "name") {
+            Some("author") => "creator",
+            Some("description") => "description",
+            Some("keywords") => "subject",
+            _ => continue,
+        };
+        if let Some(content) = attr(tag, "content") {
+            book.meta.push_str(&format!("{}: {}\n", name, content));
+        }
+    }
+    if meta {
+        return Ok(book);
+    }
+
+    let body = doc
+        .descendants()
+        .find(|n| n.has_tag_name("body"))
+        .unwrap_or_else(|| doc.root_element());
+    let mut c = Chapter::new(title);
+    c.layout = layout;
+    c.rtl = tags(&text, "body")
+        .take(1)
+        .chain(html)
+        .find_map(|tag| attr(tag, "dir"))
+        .is_some_and(|dir| dir.eq_ignore_ascii_case("rtl"));
+    render(body, &mut c);
+
+    // level 1 and 2 headings start a new chapter
+    let splits: Vec<_> = c
+        .headings
+        .iter()
+        .filter(|&&(_, _, level)| level <= 2)
+        .map(|&(start, end, _)| {
+            // take the newline before the heading, where its anchor is
+            let at = start - c.text[..start].ends_with('\n') as usize;
+            (at, c.text[start..end].trim().to_string())
+        })
+        .collect();
+    let mut chapters = Vec::new();
+    for (start, title) in splits.into_iter().rev() {
+        let mut next = c.split_off(start);
+        next.title = title;
+        chapters.push(next);
+    }
+    chapters.push(c);
+    for c in chapters.into_iter().rev() {
+        if c.text.trim().is_empty() {
+            continue;
+        }
+        book.chapters.push(c);
+    }
+    if book.chapters.is_empty() {
+        return Err(anyhow!("no readable text"));
+    }
+    for (i, c) in book.chapters.iter_mut().enumerate() {
+        for (id, pos) in c.frag.drain(..) {
+            book.links.insert(format!("#{}", id), (i, pos));
+        }
+    }
+    Ok(book)
+}
+
+// the inside of each tag named `name`, eg `a href="x"`
+pub fn tags<'a>(html: &'a str, name: &'a str) -> impl Iterator<Item = &'a str> {
+    let open = format!("<{}", name);
+    let lower = html.to_ascii_lowercase();
+    let starts: Vec<_> = lower.match_indices(&open).map(|(i, _)| i).collect();
+    starts.into_iter().filter_map(move |i| {
+        let tag = &html[i + 1..i + html[i..].find('>')?];
+        let rest = &tag[name.len()..];
+        if rest.is_empty() || rest.starts_with(|c: char| c.is_ascii_whitespace() || c == '/') {
+            Some(tag)
+        } else {
+            None
+        }
+    })
+}
+
+// attribute value from the inside of a tag
+pub fn attr<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let lower = tag.to_ascii_lowercase();
+    let key = format!("{}=", name);
+    let i = lower
+        .match_indices(&key)
+        .find(|&(i, _)| i > 0 && tag.as_bytes()[i - 1].is_ascii_whitespace())?
+        .0
+        + key.len();
+    let rest = &tag[i..];
+    match rest.chars().next()? {
+        q @ '"' | q @ '\'' => rest[1..].split(q).next(),
+        _ => rest
+            .split(|c: char| c.is_ascii_whitespace() || c == '/')
+            .next(),
+    }
+}
+
+const VOID: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "wbr",
+];
+const SKIP: &[&str] = &["head", "script", "style", "title"];
+
+// balance tag soup into something roxmltree accepts. unknown tags are kept so
+// that render falls through to their text
+pub fn xhtml(html: &str) -> String {
+    let mut out = String::from("<html><body>");
+    let mut stack: Vec<String> = Vec::new();
+    let mut rest = html;
+    while !rest.is_empty() {
+        let lt = match rest.find('<') {
+            Some(n) => n,
+            None => {
+                out.push_str(&escape(rest));
+                break;
+            }
+        };
+        out.push_str(&escape(&rest[..lt]));
+        rest = &rest[lt..];
+
+        if rest.starts_with("<!--") {
+            rest = rest.find("-->").map_or("", |n| &rest[n + 3..]);
+            continue;
+        }
+        if rest.starts_with("<!") || rest.starts_with("<?") {
+            rest = rest.find('>').map_or("", |n| &rest[n + 1..]);
+            continue;
+        }
+        if !rest[1..].starts_with(|c: char| c.is_ascii_alphabetic() || c == '/') {
+            out.push_str("&lt;");
+            rest = &rest[1..];
+            continue;
+        }
+        let gt = match rest.find('>') {
+            Some(n) => n,
+            None => {
+                out.push_str(&escape(rest));
+                break;
+            }
+        };
+        let tag = &rest[1..gt];
+        rest = &rest[gt + 1..];
+
+        let close = tag.starts_with('/');
+        let tag = tag.trim_start_matches('/');
+        let name_end = tag
+            .find(|c: char| c.is_ascii_whitespace() || c == '/')
+            .unwrap_or(tag.len());
+        let name = tag[..name_end].to_ascii_lowercase();
+        // drop namespace prefixes, eg mbp:pagebreak
+        let name = match name.rfind(':') {
+            Some(n) => name[n + 1..].to_string(),
+            None => name,
+        };
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric()) {
+            out.push_str(&escape(&format!("<{}>", tag)));
+            continue;
+        }
+        if matches!(name.as_str(), "html" | "body") {
+            continue;
+        }
+        if SKIP.contains(&name.as_str()) {
+            if !close {
+                let end = format!("</{}", name);
+                let lower = rest.to_ascii_lowercase();
+                rest = lower.find(&end).map_or("", |n| {
+                    let r = &rest[n..];
+                    r.find('>').map_or("", |m| &r[m + 1..])
+                });
+            }
+            continue;
+        }
+
+        if close {
+            if let Some(n) = stack.iter().rposition(|t| *t == name) {
+                for t in stack.drain(n..).rev() {
+                    out.push_str(&format!("</{}>", t));
+                }
+            }
+            continue;
+        }
+
+        // a block element ends an open paragraph
+        if matches!(
+            name.as_str(),
+            "p" | "div"
+                | "h1"
+                | "h2"
+                | "h3"
+                | "h4"
+                | "h5"
+                | "h6"
+                | "ul"
+                | "ol"
+                | "table"
+                | "blockquote"
+                | "pre"
+        ) {
+            if let Some(n) = stack.iter().rposition(|t| t == "p") {
+                for t in stack.drain(n..).rev() {
+                    out.push_str(&format!("</{}>", t));
+                }
+            }
+        }
+
+        out.push('<');
+        out.push_str(&name);
+        let mut seen = Vec::new();
+        for (key, value) in attrs(&tag[name_end..]) {
+            if key.contains(':') || seen.contains(&key) {
+                continue;
+            }
+            out.push_str(&format!(
+                " {}=\"{}\"",
+                key,
+                escape(&value).replace('"', "&quot;")
+            ));
+            seen.push(key);
+        }
+        if VOID.contains(&name.as_str()) || tag.ends_with('/') {
+            out.push_str("/>");
+        } else {
+            out.push('>');
+            stack.push(name);
+        }
+    }
+    for t in stack.iter().rev() {
+        out.push_str(&format!("</{}>", t));
+    }
+    out.push_str("</body></html>");
+    out
+}
+
+fn attrs(s: &str) -> Vec<(String, String)> {
+    let mut v = Vec::new();
+    let mut rest = s.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == '/');
+    while !rest.is_empty() {
+        let end = rest
+            .find(|c: char| c.is_ascii_whitespace() || c == '=' || c == '/')
+            .unwrap_or(rest.len());
+        let key = rest[..end].to_ascii_lowercase();
+        rest = rest[end..].trim_start();
+        let mut value = key.clone();
+        if let Some(r) = rest.strip_prefix('=') {
+            let r = r.trim_start();
+            let (val, r) = match r.chars().next() {
+                Some(q @ '"') | Some(q @ '\'') => {
+                    let r = &r[1..];
+                    let n = r.find(q).unwrap_or(r.len());
+                    (&r[..n], r.get(n + 1..).unwrap_or(""))
+                }
+                _ => {
+                    let n = r.find(char::is_whitespace).unwrap_or(r.len());
+                    (&r[..n], &r[n..])
+                }
+            };
+            value = val.to_string();
+            rest = r;
+        }
+        if !key.is_empty()
+            && key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_:".contains(c))
+        {
+            v.push((key, value));
+        }
+        rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == '/');
+    }
+    v
+}
+
+// escape text for xml, decoding the html entities that xml doesn't know
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(n) = rest.find(['&', '<', '>']) {
+        out.push_str(&rest[..n]);
+        let c = rest.as_bytes()[n];
+        rest = &rest[n + 1..];
+        match c {
+            b'<' => out.push_str("&lt;"),
+            b'>' => out.push_str("&gt;"),
+            _ => {
+                let end = rest.find(';').filter(|&e| {
+                    e < 10
+                        && rest[..e]
+                            .chars()
+                            .all(|c| c.is_ascii_alphanumeric() || c == '#')
+                });
+                match end.and_then(|e| entity(&rest[..e]).map(|c| (e, c))) {
+                    Some((e, c)) => {
+                        match c {
+                            '&' => out.push_str("&amp;"),
+                            '<' => out.push_str("&lt;"),
+                            _ => out.push(c),
+                        }
+                        rest = &rest[e + 1..];
+                    }
+                    None => out.push_str("&amp;"),
+                }
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+pub fn entity(name: &str) -> Option<char> {
+    if let Some(num) = name.strip_prefix('#') {
+        let n = match num.strip_prefix('x').or_else(|| num.strip_prefix('X')) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => num.parse().ok()?,
+        };
+        return std::char::from_u32(n);
+    }
+    Some(match name {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => '\u{a0}',
+        "shy" => '\u{ad}',
+        "copy" => '©',
+        "reg" => '®',
+        "trade" => '™',
+        "deg" => '°',
+        "middot" => '·',
+        "bull" => '•',
+        "hellip" => '…',
+        "ndash" => '–',
+        "mdash" => '—',
+        "lsquo" => '‘',
+        "rsquo" => '’',
+        "sbquo" => '‚',
+        "ldquo" => '“',
+        "rdquo" => '”',
+        "bdquo" => '„',
+        "laquo" => '«',
+        "raquo" => '»',
+        "times" => '×',
+        "divide" => '÷',
+        "sect" => '§',
+        "para" => '¶',
+        "eacute" => 'é',
+        "egrave" => 'è',
+        "aacute" => 'á',
+        "agrave" => 'à',
+        "iacute" => 'í',
+        "oacute" => 'ó',
+        "uacute" => 'ú',
+        "ntilde" => 'ñ',
+        "ccedil" => 'ç',
+        "auml" => 'ä',
+        "ouml" => 'ö',
+        "uuml" => 'ü',
+        "szlig" => 'ß',
+        _ => return None,
+    })
+}
@@ -0,0 +1,207 @@
+use anyhow::{anyhow, Result};
+use crossterm::cursor::{MoveDown, MoveLeft};
+use image::{imageops::FilterType, io::Reader, RgbImage};
+use std::{env, io::Cursor};
+
+// pixels per cell, a guess. cells are about twice as tall as they are wide
+const CELL: (u32, u32) = (10, 20);
+
+#[derive(Clone, Copy)]
+pub enum Protocol {
+    Kitty,
+    Sixel,
+    Iterm,
+    // upper half blocks colored with two pixels each, for any truecolor terminal
+    Halfblock,
+}
+
+pub fn detect() -> Protocol {
+    let term = env::var("TERM").unwrap_or_default();
+    let program = env::var("TERM_PROGRAM").unwrap_or_default();
+    if env::var("KITTY_WINDOW_ID").is_ok()
+        || term.contains("kitty")
+        || term.contains("ghostty")
+        || program == "WezTerm"
+    {
+        Protocol::Kitty
+    } else if term.contains("sixel")
+        || term.starts_with("foot")
+        || term.starts_with("mlterm")
+        || term.starts_with("yaft")
+    {
+        Protocol::Sixel
+    } else if program == "iTerm.app" {
+        Protocol::Iterm
+    } else {
+        Protocol::Halfblock
+    }
+}
+
+fn dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    Reader::new(Cursor::new(data))
+        .with_guessed_format()
+        .ok()?
+        .into_dimensions()
+        .ok()
+}
+
+// cells covered at the image's own size, from the header alone
+pub fn size(data: &[u8]) -> Option<(u16, u16)> {
+    let (w, h) = dimensions(data)?;
+    Some((
+        min_cells(w, CELL.0, u16::MAX),
+        min_cells(h, CELL.1, u16::MAX),
+    ))
+}
+
+// the cols and rows of an image of size cells, shrunk to fit
+pub fn fit((w, h): (u16, u16), cols: u16, rows: u16) -> (u16, u16) {
+    let scale = f64::min(cols as f64 / w as f64, rows as f64 / h as f64).min(1.0);
+    let w = ((w as f64 * scale).round() as u16).max(1);
+    let h = ((h as f64 * scale).round() as u16).max(1);
+    (w, h)
+}
+
+// escape sequence drawing the image at the cursor, fit inside cols x rows,
+// along with the cols and rows it covers
+pub fn draw(data: &[u8], protocol: Protocol, cols: u16, rows: u16) -> Result<(String, u16, u16)> {
+    let (w, h) = dimensions(data).ok_or_else(|| anyhow!("unknown image format"))?;
+    let scale = f64::min(
+        (cols as u32 * CELL.0) as f64 / w as f64,
+        (rows as u32 * CELL.1) as f64 / h as f64,
+    );
+    let cols = min_cells((w as f64 * scale) as u32, CELL.0, cols);
+    let rows = min_cells((h as f64 * scale) as u32, CELL.1, rows);
+    // iterm takes the file as is
+    if let Protocol::Iterm = protocol {
+        return Ok((iterm(data, cols, rows), cols, rows));
+    }
+    let img = image::load_from_memory(data)?;
+    // kitty scales to the cells itself, so only shrink
+    let scale = match protocol {
+        Protocol::Kitty => scale.min(1.0),
+        _ => scale,
+    };
+    let (w, h) = match protocol {
+        Protocol::Halfblock => (cols as u32, rows as u32 * 2),
+        _ => (
+            ((w as f64 * scale) as u32).max(1),
+            ((h as f64 * scale) as u32).max(1),
+        ),
+    };
+    let img = img.resize_exact(w, h, FilterType::Triangle).to_rgb8();
+
+    let s = match protocol {
+        Protocol::Kitty => kitty(&img, cols, rows),
+        Protocol::Sixel => sixel(&img),
+        _ => halfblock(&img),
+    };
+    Ok((s, cols, rows))
+}
+
+fn min_cells(pixels: u32, cell: u32, max: u16) -> u16 {
+    let n = pixels.div_ceil(cell);
+    n.min(max as u32) as u16
+}
+
+// https://sw.kovidgoyal.net/kitty/graphics-protocol/
+fn kitty(img: &RgbImage, cols: u16, rows: u16) -> String {
+    let data = base64::encode(img.as_raw());
+    let mut chunks = data.as_bytes().chunks(4096).peekable();
+    let mut control = format!(
+        "a=T,f=24,q=2,s={},v={},c={},r={},",
+        img.width(),
+        img.height(),
+        cols,
+        rows
+    );
+    let mut out = String::new();
+    while let Some(chunk) = chunks.next() {
+        let more = chunks.peek().is_some() as u8;
+        // base64 is ascii
+        let chunk = std::str::from_utf8(chunk).unwrap();
+        out.push_str(&format!("\x1b_G{}m={};{}\x1b\\", control, more, chunk));
+        control.clear();
+    }
+    out
+}
+
+// https://iterm2.com/documentation-images.html
+fn iterm(data: &[u8], cols: u16, rows: u16) -> String {
+    format!(
+        "\x1b]1337;File=inline=1;size={};width={};height={};preserveAspectRatio=1:{}\x07",
+        data.len(),
+        cols,
+        rows,
+        base64::encode(data)
+    )
+}
+
+// a pixel wide and two tall per cell, each row back under the last
+fn halfblock(img: &RgbImage) -> String {
+    let (w, h) = img.dimensions();
+    let mut out = String::new();
+    for y in (0..h).step_by(2) {
+        if y > 0 {
+            out.push_str(&format!("{}{}", MoveLeft(w as u16), MoveDown(1)));
+        }
+        for x in 0..w {
+            let top = img.get_pixel(x, y);
+            let bottom = img.get_pixel(x, (y + 1).min(h - 1));
+            out.push_str(&format!(
+                "\x1b[38;2;{};{};{};48;2;{};{};{}m▀",
+                top[0], top[1], top[2], bottom[0], bottom[1], bottom[2]
+            ));
+        }
+        // default colors, leaving other styles alone
+        out.push_str("\x1b[39;49m");
+    }
+    out
+}
+
+// https://vt100.net/docs/vt3xx-gp/chapter14.html
+// colors are quantized to a 6x6x6 cube
+fn sixel(img: &RgbImage) -> String {
+    let (w, h) = img.dimensions();
+    let mut out = format!("\x1bPq\"1;1;{};{}", w, h);
+    for i in 0..216 {
+        let (r, g, b) = (i / 36, i / 6 % 6, i % 6);
+        out.push_str(&format!("#{};2;{};{};{}", i, r * 20, g * 20, b * 20));
+    }
+    let quantize = |v: u8| (v as usize * 5 + 127) / 255;
+
+    for top in (0..h).step_by(6) {
+        // a row of sixels for each color in this band
+        let mut band = vec![Vec::new(); 216];
+        for dy in 0..6.min(h - top) {
+            for x in 0..w {
+                let p = img.get_pixel(x, top + dy);
+                let i = quantize(p[0]) * 36 + quantize(p[1]) * 6 + quantize(p[2]);
+                let row: &mut Vec<u8> = &mut band[i];
+                if row.is_empty() {
+                    row.resize(w as usize, 0);
+                }
+                row[x as usize] |= 1 << dy;
+            }
+        }
+        for (i, row) in band.iter().enumerate().filter(|(_, r)| !r.is_empty()) {
+            out.push_str(&format!("#{}", i));
+            let mut x = 0;
+            while x < row.len() {
+                let n = row[x..].iter().take_while(|&&b| b == row[x]).count();
+                let c = (63 + row[x]) as char;
+                if n > 3 {
+                    out.push_str(&format!("!{}{}", n, c));
+                } else {
+                    out.extend(std::iter::repeat_n(c, n));
+                }
+                x += n;
+            }
+            // back to the start of the band
+            out.push('$');
+        }
+        out.push('-');
+    }
+    out.push_str("\x1b\\");
+    out
+}
@@ -0,0 +1,711 @@
+use crossterm::event::KeyCode::{self, *};
+use serde::Deserialize;
+use std::{collections::HashMap, str::FromStr};
+
+// a key pressed in the page, with or without ctrl
+#[derive(Clone, Copy, PartialEq)]
+pub struct Key {
+    pub code: KeyCode,
+    pub ctrl: bool,
+}
+
+const fn key(code: KeyCode) -> Key {
+    Key { code, ctrl: false }
+}
+
+const fn ctrl(code: KeyCode) -> Key {
+    Key { code, ctrl: true }
+}
+
+impl Key {
+    // as help shows it
+    pub fn name(&self) -> String {
+        let name = match self.code {
+            Char(' ') => String::from("Space"),
+            Char(c) if self.ctrl => c.to_ascii_uppercase().to_string(),
+            Char(c) => c.to_string(),
+            F(n) => format!("F{}", n),
+            Backspace => String::from("Backspace"),
+            Enter => String::from("Enter"),
+            Left => String::from("Left"),
+            Right => String::from("Right"),
+            Up => String::from("Up"),
+            Down => String::from("Down"),
+            Home => String::from("Home"),
+            End => String::from("End"),
+            PageUp => String::from("PageUp"),
+            PageDown => String::from("PageDown"),
+            Tab => String::from("Tab"),
+            BackTab => String::from("BackTab"),
+            Delete => String::from("Delete"),
+            Insert => String::from("Insert"),
+            Esc => String::from("Esc"),
+            Null => String::from("Null"),
+        };
+        if self.ctrl {
+            format!("Ctrl-{}", name)
+        } else {
+            name
+        }
+    }
+}
+
+// a key named as help shows it, eg Ctrl-O, PageDown or F2
+impl FromStr for Key {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (ctrl, name) = match s.strip_prefix("Ctrl-") {
+            Some(name) => (true, name),
+            None => (false, s),
+        };
+        let mut chars = name.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) if ctrl => Char(c.to_ascii_lowercase()),
+            (Some(c), None) => Char(c),
+            _ => match name {
+                "Space" => Char(' '),
+                "Backspace" => Backspace,
+                "Enter" => Enter,
+                "Left" => Left,
+                "Right" => Right,
+                "Up" => Up,
+                "Down" => Down,
+                "Home" => Home,
+                "End" => End,
+                "PageUp" => PageUp,
+                "PageDown" => PageDown,
+                "Tab" => Tab,
+                "BackTab" => BackTab,
+                "Delete" => Delete,
+                "Insert" => Insert,
+                "Esc" => Esc,
+                _ => match name.strip_prefix('F').and_then(|n| n.parse().ok()) {
+                    Some(n) => F(n),
+                    None => return Err(format!("unknown key {}", s)),
+                },
+            },
+        };
+        Ok(Key { code, ctrl })
+    }
+}
+
+// the views with keys of their own, named in the config in kebab case
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Mode {
+    Page,
+    Toc,
+    TocFilter,
+    Visual,
+    Bookmarks,
+    Landmarks,
+    Results,
+    Source,
+    Picture,
+    Footnote,
+    Search,
+    Command,
+    Help,
+}
+
+impl Mode {
+    // the heading of its keys in help
+    pub fn title(&self) -> &'static str {
+        match self {
+            Mode::Page => "Reading",
+            Mode::Toc => "Table of Contents",
+            Mode::TocFilter => "Filtering the Table of Contents",
+            Mode::Visual => "Selecting",
+            Mode::Bookmarks => "Bookmarks",
+            Mode::Landmarks => "Landmarks",
+            Mode::Results => "Search Results",
+            Mode::Source => "Source",
+            Mode::Picture => "Comics",
+            Mode::Footnote => "Footnotes",
+            Mode::Search => "Searching",
+            Mode::Command => "Commands",
+            Mode::Help => "Help",
+        }
+    }
+}
+
+// what a key does in a view, named in the config in kebab case, eg
+// next-page
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    Quit,
+    Help,
+    Toc,
+    Metadata,
+    Justify,
+    Theme,
+    Status,
+    NextPage,
+    PrevPage,
+    HalfDown,
+    HalfUp,
+    LineDown,
+    LineUp,
+    ChapterStart,
+    ChapterEnd,
+    PrevChapter,
+    NextChapter,
+    NextHeading,
+    PrevHeading,
+    NextSentence,
+    PrevSentence,
+    NextParagraph,
+    PrevParagraph,
+    Percent,
+    Command,
+    SearchForward,
+    SearchBackward,
+    NextMatch,
+    PrevMatch,
+    SetMark,
+    JumpToMark,
+    NextLink,
+    PrevLink,
+    FollowLink,
+    CopyLink,
+    Back,
+    JumpBack,
+    JumpForward,
+    NextBook,
+    PrevBook,
+    Bookmark,
+    Bookmarks,
+    Landmarks,
+    Highlight,
+    Annotate,
+    Visual,
+    // in the other views
+    Close,
+    Open,
+    Expand,
+    Collapse,
+    Filter,
+    Top,
+    Bottom,
+    Remove,
+    Copy,
+    SwapEnds,
+    PrevChar,
+    NextChar,
+    PrevWord,
+    NextWord,
+    LineStart,
+    LineEnd,
+    Accept,
+    ListMatches,
+    Complete,
+}
+use Action::*;
+
+impl Action {
+    // for help, lines after the first are indented under it
+    pub fn help(&self) -> &'static str {
+        match self {
+            Quit => "Quit",
+            Help => "Help",
+            Toc => "Table of Contents",
+            Metadata => "Progress and Metadata",
+            Justify => "Toggle Justified Text",
+            Theme => "Next Theme",
+            Status => "Toggle Status Bar",
+            NextPage => "Page Down",
+            PrevPage => "Page Up",
+            HalfDown => "Half Page Down",
+            HalfUp => "Half Page Up",
+            LineDown => "Line Down",
+            LineUp => "Line Up",
+            ChapterStart => "Chapter Start",
+            ChapterEnd => "Chapter End, after a count N\ngo to page N",
+            PrevChapter => "Previous Chapter",
+            NextChapter => "Next Chapter",
+            NextHeading => "Next Heading",
+            PrevHeading => "Previous Heading",
+            NextSentence => "Next Sentence",
+            PrevSentence => "Previous Sentence",
+            NextParagraph => "Next Paragraph",
+            PrevParagraph => "Previous Paragraph",
+            Percent => "After a count N, go to N percent\nof the book",
+            Command => {
+                "Commands: toc, chapter N, page N,
+the print edition's page N if the
+book lists them, loc N for the
+location counted as kindles do,
+goto N%, mark x or mark name and
+jump x or jump name, source for the
+chapter's markup,
+set width=N, set [no]justify,
+set [no]status,
+set [no]columns for two columns
+when they fit, set [no]header,
+set [no]results for accepting a
+search to list the matches,
+set [no]regex for regular
+expressions in searches,
+set [no]hlsearch to keep matches
+reversed after a search,
+set [no]word to match whole words,
+set [no]fold to ignore accents,
+set [no]wrapscan for searches to
+go on from the other end,
+set [no]chaptersearch to search
+only the current chapter,
+set line-height=N, set overlap=N,
+b N, bn and bp to switch books,
+ls to list them, w, q"
+            }
+            SearchForward => "Search Forward, ignoring case\nunless the search has a capital",
+            SearchBackward => "Search Backward",
+            NextMatch => "Repeat search",
+            PrevMatch => "Repeat search the other way",
+            SetMark => "Set mark x, typed after it",
+            JumpToMark => "Jump to mark x, typed after it",
+            NextLink => "Pick the next link",
+            PrevLink => "Pick the previous link",
+            FollowLink => "Follow the picked link, opening\nones out of the book in the browser",
+            CopyLink => "Copy the picked link out of the book",
+            Back => "Back to the last link followed",
+            JumpBack => "Jump back",
+            JumpForward => "Jump forward",
+            NextBook => "Next book open",
+            PrevBook => "Previous book open",
+            Bookmark => "Add bookmark",
+            Bookmarks => "Bookmarks, highlights and notes",
+            Landmarks => "Landmarks: cover, start of the\ntext, index and such",
+            Highlight => "Highlight the selection,\nor remove the one clicked",
+            Annotate => "Annotate the selection or page\nin $EDITOR",
+            Visual => "Select with the keyboard",
+            Close => "Close",
+            Open => "Open",
+            Expand => "Expand",
+            Collapse => "Collapse",
+            Filter => "Filter the entries",
+            Top => "Top",
+            Bottom => "Bottom",
+            Remove => "Remove",
+            Copy => "Copy the selection",
+            SwapEnds => "Move the other end",
+            PrevChar => "Previous character",
+            NextChar => "Next character",
+            PrevWord => "Previous word",
+            NextWord => "Next word",
+            LineStart => "Line start",
+            LineEnd => "Line end",
+            Accept => "Accept",
+            ListMatches => "List the matches",
+            Complete => "Complete",
+        }
+    }
+}
+
+// actions and their keys, with a note help shows below them
+type Group = (&'static [(Action, &'static [Key])], &'static str);
+
+// the page's bindings, grouped as help shows them
+const PAGE: &[Group] = &[
+    (
+        &[
+            (Quit, &[key(Esc), key(Char('q'))]),
+            (Help, &[key(F(1))]),
+            (Toc, &[key(Tab)]),
+            (Metadata, &[key(Char('i'))]),
+            (Justify, &[key(Char('J'))]),
+            (Theme, &[key(Char('T'))]),
+            (Status, &[key(Char('s'))]),
+        ],
+        "",
+    ),
+    (
+        &[
+            (
+                NextPage,
+                &[
+                    key(PageDown),
+                    key(Right),
+                    key(Char(' ')),
+                    key(Char('f')),
+                    key(Char('l')),
+                ],
+            ),
+            (
+                PrevPage,
+                &[key(PageUp), key(Left), key(Char('b')), key(Char('h'))],
+            ),
+            (HalfDown, &[key(Char('d'))]),
+            (HalfUp, &[key(Char('u'))]),
+            (LineDown, &[key(Down), key(Char('j'))]),
+            (LineUp, &[key(Up), key(Char('k'))]),
+            (ChapterStart, &[key(Home), key(Char('g'))]),
+            (ChapterEnd, &[key(End), key(Char('G'))]),
+            (PrevChapter, &[key(Char('['))]),
+            (NextChapter, &[key(Char(']'))]),
+            (NextHeading, &[key(Char('>'))]),
+            (PrevHeading, &[key(Char('<'))]),
+            (NextSentence, &[key(Char(')'))]),
+            (PrevSentence, &[key(Char('('))]),
+            (NextParagraph, &[key(Char('}'))]),
+            (PrevParagraph, &[key(Char('{'))]),
+            (Percent, &[key(Char('%'))]),
+        ],
+        "a count N before a key moving or
+searching repeats it, or moves N
+lines for the line keys",
+    ),
+    (
+        &[
+            (Command, &[key(Char(':'))]),
+            (SearchForward, &[key(Char('/'))]),
+            (SearchBackward, &[key(Char('?'))]),
+            (NextMatch, &[key(Char('n'))]),
+            (PrevMatch, &[key(Char('N'))]),
+            (SetMark, &[key(Char('m'))]),
+            (JumpToMark, &[key(Char('\''))]),
+            (NextLink, &[key(Char('o'))]),
+            (PrevLink, &[key(Char('O'))]),
+            (FollowLink, &[key(Enter)]),
+            (CopyLink, &[key(Char('y'))]),
+            (Back, &[key(Backspace)]),
+            (JumpBack, &[ctrl(Char('o'))]),
+            // most terminals send ctrl-i as tab
+            (JumpForward, &[ctrl(Char('i')), ctrl(Char('n'))]),
+            (NextBook, &[ctrl(PageDown)]),
+            (PrevBook, &[ctrl(PageUp)]),
+            (Bookmark, &[key(Char('M'))]),
+            (Bookmarks, &[key(Char('B'))]),
+            (Landmarks, &[key(Char('L'))]),
+            (Highlight, &[key(Char('H'))]),
+            (Annotate, &[key(Char('A'))]),
+            (Visual, &[key(Char('v'))]),
+        ],
+        "",
+    ),
+];
+
+const TOC: &[Group] = &[(
+    &[
+        (Close, &[key(Esc), key(Tab), key(Char('q'))]),
+        (Open, &[key(Enter)]),
+        (Expand, &[key(Right), key(Char('l'))]),
+        (Collapse, &[key(Left), key(Char('h'))]),
+        (LineDown, &[key(Down), key(Char('j'))]),
+        (LineUp, &[key(Up), key(Char('k'))]),
+        (Top, &[key(Home), key(Char('g'))]),
+        (Bottom, &[key(End), key(Char('G'))]),
+        (NextPage, &[key(PageDown), key(Char('f'))]),
+        (PrevPage, &[key(PageUp), key(Char('b'))]),
+        (HalfDown, &[key(Char('d'))]),
+        (HalfUp, &[key(Char('u'))]),
+        (Filter, &[key(Char('/'))]),
+    ],
+    "",
+)];
+
+// keys not bound here are typed into the filter
+const TOC_FILTER: &[Group] = &[(
+    &[
+        (Close, &[key(Esc)]),
+        (Open, &[key(Enter)]),
+        (LineDown, &[key(Down)]),
+        (LineUp, &[key(Up)]),
+    ],
+    "other keys are typed into the filter",
+)];
+
+const VISUAL: &[Group] = &[(
+    &[
+        (Close, &[key(Esc), key(Char('v')), key(Char('q'))]),
+        (Copy, &[key(Char('y'))]),
+        (Highlight, &[key(Char('H'))]),
+        (SwapEnds, &[key(Char('o'))]),
+        (PrevChar, &[key(Left), key(Char('h'))]),
+        (NextChar, &[key(Right), key(Char('l'))]),
+        (NextWord, &[key(Char('w'))]),
+        (PrevWord, &[key(Char('b'))]),
+        (LineDown, &[key(Down), key(Char('j'))]),
+        (LineUp, &[key(Up), key(Char('k'))]),
+        (LineStart, &[key(Home), key(Char('0'))]),
+        (LineEnd, &[key(End), key(Char('$'))]),
+        (NextSentence, &[key(Char(')'))]),
+        (PrevSentence, &[key(Char('('))]),
+        (NextParagraph, &[key(Char('}'))]),
+        (PrevParagraph, &[key(Char('{'))]),
+    ],
+    "",
+)];
+
+const BOOKMARKS: &[Group] = &[(
+    &[
+        (Close, &[key(Esc), key(Char('q')), key(Char('B'))]),
+        (Open, &[key(Enter)]),
+        (Remove, &[key(Delete), key(Char('x'))]),
+        (LineDown, &[key(Down), key(Char('j'))]),
+        (LineUp, &[key(Up), key(Char('k'))]),
+        (Top, &[key(Home), key(Char('g'))]),
+        (Bottom, &[key(End), key(Char('G'))]),
+    ],
+    "",
+)];
+
+const LANDMARKS: &[Group] = &[(
+    &[
+        (Close, &[key(Esc), key(Char('q')), key(Char('L'))]),
+        (Open, &[key(Enter)]),
+        (LineDown, &[key(Down), key(Char('j'))]),
+        (LineUp, &[key(Up), key(Char('k'))]),
+        (Top, &[key(Home), key(Char('g'))]),
+        (Bottom, &[key(End), key(Char('G'))]),
+    ],
+    "",
+)];
+
+const RESULTS: &[Group] = &[(
+    &[
+        (Close, &[key(Esc), key(Char('q'))]),
+        (Open, &[key(Enter)]),
+        (LineDown, &[key(Down), key(Char('j'))]),
+        (LineUp, &[key(Up), key(Char('k'))]),
+        (NextPage, &[key(PageDown), key(Char('f'))]),
+        (PrevPage, &[key(PageUp), key(Char('b'))]),
+        (Top, &[key(Home), key(Char('g'))]),
+        (Bottom, &[key(End), key(Char('G'))]),
+    ],
+    "",
+)];
+
+const SOURCE: &[Group] = &[(
+    &[
+        (Close, &[key(Esc), key(Char('q'))]),
+        (LineDown, &[key(Down), key(Char('j'))]),
+        (LineUp, &[key(Up), key(Char('k'))]),
+        (HalfDown, &[key(Char('d'))]),
+        (HalfUp, &[key(Char('u'))]),
+        (NextPage, &[key(PageDown), key(Char(' ')), key(Char('f'))]),
+        (PrevPage, &[key(PageUp), key(Char('b'))]),
+        (Top, &[key(Home), key(Char('g'))]),
+        (Bottom, &[key(End), key(Char('G'))]),
+    ],
+    "",
+)];
+
+// comics, a page to a screen
+const PICTURE: &[Group] = &[(
+    &[
+        (Quit, &[key(Esc), key(Char('q'))]),
+        (Help, &[key(F(1))]),
+        (Toc, &[key(Tab)]),
+        (Metadata, &[key(Char('i'))]),
+        (Theme, &[key(Char('T'))]),
+        (
+            NextPage,
+            &[
+                key(Right),
+                key(Down),
+                key(PageDown),
+                key(Char(']')),
+                key(Char(' ')),
+                key(Char('f')),
+                key(Char('l')),
+                key(Char('j')),
+            ],
+        ),
+        (
+            PrevPage,
+            &[
+                key(Left),
+                key(Up),
+                key(PageUp),
+                key(Char('[')),
+                key(Char('b')),
+                key(Char('h')),
+                key(Char('k')),
+            ],
+        ),
+        (Top, &[key(Home), key(Char('g'))]),
+        (Bottom, &[key(End), key(Char('G'))]),
+    ],
+    "",
+)];
+
+const FOOTNOTE: &[Group] = &[(
+    &[(Close, &[key(Esc), key(Char('q'))]), (Open, &[key(Enter)])],
+    "",
+)];
+
+// keys not bound here are typed into the query
+const SEARCH: &[Group] = &[(
+    &[
+        (Close, &[key(Esc)]),
+        (Accept, &[key(Enter)]),
+        (ListMatches, &[key(Tab)]),
+    ],
+    "other keys are typed into the search",
+)];
+
+// keys not bound here are typed into the command
+const COMMAND: &[Group] = &[(
+    &[
+        (Close, &[key(Esc)]),
+        (Accept, &[key(Enter)]),
+        (Complete, &[key(Tab)]),
+    ],
+    "other keys are typed into the command",
+)];
+
+const HELP: &[Group] = &[(
+    &[
+        (Close, &[key(Esc), key(Char('q')), key(F(1))]),
+        (LineDown, &[key(Down), key(Char('j'))]),
+        (LineUp, &[key(Up), key(Char('k'))]),
+        (HalfDown, &[key(Char('d'))]),
+        (HalfUp, &[key(Char('u'))]),
+        (NextPage, &[key(PageDown), key(Char(' ')), key(Char('f'))]),
+        (PrevPage, &[key(PageUp), key(Char('b'))]),
+        (Top, &[key(Home), key(Char('g'))]),
+        (Bottom, &[key(End), key(Char('G'))]),
+    ],
+    "",
+)];
+
+// every view's bindings
+const DEFAULT: &[(Mode, &[Group])] = &[
+    (Mode::Page, PAGE),
+    (Mode::Toc, TOC),
+    (Mode::TocFilter, TOC_FILTER),
+    (Mode::Visual, VISUAL),
+    (Mode::Bookmarks, BOOKMARKS),
+    (Mode::Landmarks, LANDMARKS),
+    (Mode::Results, RESULTS),
+    (Mode::Source, SOURCE),
+    (Mode::Picture, PICTURE),
+    (Mode::Footnote, FOOTNOTE),
+    (Mode::Search, SEARCH),
+    (Mode::Command, COMMAND),
+    (Mode::Help, HELP),
+];
+
+type Bindings = Vec<(Action, Vec<Key>)>;
+type Groups = Vec<(Bindings, &'static str)>;
+
+// the keys bound to each action of each view, in help's order
+#[derive(Clone)]
+pub struct Keymap {
+    pub modes: Vec<(Mode, Groups)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let modes = DEFAULT
+            .iter()
+            .map(|(mode, groups)| {
+                let groups = groups
+                    .iter()
+                    .map(|(bindings, note)| {
+                        let bindings = bindings
+                            .iter()
+                            .map(|(action, keys)| (*action, keys.to_vec()))
+                            .collect();
+                        (bindings, *note)
+                    })
+                    .collect();
+                (*mode, groups)
+            })
+            .collect();
+        Keymap { modes }
+    }
+}
+
+impl Keymap {
+    // the bindings of a view
+    pub fn groups(&self, mode: Mode) -> &Groups {
+        &self.modes.iter().find(|(m, _)| *m == mode).unwrap().1
+    }
+    // bind actions of each view to the keys named instead, taking the keys
+    // from any others they were bound to in that view. a key can't be named
+    // for two actions of a view
+    pub fn remap(
+        &mut self,
+        remaps: &HashMap<Mode, HashMap<Action, Vec<String>>>,
+    ) -> Result<(), String> {
+        for (mode, groups) in &mut self.modes {
+            let remaps = match remaps.get(mode) {
+                Some(remaps) => remaps,
+                None => continue,
+            };
+            let actions: Vec<_> = groups
+                .iter()
+                .flat_map(|(bindings, _)| bindings)
+                .map(|(action, _)| *action)
+                .collect();
+            if let Some(action) = remaps.keys().find(|a| !actions.contains(a)) {
+                return Err(format!("{} has no {}", kebab(mode), kebab(action)));
+            }
+            let mut taken = Vec::new();
+            // in help's order, so errors don't change from run to run
+            for action in &actions {
+                for name in remaps.get(action).into_iter().flatten() {
+                    let key: Key = name.parse()?;
+                    // the page reads 1-9 as a count before the keymap sees them
+                    if let (Mode::Page, Char('1'..='9'), false) = (&*mode, key.code, key.ctrl) {
+                        return Err(format!("{} types a count in page", key.name()));
+                    }
+                    if taken.iter().any(|&(k, a)| k == key && a != *action) {
+                        return Err(format!(
+                            "{} is bound to more than one action in {}",
+                            key.name(),
+                            kebab(mode)
+                        ));
+                    }
+                    taken.push((key, *action));
+                }
+            }
+            for (bindings, _) in groups {
+                for (action, bound) in bindings.iter_mut() {
+                    if remaps.contains_key(action) {
+                        *bound = taken
+                            .iter()
+                            .filter(|(_, a)| a == action)
+                            .map(|&(k, _)| k)
+                            .collect();
+                    } else {
+                        bound.retain(|k| !taken.iter().any(|(t, _)| t == k));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+    pub fn action(&self, mode: Mode, key: Key) -> Option<Action> {
+        self.groups(mode)
+            .iter()
+            .flat_map(|(bindings, _)| bindings)
+            .find(|(_, keys)| keys.contains(&key))
+            .map(|&(action, _)| action)
+    }
+    // the keys bound to an action, for hints
+    pub fn keys(&self, mode: Mode, action: Action) -> String {
+        let keys = self
+            .groups(mode)
+            .iter()
+            .flat_map(|(bindings, _)| bindings)
+            .find(|(a, _)| *a == action)
+            .map_or(&[][..], |(_, keys)| &keys[..]);
+        keys.iter().map(Key::name).collect::<Vec<_>>().join(" ")
+    }
+}
+
+// a mode or action as the config names it, eg next-page
+fn kebab(name: &impl std::fmt::Debug) -> String {
+    let mut s = String::new();
+    for c in format!("{:?}", name).chars() {
+        if c.is_uppercase() && !s.is_empty() {
+            s.push('-');
+        }
+        s.push(c.to_ascii_lowercase());
+    }
+    s
+}
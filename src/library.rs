@@ -0,0 +1,104 @@
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode::*},
+    queue,
+    style::Print,
+    terminal,
+};
+use serde::{Deserialize, Serialize};
+use std::io::{stdout, Write};
+use unicode_width::UnicodeWidthChar;
+
+use crate::theme::Theme;
+
+// a book read before, for the library
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct Entry {
+    pub title: String,
+    pub author: String,
+    // percent of the book read
+    pub progress: f32,
+    // seconds since the epoch
+    pub read: u64,
+}
+
+impl Entry {
+    // the line for a book at path, fit to width
+    pub fn label(&self, path: &str, width: usize) -> String {
+        let name = if self.title.is_empty() {
+            path.rsplit(['/', '\\']).next().unwrap_or(path)
+        } else {
+            &self.title
+        };
+        let mut s = format!("{:>3.0}%  {}", self.progress, name);
+        if !self.author.is_empty() {
+            s.push_str(", ");
+            s.push_str(&self.author);
+        }
+        let mut cols = 0;
+        let mut fit = String::new();
+        for c in s.chars() {
+            cols += c.width().unwrap_or(0);
+            if cols > width {
+                fit.pop();
+                fit.push('…');
+                break;
+            }
+            fit.push(c);
+        }
+        fit
+    }
+}
+
+// pick one of lines with j, k and Enter, or None with Esc or q
+pub fn pick(title: &str, lines: &[String], theme: &Theme) -> crossterm::Result<Option<usize>> {
+    let mut stdout = stdout();
+    queue!(stdout, terminal::EnterAlternateScreen, cursor::Hide)?;
+    terminal::enable_raw_mode()?;
+
+    let mut i = 0;
+    let mut top = 0;
+    let picked = loop {
+        let rows = terminal::size()?.1 as usize;
+        // the title and a blank row above the list
+        let height = rows.saturating_sub(2).max(1);
+        if i < top {
+            top = i;
+        } else if i >= top + height {
+            top = i + 1 - height;
+        }
+        queue!(
+            stdout,
+            Print(theme.reset()),
+            terminal::Clear(terminal::ClearType::All),
+            cursor::MoveTo(0, 0),
+            Print(title)
+        )?;
+        for (row, n) in (top..lines.len().min(top + height)).enumerate() {
+            let line = if n == i {
+                theme.select(&lines[n])
+            } else {
+                lines[n].clone()
+            };
+            queue!(stdout, cursor::MoveTo(0, row as u16 + 2), Print(line))?;
+        }
+        stdout.flush()?;
+
+        if let Event::Key(e) = event::read()? {
+            match e.code {
+                Esc | Char('q') => break None,
+                Enter => break Some(i),
+                Down | Char('j') => i = (i + 1).min(lines.len().saturating_sub(1)),
+                Up | Char('k') => i = i.saturating_sub(1),
+                Home | Char('g') => i = 0,
+                End | Char('G') => i = lines.len().saturating_sub(1),
+                _ => (),
+            }
+        }
+    };
+
+    queue!(stdout, terminal::LeaveAlternateScreen, cursor::Show)?;
+    terminal::disable_raw_mode()?;
+    stdout.flush()?;
+    Ok(picked)
+}
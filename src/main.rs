@@ -1,30 +1,194 @@
 use anyhow::Result;
 use crossterm::{
     cursor,
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event},
-    queue,
+    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyModifiers},
+    execute, queue,
     style::{self, Print},
     terminal,
 };
+use hypher::Lang;
+use regex::{Regex, RegexBuilder};
 use serde::{Deserialize, Serialize};
 use std::{
-    cmp::min,
-    collections::HashMap,
-    env, fs,
-    io::{stdout, Write},
-    iter,
-    process::exit,
+    borrow::Cow,
+    cell::RefCell,
+    cmp::{max, min, Reverse},
+    collections::{HashMap, HashSet},
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, stdout, Read, Write},
+    iter, mem,
+    path::{Path, PathBuf},
+    process::{self, exit, Command},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
+use unicode_normalization::{char::is_combining_mark, is_nfc, UnicodeNormalization};
+use unicode_segmentation::UnicodeSegmentation;
 use unicode_width::UnicodeWidthChar;
 
 mod view;
-use view::{Nav, Page, Search, View};
+use view::{Cover, Nav, Page, Picture, Search, View};
 
 mod epub;
-use epub::Chapter;
+use epub::{Chapter, Layout, Punct, Ruby};
 
-fn wrap(text: &str, max_cols: usize) -> Vec<(usize, usize)> {
+mod theme;
+use theme::{Styles, Theme};
+
+mod charset;
+mod comic;
+mod config;
+mod css;
+#[cfg(feature = "djvu")]
+mod djvu;
+mod fb2;
+#[cfg(feature = "highlight")]
+mod highlight;
+mod html;
+mod img;
+mod keys;
+mod library;
+mod math;
+mod md;
+mod mobi;
+mod pdf;
+#[cfg(feature = "svg")]
+mod svg;
+mod txt;
+
+// soft hyphen, a break drawn as a hyphen only when taken
+const SHY: char = '\u{ad}';
+
+// cols of a grapheme: its base char, or two for emoji presentation and flags
+fn grapheme_width(g: &str) -> usize {
+    let mut chars = g.chars();
+    let base = chars.next().unwrap_or(' ');
+    let flag = ('\u{1f1e6}'..='\u{1f1ff}').contains(&base) && chars.next().is_some();
+    if flag || g.contains('\u{fe0f}') {
+        2
+    } else if base == SHY {
+        0
+    } else {
+        // https://github.com/unicode-rs/unicode-width/issues/6
+        base.width().unwrap_or(0)
+    }
+}
+
+fn text_width(s: &str) -> usize {
+    s.graphemes(true).map(grapheme_width).sum()
+}
+
+// text as searches see it: NFC, or without diacritics when folded
+fn normalize(s: &str, fold: bool) -> String {
+    if fold {
+        s.nfd().filter(|&c| !is_combining_mark(c)).nfc().collect()
+    } else {
+        s.nfc().collect()
+    }
+}
+
+// a chapter's text normalized for searching, and where each grapheme
+// starts in it and in the chapter
+struct SearchText {
+    text: String,
+    starts: Vec<(usize, usize)>,
+}
+
+impl SearchText {
+    // none when the text is searched as it is
+    fn new(text: &str, fold: bool) -> Option<Self> {
+        if !fold && is_nfc(text) {
+            return None;
+        }
+        let mut folded = String::new();
+        let mut starts = Vec::new();
+        for (i, g) in text.grapheme_indices(true) {
+            starts.push((folded.len(), i));
+            folded.push_str(&normalize(g, fold));
+        }
+        Some(SearchText {
+            text: folded,
+            starts,
+        })
+    }
+    // the byte here for a byte of the chapter
+    fn here(&self, byte: usize) -> usize {
+        let i = self.starts.partition_point(|&(_, b)| b < byte);
+        self.starts.get(i).map_or(self.text.len(), |&(a, _)| a)
+    }
+    // a match from a to b here, widened to whole graphemes of the chapter
+    fn there(&self, a: usize, b: usize, len: usize) -> (usize, usize) {
+        let i = self.starts.partition_point(|&(f, _)| f <= a);
+        let j = self.starts.partition_point(|&(f, _)| f < b);
+        let start = self.starts[i.saturating_sub(1)].1;
+        let end = self.starts.get(j).map_or(len, |&(_, b)| b);
+        (start, end)
+    }
+}
+
+// kinsoku: chars which can't start or end a line
+const NO_START: &str =
+    "、。，．・：；？！ー）」』】〕〉》〙〗～…‥ぁぃぅぇぉっゃゅょゎァィゥェォッャュョヮヵヶ";
+const NO_END: &str = "（「『【〔〈《〘〖";
+
+// ideographs, kana, and cjk punctuation, which break without spaces
+fn cjk(g: &str) -> bool {
+    g.chars().next().is_some_and(|c| {
+        matches!(c, '\u{2e80}'..='\u{9fff}'
+            | '\u{f900}'..='\u{faff}'
+            | '\u{ff00}'..='\u{ffef}'
+            | '\u{20000}'..='\u{3ffff}')
+    })
+}
+
+// not cjk, which breaks anywhere
+fn letter(c: char) -> bool {
+    c.is_alphabetic() && !cjk(c.encode_utf8(&mut [0; 4]))
+}
+
+// the last syllable break in the word at byte `word` that fits in cols,
+// leaving a col for the hyphen
+fn hyphenate(text: &str, word: usize, cols: usize, lang: Lang) -> Option<usize> {
+    // skip opening punctuation
+    let start = word + text[word..].find(letter)?;
+    let end = text[start..]
+        .find(|c| !letter(c))
+        .map_or(text.len(), |n| start + n);
+    let mut b = start;
+    let mut fit = None;
+    for syllable in hypher::hyphenate(&text[start..end], lang) {
+        b += syllable.len();
+        if b == end || text_width(&text[word..b]) + 1 > cols {
+            break;
+        }
+        fit = Some(b);
+    }
+    fit
+}
+
+// a line ending between two letters, drawn with a hyphen
+fn hyphenated(text: &str, end: usize) -> bool {
+    text[..end].chars().next_back().is_some_and(letter)
+        && text[end..].chars().next().is_some_and(letter)
+}
+
+// the text to draw
+fn visible(text: &str) -> String {
+    text.replace(SHY, "")
+}
+
+// indent gives the cols to leave before the line starting at a byte. with a
+// language, words that don't fit are hyphenated
+fn wrap(
+    text: &str,
+    max_cols: usize,
+    lang: Option<Lang>,
+    indent: impl Fn(usize) -> usize,
+) -> Vec<(usize, usize)> {
     let mut lines = Vec::new();
+    // cols for this line
+    let mut width = max_cols - indent(0);
     // bytes
     let mut start = 0;
     let mut end = 0;
@@ -32,52 +196,117 @@ fn wrap(text: &str, max_cols: usize) -> Vec<(usize, usize)> {
     let mut after = 0;
     // cols of unbroken line
     let mut cols = 0;
-    // are we breaking on whitespace?
-    let mut space = false;
+    // whitespace bytes to skip at the break
+    let mut skip = 0;
+    let mut prev = "\n";
+    // byte and cols of the previous grapheme
+    let mut last = (0, 0);
 
-    // should probably use unicode_segmentation grapheme_indices
-    for (i, c) in text.char_indices() {
-        // https://github.com/unicode-rs/unicode-width/issues/6
-        let char_cols = c.width().unwrap_or(0);
-        cols += char_cols;
-        match c {
-            '\n' => {
+    for (i, g) in text.grapheme_indices(true) {
+        let cjk_break = (cjk(g) || cjk(prev)) && !NO_START.contains(g) && !NO_END.contains(prev);
+        let letters = prev.chars().all(letter) && g.chars().all(letter);
+        prev = g;
+        let g_cols = match g {
+            "\t" => epub::TAB,
+            _ => grapheme_width(g),
+        };
+        cols += g_cols;
+        match g {
+            "\n" | "\r\n" => {
                 after = 0;
                 end = i;
-                space = true;
-                cols = max_cols + 1;
+                skip = g.len();
+                cols = width + 1;
             }
-            ' ' => {
+            " " | "\t" => {
                 after = 0;
                 end = i;
-                space = true;
+                skip = 1;
             }
-            '-' | '—' if cols <= max_cols => {
+            "-" | "—" if cols <= width => {
                 after = 0;
-                end = i + c.len_utf8();
-                space = false;
+                end = i + g.len();
+                skip = 0;
+            }
+            "\u{ad}" if cols < width => {
+                after = 0;
+                end = i + g.len();
+                skip = 0;
+            }
+            _ if cjk_break => {
+                after = g_cols;
+                end = i;
+                skip = 0;
+            }
+            _ => after += g_cols,
+        }
+        if cols > width && letters {
+            if let Some(b) =
+                lang.and_then(|lang| hyphenate(text, end + skip, width - (cols - after), lang))
+            {
+                lines.push((start, b));
+                start = b;
+                end = b;
+                skip = 0;
+                cols = text_width(&text[b..i + g.len()]);
+                after = cols;
+                width = max_cols - indent(start);
             }
-            _ => after += char_cols,
         }
-        if cols > max_cols {
-            // break a single long word
-            if cols == after {
-                after = char_cols;
+        if cols > width {
+            // break a single long word, with room for the hyphen
+            if cols == after && lang.is_some() && letters && last.0 > start {
+                after = last.1 + g_cols;
+                end = last.0;
+                skip = 0;
+            } else if cols == after {
+                after = g_cols;
                 end = i;
-                space = false;
+                skip = 0;
             }
             lines.push((start, end));
-            start = end;
-            if space {
-                start += 1;
-            }
+            start = end + skip;
             cols = after;
+            width = max_cols - indent(start);
         }
+        last = (i, g_cols);
+    }
+    // text not ending in a newline
+    if start < text.len() {
+        lines.push((start, text.len()));
     }
 
     lines
 }
 
+// add a range to sorted ones apart from each other, joining any it touches
+fn merge(ranges: &mut Vec<(usize, usize)>, (mut start, mut end): (usize, usize)) {
+    let i = ranges.partition_point(|r| r.1 < start);
+    let mut j = i;
+    while j < ranges.len() && ranges[j].0 <= end {
+        start = min(start, ranges[j].0);
+        end = max(end, ranges[j].1);
+        j += 1;
+    }
+    ranges.splice(i..j, [(start, end)]);
+}
+
+// a new file to edit a note in, named for this process and made only if it
+// isn't there, so other instances and files left behind are never written
+fn note_file() -> io::Result<(PathBuf, File)> {
+    static NOTES: AtomicUsize = AtomicUsize::new(0);
+    loop {
+        let n = NOTES.fetch_add(1, Ordering::Relaxed);
+        let name = format!("bk-note-{}-{}.txt", process::id(), n);
+        let path = env::temp_dir().join(name);
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
 fn get_line(lines: &[(usize, usize)], byte: usize) -> usize {
     match lines.binary_search_by_key(&byte, |&(a, _)| a) {
         Ok(n) => n,
@@ -96,37 +325,362 @@ enum Direction {
     Prev,
 }
 
+#[derive(Default)]
+pub struct Book {
+    chapters: Vec<Chapter>,
+    links: HashMap<String, (usize, usize)>,
+    meta: String,
+    images: Vec<Vec<u8>>,
+    // empty for a flat toc of the chapters
+    toc: Vec<TocEntry>,
+    // index into images, from the opf's cover metadata
+    cover: Option<usize>,
+    // chapter and byte where the text begins, past the front matter
+    start: Option<(usize, usize)>,
+    // title, chapter and byte of the cover, index and such
+    landmarks: Vec<(String, usize, usize)>,
+    // label, chapter and byte of each page of the print edition
+    page_list: Vec<(String, usize, usize)>,
+    // a comic's images, left empty above until they're shown
+    pages: Option<comic::Pages>,
+}
+
+pub struct TocEntry {
+    title: String,
+    // nesting depth, from 0
+    level: usize,
+    chapter: usize,
+    byte: usize,
+    // a chapter the book's toc leaves out
+    synthetic: bool,
+}
+
+// primary language subtags, eg en for en-US
+fn languages(meta: &str) -> impl Iterator<Item = String> + '_ {
+    meta.lines()
+        .filter_map(|l| l.strip_prefix("language: "))
+        .map(|lang| lang.split(['-', '_']).next().unwrap().trim().to_lowercase())
+}
+
+// arabic, hebrew, persian, urdu and friends
+fn rtl_language(meta: &str) -> bool {
+    languages(meta).any(|lang| {
+        [
+            "ar", "arc", "ckb", "dv", "fa", "he", "iw", "ps", "sd", "ug", "ur", "yi",
+        ]
+        .contains(&lang.as_str())
+    })
+}
+
+fn hyphenation(meta: &str) -> Option<Lang> {
+    languages(meta).find_map(|lang| match *lang.as_bytes() {
+        [a, b] => Lang::from_iso([a, b]),
+        _ => None,
+    })
+}
+
+// by content, the extension can be missing or wrong
+fn open(path: &str, meta: bool, layout: Layout) -> Result<Book> {
+    if Path::new(path).is_dir() {
+        return epub::open(path, meta, layout);
+    }
+    let mut head = Vec::new();
+    File::open(path)?.take(1024).read_to_end(&mut head)?;
+    let lower = path.to_lowercase();
+
+    if head.starts_with(b"PK\x03\x04") {
+        let zip = zip::ZipArchive::new(File::open(path)?)?;
+        let names: Vec<_> = zip.file_names().map(str::to_lowercase).collect();
+        if names.iter().any(|n| n == "meta-inf/container.xml") {
+            epub::open(path, meta, layout)
+        } else if names.iter().any(|n| n.ends_with(".fb2")) {
+            fb2::open(path, meta, layout)
+        } else {
+            comic::open(path, meta)
+        }
+    } else if head.starts_with(b"%PDF") {
+        pdf::open(path, meta)
+    } else if head.get(60..68) == Some(b"BOOKMOBI") {
+        mobi::open(path, meta, layout)
+    } else if head.starts_with(b"Rar!") {
+        comic::open(path, meta)
+    } else if head.starts_with(b"AT&TFORM") {
+        #[cfg(feature = "djvu")]
+        return djvu::open(path, meta);
+        #[cfg(not(feature = "djvu"))]
+        Err(anyhow::anyhow!("built without djvu support"))
+    } else {
+        let text = String::from_utf8_lossy(&head).to_lowercase();
+        if text.contains("<fictionbook") {
+            fb2::open(path, meta, layout)
+        } else if text.contains("<html") || text.contains("<!doctype html") {
+            html::open(path, meta, layout)
+        } else if lower.ends_with(".md") || lower.ends_with(".markdown") {
+            md::open(path, meta, layout)
+        } else {
+            txt::open(path, meta, layout)
+        }
+    }
+}
+
+// reading speed, from scrolling forward
+#[derive(Default)]
+struct Pace {
+    lines: usize,
+    secs: f32,
+    // when the last line of the book was at the top
+    last: Option<(Instant, usize)>,
+}
+
+impl Pace {
+    // after moving to line of the book. jumps past a page don't count, and
+    // a page left open for long counts as a few minutes
+    fn update(&mut self, line: usize, page: usize) {
+        let now = Instant::now();
+        if let Some((then, prev)) = self.last {
+            if line > prev && line - prev <= page {
+                self.lines += line - prev;
+                self.secs += now.duration_since(then).as_secs_f32().min(300.0);
+            }
+        }
+        self.last = Some((now, line));
+    }
+    // to read lines, once there's enough to go by
+    fn minutes(&self, lines: usize) -> Option<usize> {
+        if self.lines < 50 {
+            return None;
+        }
+        Some((lines as f32 * self.secs / self.lines as f32 / 60.0).round() as usize)
+    }
+}
+
+// run f on the main screen out of raw mode, for an editor or browser
+fn outside<T>(f: impl FnOnce() -> T) -> crossterm::Result<T> {
+    let mut stdout = stdout();
+    execute!(
+        stdout,
+        terminal::LeaveAlternateScreen,
+        cursor::Show,
+        DisableMouseCapture
+    )?;
+    terminal::disable_raw_mode()?;
+    let t = f();
+    execute!(
+        stdout,
+        terminal::EnterAlternateScreen,
+        cursor::Hide,
+        EnableMouseCapture
+    )?;
+    terminal::enable_raw_mode()?;
+    Ok(t)
+}
+
+// to the terminal's clipboard, with osc 52
+fn copy(text: &str) -> crossterm::Result<()> {
+    let mut stdout = stdout();
+    write!(stdout, "\x1b]52;c;{}\x07", base64::encode(text))?;
+    stdout.flush()?;
+    Ok(())
+}
+
+// cols between two columns
+const GAP: u16 = 6;
+// bytes of text per location, as kindles count them
+const LOCATION: usize = 150;
+
 pub struct Bk<'a> {
     chapters: Vec<epub::Chapter>,
     // position in the book
     chapter: usize,
     line: usize,
+    // digits typed before a command
+    count: usize,
+    pace: Pace,
+    // first page of each chapter then the total, at page_size lines a page
+    pages: Vec<usize>,
+    page_size: usize,
+    // chapter and byte, so they survive a resize
     mark: HashMap<char, (usize, usize)>,
+    // marks given a name longer than a letter with :mark
+    named: HashMap<String, (usize, usize)>,
+    // positions jumped from, and where ctrl-o and ctrl-i are in them
+    jumps: Vec<(usize, usize)>,
+    jump_index: usize,
     links: HashMap<String, (usize, usize)>,
+    images: Vec<Vec<u8>>,
+    // where a comic's images are read from when shown
+    archive: Option<RefCell<comic::Pages>>,
+    toc: Vec<TocEntry>,
+    // selected toc entry
+    toc_index: usize,
+    // toc entries showing their children
+    expanded: HashSet<usize>,
+    // typed after / in the toc, narrowing it
+    toc_filter: Option<String>,
+    // the entry selected when the filter began, selected again once it's empty
+    toc_unfiltered: usize,
+    protocol: img::Protocol,
+    // cells of each image at its own size, None if it can't be read
+    sizes: Vec<Option<(u16, u16)>>,
+    // every chapter is a single image
+    comic: bool,
+    // index into images, shown until a key when opening
+    cover: Option<usize>,
     // layout
     cols: u16,
     rows: usize,
     max_width: u16,
+    // spread lines to the full width
+    justify: bool,
+    // two columns side by side, if they fit
+    columns: bool,
+    // rows per line of text, blank rows filling the rest
+    line_height: f32,
+    // lines of the last page kept when paging
+    overlap: usize,
+    // a status bar in the last row
+    status: bool,
+    // the toc title in the first row
+    header: bool,
+    theme: Theme,
+    // the rest to cycle through
+    themes: Vec<Theme>,
+    styles: Styles,
     // view state
     view: Option<&'a dyn View>,
     cursor: usize,
     dir: Direction,
     meta: Vec<String>,
     query: String,
+    // typed after :
+    command: String,
+    // an error from a command, until the next key
+    message: Option<String>,
+    // a command asked to save the state now
+    write: bool,
+    // names of the books open, this one's index, and the one to switch to
+    books: Vec<String>,
+    book: usize,
+    switch: Option<usize>,
+    // target of the footnote being shown, and the link to it
+    note: (usize, usize, usize),
+    // chapter, top of the page and link, for each link followed in the book
+    returns: Vec<(usize, usize, usize)>,
+    // chapter, byte and the start of the line, in book order
+    bookmarks: Vec<(usize, usize, String)>,
+    // the selected bookmark
+    bookmark: usize,
+    landmarks: Vec<(String, usize, usize)>,
+    // selected landmark
+    landmark: usize,
+    keys: keys::Keymap,
+    // top row of :source, and the byte reversed in it
+    source: (usize, usize),
+    // the chapter's markup while :source shows it
+    markup: String,
+    // top row of help
+    help: usize,
+    page_list: Vec<(String, usize, usize)>,
+    // chapter, start and end of every match of the query, and the selected
+    results: Vec<(usize, usize, usize)>,
+    result: usize,
+    // Enter in search lists the results
+    list_results: bool,
+    // the query is a regular expression
+    regex: bool,
+    // matches stay reversed in the page after a search
+    hlsearch: bool,
+    // matches are whole words
+    word: bool,
+    // searches go on from the other end of the book
+    wrapscan: bool,
+    // searches stay in the current chapter
+    chapter_search: bool,
+    // searches ignore diacritics
+    fold: bool,
+    // each chapter's text for searching, when it's not the text itself
+    search_texts: Vec<Option<SearchText>>,
+    // the query and flags last counted, and its matches in each chapter
+    counted: (String, bool, bool, bool),
+    counts: Vec<usize>,
+    // typing a search
+    searching: bool,
+    // chapter, start and end of highlighted text, in book order
+    highlights: Vec<(usize, usize, usize)>,
+    // chapter, and the bytes where a mouse selection started and is now
+    selection: Option<(usize, usize, usize)>,
+    // the selection is from v, so one grapheme counts
+    visual: bool,
+    // chapter and index of the link picked with the keyboard
+    link: Option<(usize, usize)>,
+    // chapter, byte and note, in book order
+    annotations: Vec<(usize, usize, String)>,
+    // the bytes shown of each chapter, as sorted ranges apart from each other
+    seen: Vec<Vec<(usize, usize)>>,
 }
 
 impl Bk<'_> {
-    fn new(epub: epub::Epub, args: Props) -> Self {
+    fn new(book: Book, args: Props) -> Self {
         let (cols, rows) = terminal::size().unwrap();
         let width = min(cols, args.width) as usize;
-        let meta = wrap(&epub.meta, width)
+        let meta = wrap(&book.meta, width, None, |_| 0)
             .into_iter()
-            .map(|(a, b)| String::from(&epub.meta[a..b]))
+            .map(|(a, b)| String::from(&book.meta[a..b]))
             .collect();
 
-        let mut chapters = epub.chapters;
+        let mut chapters = book.chapters;
+        let mut toc = book.toc;
+        if toc.is_empty() {
+            toc = chapters
+                .iter()
+                .enumerate()
+                .map(|(i, c)| TocEntry {
+                    title: c.title.clone(),
+                    level: 0,
+                    chapter: i,
+                    byte: 0,
+                    synthetic: false,
+                })
+                .collect();
+        } else {
+            // otherwise chapters the toc skips are unreachable from it
+            for (i, c) in chapters.iter().enumerate() {
+                if toc.iter().any(|e| e.chapter == i) {
+                    continue;
+                }
+                let at = toc.iter().position(|e| e.chapter > i).unwrap_or(toc.len());
+                toc.insert(
+                    at,
+                    TocEntry {
+                        title: c.title.clone(),
+                        level: toc.get(at).map_or(0, |e| e.level),
+                        chapter: i,
+                        byte: 0,
+                        synthetic: true,
+                    },
+                );
+            }
+        }
+        // the book could have changed since they were saved
+        let mut marks = args.marks;
+        marks.retain(|_, m| chapters.get(m.0).is_some_and(|c| m.1 < c.text.len()));
+        let mut named = args.named;
+        named.retain(|_, m| chapters.get(m.0).is_some_and(|c| m.1 < c.text.len()));
+        let mut bookmarks = args.bookmarks;
+        bookmarks.retain(|b| chapters.get(b.0).is_some_and(|c| b.1 <= c.text.len()));
+        let mut highlights = args.highlights;
+        highlights.retain(|h| chapters.get(h.0).is_some_and(|c| h.2 <= c.text.len()));
+        let mut annotations = args.annotations;
+        annotations.retain(|a| chapters.get(a.0).is_some_and(|c| a.1 < c.text.len()));
+        let mut seen = args.seen;
+        seen.resize(chapters.len(), Vec::new());
+        let sizes: Vec<_> = book.images.iter().map(|data| img::size(data)).collect();
+        let rtl = rtl_language(&book.meta);
+        let lang = hyphenation(&book.meta);
         for c in &mut chapters {
-            c.lines = wrap(&c.text, width);
+            c.rtl |= rtl;
+            c.lang = lang;
+            c.wrap(width, &sizes);
             if c.title.chars().count() > width {
                 c.title = c
                     .title
@@ -137,31 +691,260 @@ impl Bk<'_> {
             }
         }
 
+        // chapters of a lone image
+        let comic = chapters.iter().all(|c| match c.images[..] {
+            [(byte, _)] => {
+                c.text[..byte].trim().is_empty() && !c.text[byte..].trim().contains('\n')
+            }
+            _ => false,
+        });
+
+        let (chapter, byte) = match book.start {
+            Some(start) if args.start => start,
+            // --chapter past the end
+            _ if args.chapter >= chapters.len() => (chapters.len() - 1, 0),
+            _ => (args.chapter, args.byte),
+        };
+        let protocol = img::detect();
         let mut bk = Bk {
             chapters,
-            chapter: args.chapter,
+            chapter,
             line: 0,
-            mark: HashMap::new(),
-            links: epub.links,
+            count: 0,
+            pace: Pace::default(),
+            pages: Vec::new(),
+            page_size: 1,
+            mark: marks,
+            named,
+            jumps: Vec::new(),
+            jump_index: 0,
+            links: book.links,
+            images: book.images,
+            archive: book.pages.map(RefCell::new),
+            toc,
+            toc_index: 0,
+            expanded: HashSet::new(),
+            toc_filter: None,
+            toc_unfiltered: 0,
+            protocol,
+            sizes,
+            comic,
+            // a graphics protocol, not half blocks
+            cover: book
+                .cover
+                .filter(|_| !comic && !matches!(protocol, img::Protocol::Halfblock)),
             cols,
             rows: rows as usize,
             max_width: args.width,
-            view: Some(if args.toc { &Nav } else { &Page }),
+            justify: args.justify,
+            columns: args.columns,
+            status: false,
+            header: args.header,
+            line_height: args.line_height,
+            overlap: args.overlap,
+            theme: args.themes[0],
+            themes: args.themes[1..].to_vec(),
+            styles: args.styles,
+            view: Some(if comic { &Picture } else { &Page }),
             cursor: 0,
             dir: Direction::Next,
             meta,
             query: String::new(),
+            command: String::new(),
+            message: None,
+            write: false,
+            books: Vec::new(),
+            book: 0,
+            switch: None,
+            note: (0, 0, 0),
+            returns: Vec::new(),
+            bookmarks,
+            bookmark: 0,
+            landmarks: book.landmarks,
+            landmark: 0,
+            keys: args.keys,
+            source: (0, 0),
+            markup: String::new(),
+            help: 0,
+            page_list: book.page_list,
+            results: Vec::new(),
+            result: 0,
+            list_results: args.list_results,
+            regex: args.regex,
+            hlsearch: args.hlsearch,
+            word: args.word,
+            wrapscan: args.wrapscan,
+            chapter_search: args.chapter_search,
+            fold: args.fold,
+            search_texts: Vec::new(),
+            counted: (String::new(), false, false, false),
+            counts: Vec::new(),
+            searching: false,
+            highlights,
+            seen,
+            selection: None,
+            visual: false,
+            link: None,
+            annotations,
         };
 
-        bk.line = get_line(&bk.chap().lines, args.byte);
+        bk.paginate();
+        bk.refold();
+        bk.line = get_line(&bk.chap().lines, byte);
         bk.mark('\'');
+        if args.toc {
+            Nav.start(&mut bk);
+        } else if bk.cover.is_some() {
+            bk.view = Some(&Cover);
+        }
 
         bk
     }
+    // the reading view
+    fn page(&self) -> &'static dyn View {
+        if self.comic {
+            &Picture
+        } else {
+            &Page
+        }
+    }
+    // screen row of the nth line of a page
+    fn row(&self, n: usize) -> usize {
+        n + (n as f32 * (self.line_height - 1.0)) as usize
+    }
+    // rows for the text, less the status bar and header
+    fn text_rows(&self) -> usize {
+        let bars = self.status as usize + self.header as usize;
+        self.rows.saturating_sub(bars).max(1)
+    }
+    // lines of text in a column
+    fn column_rows(&self) -> usize {
+        (0..self.text_rows())
+            .take_while(|&n| self.row(n) < self.text_rows())
+            .count()
+            .max(1)
+    }
+    // lines of text on a page
+    fn page_rows(&self) -> usize {
+        self.column_rows() * if self.spread() { 2 } else { 1 }
+    }
+    // an image's data, from the comic's archive if it wasn't read on opening
+    fn image(&self, i: usize) -> Result<Cow<'_, [u8]>> {
+        match &self.archive {
+            Some(archive) => Ok(Cow::Owned(archive.borrow_mut().read(i)?)),
+            None => Ok(Cow::Borrowed(&self.images[i])),
+        }
+    }
+    // the page, joined to the ranges seen before. it runs to the next
+    // page's first line, so pages read one after another join up
+    fn see(&mut self) {
+        let c = self.chap();
+        let last = min(self.line + self.page_rows(), c.lines.len());
+        let start = c.lines.get(self.line).map_or(0, |l| l.0);
+        let end = c.lines.get(last).map_or(c.text.len(), |l| l.0);
+        merge(&mut self.seen[self.chapter], (start, end));
+    }
+    // count the matches in the book when the search changes
+    fn count_matches(&mut self) {
+        let key = (self.query.clone(), self.regex, self.word, self.fold);
+        if self.counted == key {
+            return;
+        }
+        self.counts = match self.pattern() {
+            Some(re) => (0..self.chapters.len())
+                .map(|c| {
+                    re.find_iter(self.search_text(c))
+                        .filter(|m| !m.is_empty())
+                        .count()
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        self.counted = key;
+    }
+    // the first match from the top of the screen on, from 1, and the
+    // number of matches in the book
+    fn match_index(&self) -> Option<(usize, usize)> {
+        if self.query.is_empty() || self.counts.is_empty() {
+            return None;
+        }
+        let pos = self.chap().lines[self.line].0;
+        let here = self.matches_in(self.chapter, 0, pos).len();
+        let (before, total) = if self.chapter_search {
+            (here, self.counts[self.chapter])
+        } else {
+            let before: usize = self.counts[..self.chapter].iter().sum();
+            (before + here, self.counts.iter().sum())
+        };
+        Some((min(before + 1, total), total))
+    }
+    // the title, or else the file name of the book at path
+    fn name(&self, path: &str) -> String {
+        let title = self.meta.iter().find_map(|m| m.strip_prefix("title: "));
+        match title {
+            Some(title) if !title.is_empty() => title.to_string(),
+            _ => path.rsplit(['/', '\\']).next().unwrap_or(path).to_string(),
+        }
+    }
+    // leave this book for another one open
+    fn switch_to(&mut self, book: usize) {
+        if book != self.book && book < self.books.len() {
+            self.switch = Some(book);
+            self.view = None;
+        }
+    }
+    // the last toc entry at or before the top of the page
+    fn toc_entry(&self) -> Option<usize> {
+        let pos = (self.chapter, self.chap().lines[self.line].0);
+        self.toc.iter().rposition(|e| (e.chapter, e.byte) <= pos)
+    }
+    // the print page the top of the screen is on
+    fn print_page(&self) -> Option<&str> {
+        let pos = (self.chapter, self.chap().lines[self.line].0);
+        self.page_list
+            .iter()
+            .filter(|p| (p.1, p.2) <= pos)
+            .max_by_key(|p| (p.1, p.2))
+            .map(|p| p.0.as_str())
+    }
+    // the print page labeled page
+    fn goto_print_page(&mut self, page: &str) -> bool {
+        let found = self
+            .page_list
+            .iter()
+            .find(|p| p.0.eq_ignore_ascii_case(page))
+            .map(|p| (p.1, p.2));
+        if let Some((c, byte)) = found {
+            let line = get_line(&self.chapters[c].lines, byte);
+            self.jump((c, line));
+        }
+        found.is_some()
+    }
+    // lines to page by, keeping the overlap
+    fn page_step(&self) -> usize {
+        self.page_rows().saturating_sub(self.overlap).max(1)
+    }
+    // two columns fit
+    fn spread(&self) -> bool {
+        self.columns && self.cols >= 2 * self.max_width + GAP
+    }
     fn pad(&self) -> u16 {
+        if self.spread() {
+            return (self.cols - 2 * self.max_width - GAP) / 2;
+        }
         self.cols.saturating_sub(self.max_width) / 2
     }
-    fn run(&mut self) -> crossterm::Result<()> {
+    // wrap to a new width, staying at the same text
+    fn rewrap(&mut self) {
+        let byte = self.chap().lines[self.line].0;
+        let width = min(self.cols, self.max_width) as usize;
+        for c in &mut self.chapters {
+            c.wrap(width, &self.sizes);
+        }
+        self.line = get_line(&self.chap().lines, byte);
+        self.paginate();
+    }
+    fn run(&mut self, mut write: impl FnMut(&Bk) -> Result<()>) -> crossterm::Result<()> {
         let mut stdout = stdout();
         queue!(
             stdout,
@@ -172,10 +955,12 @@ impl Bk<'_> {
         terminal::enable_raw_mode()?;
 
         while let Some(view) = self.view {
+            self.see();
+            // the clear fills with the background color
             queue!(
                 stdout,
-                terminal::Clear(terminal::ClearType::All),
-                Print(style::Attribute::Reset)
+                Print(self.theme.reset()),
+                terminal::Clear(terminal::ClearType::All)
             )?;
             for (i, line) in view.render(self).iter().enumerate() {
                 queue!(stdout, cursor::MoveTo(self.pad(), i as u16), Print(line))?;
@@ -183,63 +968,385 @@ impl Bk<'_> {
             queue!(stdout, cursor::MoveTo(self.pad(), self.cursor as u16))?;
             stdout.flush().unwrap();
 
-            match event::read()? {
-                Event::Key(e) => view.on_key(self, e.code),
+            // redraw the status bar clock on the minute
+            if self.status {
+                let secs = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.as_secs());
+                if !event::poll(Duration::from_secs(60 - secs % 60))? {
+                    continue;
+                }
+            }
+            let event = event::read()?;
+            self.message = None;
+            match event {
+                Event::Key(e) => {
+                    let key = keys::Key {
+                        code: e.code,
+                        ctrl: e.modifiers.contains(KeyModifiers::CONTROL),
+                    };
+                    view.on_key(self, key)
+                }
                 Event::Mouse(e) => view.on_mouse(self, e),
                 Event::Resize(cols, rows) => {
                     self.rows = rows as usize;
                     if cols != self.cols {
                         self.cols = cols;
-                        let width = min(cols, self.max_width) as usize;
-                        for c in &mut self.chapters {
-                            c.lines = wrap(&c.text, width);
-                        }
+                        self.rewrap();
+                    } else {
+                        self.paginate();
                     }
                     view.on_resize(self);
-                    // XXX marks aren't updated
+                    // the lines moved
+                    self.pace.last = None;
                 }
             }
+            if mem::take(&mut self.write) {
+                if let Err(e) = write(self) {
+                    self.message = Some(format!("error saving state: {}", e));
+                }
+            }
+            self.count_matches();
+            if self.pace.last.is_none_or(|last| last.1 != self.book_line()) {
+                self.pace.update(self.book_line(), self.page_rows());
+            }
         }
 
         queue!(
             stdout,
+            Print(style::Attribute::Reset),
             terminal::LeaveAlternateScreen,
             cursor::Show,
             DisableMouseCapture
         )?;
         terminal::disable_raw_mode()
     }
+    fn next_theme(&mut self) {
+        self.themes.push(self.theme);
+        self.theme = self.themes.remove(0);
+    }
+    // bookmark the top of the page, remembering its first words
+    fn add_bookmark(&mut self) {
+        let c = self.chap();
+        let byte = c.lines[self.line].0;
+        let snippet = c.lines[self.line..]
+            .iter()
+            .map(|&(a, b)| visible(c.text[a..b].trim()))
+            .find(|line| !line.is_empty())
+            .unwrap_or_default();
+        let snippet = snippet.chars().take(60).collect();
+        let mark = (self.chapter, byte, snippet);
+        match self
+            .bookmarks
+            .binary_search_by_key(&(mark.0, mark.1), |b| (b.0, b.1))
+        {
+            Ok(_) => (),
+            Err(i) => self.bookmarks.insert(i, mark),
+        }
+    }
+    // the selected bytes of this chapter, through the last grapheme
+    fn selected(&self) -> Option<(usize, usize)> {
+        let (chapter, a, b) = self.selection?;
+        if chapter != self.chapter || (a == b && !self.visual) {
+            return None;
+        }
+        let (start, last) = (min(a, b), max(a, b));
+        let text = &self.chap().text[last..];
+        Some((
+            start,
+            last + text.graphemes(true).next().map_or(0, str::len),
+        ))
+    }
+    // highlight the selection, or remove the highlights in it or at a click
+    fn toggle_highlight(&mut self) {
+        let selected = self.selected();
+        let (chapter, click, _) = match self.selection.take() {
+            Some(s) if s.0 == self.chapter => s,
+            _ => return,
+        };
+        let (start, end) = selected.unwrap_or((click, click + 1));
+        let n = self.highlights.len();
+        self.highlights
+            .retain(|h| h.0 != chapter || h.2 <= start || end <= h.1);
+        if self.highlights.len() == n && selected.is_some() {
+            let i = self
+                .highlights
+                .partition_point(|h| (h.0, h.1) < (chapter, start));
+            self.highlights.insert(i, (chapter, start, end));
+        }
+    }
+    // the ' mark is where the last jump was from, and goes on the jump list
     fn mark(&mut self, c: char) {
-        self.mark.insert(c, (self.chapter, self.line));
+        let pos = (self.chapter, self.chap().lines[self.line].0);
+        if c == '\'' {
+            self.jumps.truncate(self.jump_index);
+            if self.jumps.last() != Some(&pos) {
+                self.jumps.push(pos);
+            }
+            if self.jumps.len() > 100 {
+                self.jumps.remove(0);
+            }
+            self.jump_index = self.jumps.len();
+        }
+        self.mark.insert(c, pos);
+    }
+    // ctrl-o, saving where it's from to come back with ctrl-i
+    fn jump_back(&mut self) {
+        if self.jump_index == self.jumps.len() {
+            let pos = (self.chapter, self.chap().lines[self.line].0);
+            if self.jumps.last() != Some(&pos) {
+                self.jumps.push(pos);
+            }
+            self.jump_index = self.jumps.len() - 1;
+        }
+        if self.jump_index > 0 {
+            self.jump_index -= 1;
+            self.goto_jump();
+        }
+    }
+    fn jump_forward(&mut self) {
+        if self.jump_index + 1 < self.jumps.len() {
+            self.jump_index += 1;
+            self.goto_jump();
+        }
+    }
+    fn goto_jump(&mut self) {
+        let (c, byte) = self.jumps[self.jump_index];
+        self.chapter = c;
+        self.line = get_line(&self.chap().lines, byte);
+    }
+    // write a note in $EDITOR at the selection, or the highlight it's in, or
+    // else the top of the page. an empty note removes it
+    fn annotate(&mut self) -> crossterm::Result<()> {
+        let byte = match self.selection.take() {
+            Some((chapter, a, b)) if chapter == self.chapter => {
+                let a = min(a, b);
+                self.highlights
+                    .iter()
+                    .find(|h| h.0 == chapter && h.1 <= a && a < h.2)
+                    .map_or(a, |h| h.1)
+            }
+            _ => self.chap().lines[self.line].0,
+        };
+        let key = (self.chapter, byte);
+        let i = self.annotations.binary_search_by_key(&key, |a| (a.0, a.1));
+        let (path, mut file) = note_file()?;
+        file.write_all(i.map_or("", |i| &self.annotations[i].2).as_bytes())?;
+        drop(file);
+        let editor = env::var("VISUAL")
+            .or_else(|_| env::var("EDITOR"))
+            .unwrap_or_else(|_| String::from(if cfg!(windows) { "notepad" } else { "vi" }));
+        let mut words = editor.split_whitespace();
+        let status = outside(|| {
+            Command::new(words.next().unwrap_or("vi"))
+                .args(words)
+                .arg(&path)
+                .status()
+        })?;
+        let note = fs::read_to_string(&path);
+        fs::remove_file(&path)?;
+        if !status?.success() {
+            return Ok(());
+        }
+        let note = note?.trim().to_string();
+        match i {
+            Ok(i) if note.is_empty() => {
+                self.annotations.remove(i);
+            }
+            Ok(i) => self.annotations[i].2 = note,
+            Err(_) if note.is_empty() => (),
+            Err(i) => self.annotations.insert(i, (key.0, key.1, note)),
+        }
+        Ok(())
+    }
+    // in the browser, or whatever opens the kind of url
+    fn open_url(&self, url: &str) -> crossterm::Result<()> {
+        let opener = if cfg!(windows) {
+            "explorer"
+        } else if cfg!(target_os = "macos") {
+            "open"
+        } else {
+            "xdg-open"
+        };
+        outside(|| Command::new(opener).arg(url).status())??;
+        Ok(())
     }
     fn jump(&mut self, (c, l): (usize, usize)) {
         self.mark('\'');
         self.chapter = c;
         self.line = l;
     }
+    // the line of the heading after or before line, in this chapter
+    fn heading(&self, line: usize, forward: bool) -> Option<usize> {
+        let c = self.chap();
+        let mut lines = c.headings.iter().map(|h| get_line(&c.lines, h.0));
+        if forward {
+            lines.find(|&l| l > line)
+        } else {
+            lines.rev().find(|&l| l < line)
+        }
+    }
+    // the top line to the next or previous paragraph or sentence starting
+    // on another line
+    fn move_by(&mut self, paragraph: bool, forward: bool) {
+        let c = self.chap();
+        let next = |b| {
+            if paragraph {
+                c.paragraph(b, forward)
+            } else {
+                c.sentence(b, forward)
+            }
+        };
+        let mut byte = c.lines[self.line].0;
+        while let Some(b) = next(byte) {
+            byte = b;
+            if get_line(&c.lines, b) != self.line {
+                break;
+            }
+        }
+        self.line = get_line(&c.lines, byte);
+    }
+    // go to a link in the book, to come back to link i here
+    fn follow_link(&mut self, i: usize, (c, byte): (usize, usize)) {
+        let top = self.chap().lines[self.line].0;
+        self.returns.push((self.chapter, top, i));
+        self.jump((c, get_line(&self.chapters[c].lines, byte)));
+    }
+    // back to the page of the last link followed, picking it
+    fn go_back(&mut self) {
+        if let Some((c, byte, i)) = self.returns.pop() {
+            self.jump((c, get_line(&self.chapters[c].lines, byte)));
+            self.link = Some((c, i));
+        }
+    }
+    fn jump_mark(&mut self, c: char) {
+        if let Some(&(c, byte)) = self.mark.get(&c) {
+            self.jump((c, get_line(&self.chapters[c].lines, byte)));
+        }
+    }
+    // a mark a letter names, or one named with :mark
+    fn jump_named(&mut self, name: &str) -> bool {
+        let mut chars = name.chars();
+        let pos = match (chars.next(), chars.next()) {
+            (Some(c), None) => self.mark.get(&c),
+            _ => self.named.get(name),
+        };
+        match pos {
+            Some(&(c, byte)) => {
+                self.jump((c, get_line(&self.chapters[c].lines, byte)));
+                true
+            }
+            None => false,
+        }
+    }
     fn jump_reset(&mut self) {
-        let &(c, l) = self.mark.get(&'\'').unwrap();
+        let &(c, byte) = self.mark.get(&'\'').unwrap();
         self.chapter = c;
-        self.line = l;
+        self.line = get_line(&self.chap().lines, byte);
     }
     fn chap(&self) -> &Chapter {
         &self.chapters[self.chapter]
     }
+    // the reading order skips linear="no" chapters, nav can still reach them
+    fn next_linear(&self) -> Option<usize> {
+        (self.chapter + 1..self.chapters.len()).find(|&c| self.chapters[c].linear)
+    }
+    fn prev_linear(&self) -> Option<usize> {
+        (0..self.chapter).rev().find(|&c| self.chapters[c].linear)
+    }
+    // number the pages, each chapter starting a new one. they only change
+    // with the screen size, not with the status bar
+    fn paginate(&mut self) {
+        self.page_size = self.page_rows();
+        let mut n = 0;
+        self.pages.clear();
+        for c in &self.chapters {
+            self.pages.push(n);
+            n += max(1, c.lines.len().div_ceil(self.page_size));
+        }
+        self.pages.push(n);
+    }
+    // the page at the top of the screen, from 0, and the number of pages
+    fn page_number(&self) -> (usize, usize) {
+        let page = self.pages[self.chapter] + self.line / self.page_size;
+        (page, *self.pages.last().unwrap())
+    }
+    // the page from page_number
+    fn goto_page(&mut self, page: usize) {
+        let (_, pages) = self.page_number();
+        let page = min(page, pages - 1);
+        let c = self.pages.partition_point(|&p| p <= page) - 1;
+        let line = (page - self.pages[c]) * self.page_size;
+        self.jump((c, min(line, self.chapters[c].lines.len() - 1)));
+    }
+    // percent of the lines before the top of the screen
+    fn progress(&self) -> f32 {
+        let total: usize = self.chapters.iter().map(|c| c.lines.len()).sum();
+        self.book_line() as f32 / total.max(1) as f32 * 100.0
+    }
+    // lines of the book before the top of the screen
+    fn book_line(&self) -> usize {
+        let before: usize = self.chapters[..self.chapter]
+            .iter()
+            .map(|c| c.lines.len())
+            .sum();
+        before + self.line
+    }
+    // the location at the top of the screen, from 1, and the number of them
+    fn location(&self) -> (usize, usize) {
+        let before: usize = self.chapters[..self.chapter]
+            .iter()
+            .map(|c| c.text.len())
+            .sum();
+        let total: usize = self.chapters.iter().map(|c| c.text.len()).sum();
+        let byte = before + self.chap().lines[self.line].0;
+        (byte / LOCATION + 1, total.div_ceil(LOCATION).max(1))
+    }
+    // the location from location
+    fn goto_location(&mut self, location: usize) {
+        let mut byte = (location - 1) * LOCATION;
+        for (c, chapter) in self.chapters.iter().enumerate() {
+            if byte < chapter.text.len() {
+                let mut line = get_line(&chapter.lines, byte);
+                // the first line starting in it, so the status shows it
+                let next = chapter.lines.get(line + 1);
+                if chapter.lines[line].0 < byte && next.is_some_and(|l| l.0 < byte + LOCATION) {
+                    line += 1;
+                }
+                self.jump((c, line));
+                return;
+            }
+            byte -= chapter.text.len();
+        }
+        self.goto_percent(100);
+    }
+    // a percent of the lines in the whole book
+    fn goto_percent(&mut self, percent: usize) {
+        let total: usize = self.chapters.iter().map(|c| c.lines.len()).sum();
+        let mut line = min(total * percent / 100, total.saturating_sub(1));
+        for (c, chapter) in self.chapters.iter().enumerate() {
+            if line < chapter.lines.len() {
+                self.jump((c, line));
+                return;
+            }
+            line -= chapter.lines.len();
+        }
+    }
     fn next_chapter(&mut self) {
-        if self.chapter < self.chapters.len() - 1 {
-            self.chapter += 1;
+        if let Some(c) = self.next_linear() {
+            self.chapter = c;
             self.line = 0;
         }
     }
     fn prev_chapter(&mut self) {
-        if self.chapter > 0 {
-            self.chapter -= 1;
+        if let Some(c) = self.prev_linear() {
+            self.chapter = c;
             self.line = 0;
         }
     }
     fn scroll_down(&mut self, n: usize) {
-        if self.line + self.rows < self.chap().lines.len() {
-            self.line += n;
+        if self.line + self.page_rows() < self.chap().lines.len() {
+            self.line = min(self.line + n, self.chap().lines.len() - 1);
         } else {
             self.next_chapter();
         }
@@ -247,49 +1354,141 @@ impl Bk<'_> {
     fn scroll_up(&mut self, n: usize) {
         if self.line > 0 {
             self.line = self.line.saturating_sub(n);
-        } else if self.chapter > 0 {
-            self.chapter -= 1;
-            self.line = self.chap().lines.len().saturating_sub(self.rows);
+        } else if let Some(c) = self.prev_linear() {
+            self.chapter = c;
+            self.line = self.chap().lines.len().saturating_sub(self.page_rows());
         }
     }
     fn start_search(&mut self, dir: Direction) {
         self.mark('\'');
         self.query.clear();
+        self.searching = true;
         self.dir = dir;
         self.view = Some(&Search);
     }
+    // the query, as typed if it's not a valid regex. smart case: ignoring
+    // case unless it has a capital, not counting escapes like \W
+    fn pattern(&self) -> Option<Regex> {
+        if self.query.is_empty() {
+            return None;
+        }
+        let query = normalize(&self.query, self.fold);
+        let regex = self.regex && Regex::new(&query).is_ok();
+        let mut escaped = false;
+        let capital = query.chars().any(|c| {
+            let capital = c.is_uppercase() && !escaped;
+            escaped = regex && c == '\\' && !escaped;
+            capital
+        });
+        let literal = regex::escape(&query);
+        let pattern = if regex { &query } else { &literal };
+        let pattern = if self.word {
+            format!(r"\b(?:{})\b", pattern)
+        } else {
+            pattern.to_string()
+        };
+        RegexBuilder::new(&pattern)
+            .case_insensitive(!capital)
+            .build()
+            .ok()
+    }
+    // normalize the chapters for searching, after fold changes
+    fn refold(&mut self) {
+        self.search_texts = self
+            .chapters
+            .iter()
+            .map(|c| SearchText::new(&c.text, self.fold))
+            .collect();
+    }
+    fn search_text(&self, c: usize) -> &str {
+        match &self.search_texts[c] {
+            Some(s) => &s.text,
+            None => &self.chapters[c].text,
+        }
+    }
+    // start and end of the matches in chapter c
+    fn matches(&self, c: usize) -> Vec<(usize, usize)> {
+        self.matches_in(c, 0, self.chapters[c].text.len())
+    }
+    // the matches starting from start to end
+    fn matches_in(&self, c: usize, start: usize, end: usize) -> Vec<(usize, usize)> {
+        let re = match self.pattern() {
+            Some(re) => re,
+            None => return Vec::new(),
+        };
+        let folded = self.search_texts[c].as_ref();
+        let (start, end) = match folded {
+            Some(s) => (s.here(start), s.here(end)),
+            None => (start, end),
+        };
+        let text = self.search_text(c);
+        let mut matches = Vec::new();
+        let mut at = start;
+        while let Some(m) = re.find_at(text, at).filter(|m| m.start() < end) {
+            // empty matches, like a*, would never move on
+            if !m.is_empty() {
+                matches.push((m.start(), m.end()));
+            }
+            at = match text[m.end()..].chars().next() {
+                Some(ch) if m.is_empty() => m.end() + ch.len_utf8(),
+                Some(_) => m.end(),
+                None => break,
+            };
+        }
+        if let Some(s) = folded {
+            let len = self.chapters[c].text.len();
+            for m in &mut matches {
+                *m = s.there(m.0, m.1, len);
+            }
+        }
+        matches
+    }
     fn search(&mut self, args: SearchArgs) -> bool {
         let (start, end) = self.chap().lines[self.line];
+        let chapter = self.chapter;
+        let len = |c: usize| self.chapters[c].text.len();
+        // chapter and bytes to look in, in order, and whether that wrapped
+        let mut ranges = Vec::new();
         match args.dir {
             Direction::Next => {
                 let byte = if args.skip { end } else { start };
-                let head = (self.chapter, byte);
-                let tail = (self.chapter + 1..self.chapters.len() - 1).map(|n| (n, 0));
-                for (c, byte) in iter::once(head).chain(tail) {
-                    if let Some(index) = self.chapters[c].text[byte..].find(&self.query) {
-                        self.line = get_line(&self.chapters[c].lines, index + byte);
-                        self.chapter = c;
-                        return true;
-                    }
+                ranges.push((chapter, byte, len(chapter), false));
+                ranges.extend((chapter + 1..self.chapters.len()).map(|c| (c, 0, len(c), false)));
+                if self.wrapscan {
+                    ranges.extend((0..chapter).map(|c| (c, 0, len(c), true)));
+                    ranges.push((chapter, 0, byte, true));
                 }
-                false
             }
             Direction::Prev => {
                 let byte = if args.skip { start } else { end };
-                let head = (self.chapter, byte);
-                let tail = (0..self.chapter)
-                    .rev()
-                    .map(|c| (c, self.chapters[c].text.len()));
-                for (c, byte) in iter::once(head).chain(tail) {
-                    if let Some(index) = self.chapters[c].text[..byte].rfind(&self.query) {
-                        self.line = get_line(&self.chapters[c].lines, index);
-                        self.chapter = c;
-                        return true;
-                    }
+                ranges.push((chapter, 0, byte, false));
+                ranges.extend((0..chapter).rev().map(|c| (c, 0, len(c), false)));
+                if self.wrapscan {
+                    let after = chapter + 1..self.chapters.len();
+                    ranges.extend(after.rev().map(|c| (c, 0, len(c), true)));
+                    ranges.push((chapter, byte, len(chapter), true));
                 }
-                false
             }
         }
+        if self.chapter_search {
+            ranges.retain(|r| r.0 == chapter);
+        }
+        for (c, from, to, wrapped) in ranges {
+            let matches = self.matches_in(c, from, to);
+            let found = match args.dir {
+                Direction::Next => matches.first(),
+                Direction::Prev => matches.iter().rfind(|m| m.1 <= to),
+            };
+            if let Some(&(index, _)) = found {
+                self.line = get_line(&self.chapters[c].lines, index);
+                self.chapter = c;
+                if wrapped {
+                    self.message = Some(String::from("search wrapped"));
+                }
+                return true;
+            }
+        }
+        false
     }
 }
 
@@ -297,19 +1496,105 @@ impl Bk<'_> {
 /// read a book
 struct Args {
     #[argh(positional)]
-    path: Option<String>,
+    paths: Vec<String>,
 
     /// print metadata and exit
     #[argh(switch, short = 'm')]
     meta: bool,
 
+    /// the same as --meta
+    #[argh(switch)]
+    info: bool,
+
+    /// print the text and exit
+    #[argh(switch)]
+    dump: bool,
+
+    /// print the version and exit
+    #[argh(switch, short = 'V')]
+    version: bool,
+
+    /// where positions, bookmarks and such are saved
+    #[argh(option)]
+    state_file: Option<String>,
+
+    /// without a path, open the last book instead of picking one
+    #[argh(switch, short = 'l')]
+    last: bool,
+
     /// start with table of contents open
     #[argh(switch, short = 't')]
     toc: bool,
 
+    /// open at chapter N, from 1
+    #[argh(option)]
+    chapter: Option<usize>,
+
+    /// open new books at the cover, not where the text begins
+    #[argh(switch)]
+    front_matter: bool,
+
     /// characters per line
-    #[argh(option, short = 'w', default = "75")]
-    width: u16,
+    #[argh(option, short = 'w')]
+    width: Option<u16>,
+
+    /// justify text
+    #[argh(switch, short = 'j')]
+    justify: bool,
+
+    /// the title of the current section in the top row
+    #[argh(switch)]
+    header: bool,
+
+    /// two columns side by side when the terminal is wide enough
+    #[argh(switch, short = 'c')]
+    columns: bool,
+
+    /// rows per line, eg 1.5 for a blank row after every other line
+    #[argh(option)]
+    line_height: Option<f32>,
+
+    /// lines of the last page to keep at the top when paging
+    #[argh(option)]
+    overlap: Option<usize>,
+
+    /// search with regular expressions
+    #[argh(switch)]
+    regex: bool,
+
+    /// no blank line between paragraphs
+    #[argh(switch)]
+    compact: bool,
+
+    /// spaces to indent the first line of paragraphs
+    #[argh(option)]
+    indent: Option<usize>,
+
+    /// colors: default, dark, light or sepia, then any of
+    /// fg, bg, dim, accent, selection, search, highlight=color, eg
+    /// dark,accent=#ffaf00.
+    /// T cycles through themes split by ;
+    #[argh(option)]
+    theme: Option<String>,
+
+    /// ruby, like furigana: inline in parentheses, or above
+    #[argh(option)]
+    ruby: Option<Ruby>,
+
+    /// punctuation fixes, any of curly or straight quotes, dashes (-- to
+    /// an em dash), spaces (collapse runs) and zero-width (strip them), eg
+    /// curly,dashes
+    #[argh(option)]
+    punct: Option<Punct>,
+
+    /// ignore the book's css
+    #[argh(switch)]
+    no_css: bool,
+
+    /// attributes and colors to add to heading, quote, emphasis,
+    /// strong, link or code, eg heading=bold,#ffaf00;link=cyan,underline
+    #[argh(option)]
+    style: Vec<String>,
 }
 
 struct Props {
@@ -317,27 +1602,110 @@ struct Props {
     byte: usize,
     width: u16,
     toc: bool,
+    // a book not opened before, to begin at the text
+    start: bool,
+    justify: bool,
+    header: bool,
+    columns: bool,
+    line_height: f32,
+    overlap: usize,
+    regex: bool,
+    hlsearch: bool,
+    word: bool,
+    wrapscan: bool,
+    chapter_search: bool,
+    fold: bool,
+    list_results: bool,
+    keys: keys::Keymap,
+    themes: Vec<Theme>,
+    styles: Styles,
+    bookmarks: Vec<(usize, usize, String)>,
+    highlights: Vec<(usize, usize, usize)>,
+    annotations: Vec<(usize, usize, String)>,
+    marks: HashMap<char, (usize, usize)>,
+    named: HashMap<String, (usize, usize)>,
+    seen: Vec<Vec<(usize, usize)>>,
 }
 
 #[derive(Default, Deserialize, Serialize)]
 struct Save {
     last: String,
     files: HashMap<String, (usize, usize)>,
+    // chapter, byte and snippet, by path
+    #[serde(default)]
+    bookmarks: HashMap<String, Vec<(usize, usize, String)>>,
+    // chapter, start and end, by path
+    #[serde(default)]
+    highlights: HashMap<String, Vec<(usize, usize, usize)>>,
+    // chapter, byte and note, by path
+    #[serde(default)]
+    annotations: HashMap<String, Vec<(usize, usize, String)>>,
+    // a to z, to chapter and byte, by path
+    #[serde(default)]
+    marks: HashMap<String, HashMap<char, (usize, usize)>>,
+    // marks named with :mark, to chapter and byte, by path
+    #[serde(default)]
+    named: HashMap<String, HashMap<String, (usize, usize)>>,
+    // start and end of the ranges read of each chapter, by path
+    #[serde(default)]
+    seen: HashMap<String, Vec<Vec<(usize, usize)>>>,
+    #[serde(default)]
+    library: HashMap<String, library::Entry>,
 }
 
 struct State {
     save: Save,
-    save_path: String,
-    path: String,
+    save_path: PathBuf,
     meta: bool,
-    bk: Props,
+    dump: bool,
+    layout: Layout,
+    // each book, and the settings to open it with
+    books: Vec<(String, Props)>,
+}
+
+// books shown to continue reading, before the whole library
+const RECENT: usize = 5;
+
+// a book to open, picked from those read before, the most recent first. the
+// first few are offered before the rest
+fn library(save: &Save, theme: &Theme) -> Result<Option<String>> {
+    let mut books: Vec<_> = save
+        .files
+        .keys()
+        .filter(|p| Path::new(p).exists())
+        .map(|p| (p, save.library.get(p).cloned().unwrap_or_default()))
+        .collect();
+    // saves from before the library only know the last book
+    books.sort_by_key(|(p, e)| (Reverse(e.read), **p != save.last, p.to_string()));
+    if books.is_empty() {
+        return Err(anyhow::anyhow!("no path arg and no books read before"));
+    }
+    let width = terminal::size()?.0 as usize;
+    let lines: Vec<_> = books.iter().map(|(p, e)| e.label(p, width)).collect();
+    if books.len() > RECENT {
+        let mut recent = lines[..RECENT].to_vec();
+        recent.push(String::from("      All books…"));
+        match library::pick("Continue reading", &recent, theme)? {
+            Some(i) if i < RECENT => return Ok(Some(books[i].0.clone())),
+            Some(_) => (),
+            None => return Ok(None),
+        }
+    }
+    let picked = library::pick("Library", &lines, theme)?;
+    Ok(picked.map(|i| books[i].0.clone()))
 }
 
 fn init() -> Result<State> {
-    let save_path = if cfg!(windows) {
-        format!("{}\\bk", env::var("APPDATA")?)
-    } else {
-        format!("{}/.local/share/bk", env::var("HOME")?)
+    let args: Args = argh::from_env();
+    if args.version {
+        println!("bk {}", env!("CARGO_PKG_VERSION"));
+        exit(0);
+    }
+    let config = config::load()?;
+    let config = over(&args, config);
+    let save_path = match args.state_file.as_ref().or(config.save.as_ref()) {
+        Some(path) => PathBuf::from(path),
+        None => config::save_path()?,
     };
     // XXX will silently create a new default save if ron errors but path arg works.
     // revisit if/when stabilizing. ez file format upgrades
@@ -347,78 +1715,245 @@ fn init() -> Result<State> {
             let save: Save = ron::from_str(&s)?;
             Ok(save)
         });
-    let args: Args = argh::from_env();
 
-    let mut path = args.path;
     // abort on path error
-    if path.is_some() {
-        path = Some(
-            fs::canonicalize(path.unwrap())?
-                .to_str()
-                .unwrap()
-                .to_string(),
-        );
-    }
-
-    let (path, chapter, byte) = match (&save, &path) {
-        (Err(_), None) => return Err(anyhow::anyhow!("no path arg and no or invalid save file")),
-        (Err(_), Some(p)) => (p, 0, 0),
-        (Ok(save), None) => {
-            let &(chapter, byte) = save.files.get(&save.last).unwrap();
-            (&save.last, chapter, byte)
-        }
-        (Ok(save), Some(p)) => {
-            if save.files.contains_key(p) {
-                let &(chapter, byte) = save.files.get(p).unwrap();
-                (p, chapter, byte)
-            } else {
-                (p, 0, 0)
-            }
+    let mut paths = Vec::new();
+    for path in &args.paths {
+        paths.push(fs::canonicalize(path)?.to_str().unwrap().to_string());
+    }
+
+    let themes = theme::themes(&config.theme)?;
+    let mut keys = keys::Keymap::default();
+    keys.remap(&config.keys).map_err(anyhow::Error::msg)?;
+    if paths.is_empty() {
+        let save = match &save {
+            Ok(save) => save,
+            Err(_) => return Err(anyhow::anyhow!("no path arg and no or invalid save file")),
+        };
+        let picked = if args.last && save.files.contains_key(&save.last) {
+            Some(save.last.clone())
+        } else {
+            library(save, &themes[0])?
+        };
+        match picked {
+            Some(p) => paths.push(p),
+            None => exit(0),
         }
-    };
+    }
+    let save = save.unwrap_or_default();
+    let mut books = Vec::new();
+    for path in paths {
+        let props = props(&args, &config, &save, &path, themes.clone(), keys.clone())?;
+        books.push((path, props));
+    }
 
     Ok(State {
         save_path,
-        path: path.clone(),
-        save: save.unwrap_or_default(),
-        meta: args.meta,
-        bk: Props {
-            chapter,
-            byte,
-            width: args.width,
-            toc: args.toc,
+        save,
+        meta: args.meta || args.info,
+        dump: args.dump,
+        layout: Layout {
+            blank: !config.compact,
+            indent: config.indent,
+            ruby: match args.ruby {
+                Some(ruby) => ruby,
+                None => config.ruby.parse().map_err(anyhow::Error::msg)?,
+            },
+            punct: match args.punct {
+                Some(punct) => punct,
+                None => config.punct.parse().map_err(anyhow::Error::msg)?,
+            },
+            css: !config.no_css,
         },
+        books,
+    })
+}
+
+// the config, with the flags given instead
+fn over(args: &Args, mut config: config::Config) -> config::Config {
+    config.width = args.width.unwrap_or(config.width);
+    config.line_height = args.line_height.unwrap_or(config.line_height);
+    config.overlap = args.overlap.unwrap_or(config.overlap);
+    config.indent = args.indent.unwrap_or(config.indent);
+    if let Some(theme) = &args.theme {
+        config.theme = theme.clone();
+    }
+    config.front_matter |= args.front_matter;
+    config.justify |= args.justify;
+    config.header |= args.header;
+    config.columns |= args.columns;
+    config.regex |= args.regex;
+    config.compact |= args.compact;
+    config.no_css |= args.no_css;
+    // later styles win
+    config.style.extend(args.style.iter().cloned());
+    config
+}
+
+// the settings from args and the config, and what was saved for the book
+// at path
+fn props(
+    args: &Args,
+    config: &config::Config,
+    save: &Save,
+    path: &str,
+    themes: Vec<Theme>,
+    keys: keys::Keymap,
+) -> Result<Props> {
+    let (chapter, byte) = match args.chapter {
+        Some(n) => (n.saturating_sub(1), 0),
+        None => save.files.get(path).copied().unwrap_or((0, 0)),
+    };
+    Ok(Props {
+        chapter,
+        byte,
+        width: config.width,
+        toc: args.toc,
+        start: !config.front_matter && args.chapter.is_none() && !save.files.contains_key(path),
+        justify: config.justify,
+        header: config.header,
+        columns: config.columns,
+        line_height: config.line_height.max(1.0),
+        overlap: config.overlap,
+        regex: config.regex,
+        hlsearch: config.hlsearch,
+        word: config.word,
+        wrapscan: config.wrapscan,
+        chapter_search: config.chapter_search,
+        fold: config.fold,
+        list_results: config.results,
+        keys,
+        themes,
+        styles: Styles::parse(&config.style.join(";"))?,
+        bookmarks: save.bookmarks.get(path).cloned().unwrap_or_default(),
+        highlights: save.highlights.get(path).cloned().unwrap_or_default(),
+        annotations: save.annotations.get(path).cloned().unwrap_or_default(),
+        marks: save.marks.get(path).cloned().unwrap_or_default(),
+        named: save.named.get(path).cloned().unwrap_or_default(),
+        seen: save.seen.get(path).cloned().unwrap_or_default(),
     })
 }
 
 fn main() {
-    let mut state = init().unwrap_or_else(|e| {
+    let state = init().unwrap_or_else(|e| {
         println!("init error: {}", e);
         exit(1);
     });
-    let epub = epub::Epub::new(&state.path, state.meta).unwrap_or_else(|e| {
-        println!("epub error: {}", e);
-        exit(1);
-    });
-    if state.meta {
-        println!("{}", epub.meta);
+    let State {
+        mut save,
+        save_path,
+        meta,
+        dump,
+        layout,
+        books,
+    } = state;
+    let mut open_books = Vec::new();
+    for (path, props) in books {
+        let book = open(&path, meta, layout).unwrap_or_else(|e| {
+            println!("book error: {}", e);
+            exit(1);
+        });
+        if meta {
+            println!("{}", book.meta);
+            continue;
+        }
+        if dump {
+            for c in &book.chapters {
+                println!("{}", visible(&c.text));
+            }
+            continue;
+        }
+        open_books.push((path, Bk::new(book, props)));
+    }
+    if meta || dump {
         exit(0);
     }
-    let mut bk = Bk::new(epub, state.bk);
-    bk.run().unwrap_or_else(|e| {
-        println!("run error: {}", e);
-        exit(1);
-    });
+    let mut books = open_books;
+    let names: Vec<_> = books.iter().map(|(path, bk)| bk.name(path)).collect();
+    for (i, (_, bk)) in books.iter_mut().enumerate() {
+        bk.books = names.clone();
+        bk.book = i;
+    }
+
+    // each book runs until it quits, or switches to another
+    let mut i = 0;
+    loop {
+        let (path, bk) = &mut books[i];
+        if bk.view.is_none() {
+            bk.view = Some(bk.page());
+        }
+        bk.run(|bk| write(&mut save, &save_path, path, bk))
+            .unwrap_or_else(|e| {
+                println!("run error: {}", e);
+                exit(1);
+            });
+        match bk.switch.take() {
+            Some(next) => i = next,
+            None => break,
+        }
+    }
+    // the last book read goes last
+    let last = books.remove(i);
+    for (path, bk) in books.iter().chain(iter::once(&last)) {
+        write(&mut save, &save_path, path, bk).unwrap_or_else(|e| {
+            println!("error saving state: {}", e);
+            exit(1);
+        });
+    }
+}
 
+// the position and what the reader added, by path
+fn write(save: &mut Save, save_path: &Path, path: &str, bk: &Bk) -> Result<()> {
     let byte = bk.chap().lines[bk.line].0;
-    state
-        .save
-        .files
-        .insert(state.path.clone(), (bk.chapter, byte));
-    state.save.last = state.path;
-    let serialized = ron::to_string(&state.save).unwrap();
-    fs::write(state.save_path, serialized).unwrap_or_else(|e| {
-        println!("error saving state: {}", e);
-        exit(1);
-    });
+    save.files.insert(path.to_string(), (bk.chapter, byte));
+    if bk.bookmarks.is_empty() {
+        save.bookmarks.remove(path);
+    } else {
+        save.bookmarks
+            .insert(path.to_string(), bk.bookmarks.clone());
+    }
+    if bk.highlights.is_empty() {
+        save.highlights.remove(path);
+    } else {
+        save.highlights
+            .insert(path.to_string(), bk.highlights.clone());
+    }
+    if bk.annotations.is_empty() {
+        save.annotations.remove(path);
+    } else {
+        save.annotations
+            .insert(path.to_string(), bk.annotations.clone());
+    }
+    let mut marks = bk.mark.clone();
+    marks.retain(|c, _| c.is_ascii_lowercase());
+    if marks.is_empty() {
+        save.marks.remove(path);
+    } else {
+        save.marks.insert(path.to_string(), marks);
+    }
+    if bk.named.is_empty() {
+        save.named.remove(path);
+    } else {
+        save.named.insert(path.to_string(), bk.named.clone());
+    }
+    save.seen.insert(path.to_string(), bk.seen.clone());
+    let meta = |key: &str| {
+        let value = bk.meta.iter().find_map(|m| m.strip_prefix(key));
+        value.unwrap_or_default().to_string()
+    };
+    let entry = library::Entry {
+        title: meta("title: "),
+        author: meta("creator: "),
+        progress: bk.progress(),
+        read: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs()),
+    };
+    save.library.insert(path.to_string(), entry);
+    save.last = path.to_string();
+    if let Some(dir) = save_path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(save_path, ron::to_string(save)?)?;
+    Ok(())
 }
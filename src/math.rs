@@ -0,0 +1,262 @@
+use roxmltree::Node;
+use std::{iter::Peekable, str::Chars};
+
+use crate::epub::script;
+
+// spaced out, the rest of the operators are kept tight
+const RELATIONS: &str = "=<>≤≥≠≈≡∼≃≅∝→←↔⇒⇐⇔∈∉⊂⊃⊆⊇";
+
+// latex commands for a symbol
+const SYMBOLS: &[(&str, &str)] = &[
+    ("alpha", "α"),
+    ("beta", "β"),
+    ("gamma", "γ"),
+    ("delta", "δ"),
+    ("epsilon", "ϵ"),
+    ("varepsilon", "ε"),
+    ("zeta", "ζ"),
+    ("eta", "η"),
+    ("theta", "θ"),
+    ("vartheta", "ϑ"),
+    ("iota", "ι"),
+    ("kappa", "κ"),
+    ("lambda", "λ"),
+    ("mu", "μ"),
+    ("nu", "ν"),
+    ("xi", "ξ"),
+    ("pi", "π"),
+    ("rho", "ρ"),
+    ("sigma", "σ"),
+    ("tau", "τ"),
+    ("upsilon", "υ"),
+    ("phi", "ϕ"),
+    ("varphi", "φ"),
+    ("chi", "χ"),
+    ("psi", "ψ"),
+    ("omega", "ω"),
+    ("Gamma", "Γ"),
+    ("Delta", "Δ"),
+    ("Theta", "Θ"),
+    ("Lambda", "Λ"),
+    ("Xi", "Ξ"),
+    ("Pi", "Π"),
+    ("Sigma", "Σ"),
+    ("Upsilon", "Υ"),
+    ("Phi", "Φ"),
+    ("Psi", "Ψ"),
+    ("Omega", "Ω"),
+    ("cdot", "·"),
+    ("times", "×"),
+    ("div", "÷"),
+    ("pm", "±"),
+    ("mp", "∓"),
+    ("leq", "≤"),
+    ("le", "≤"),
+    ("geq", "≥"),
+    ("ge", "≥"),
+    ("neq", "≠"),
+    ("ne", "≠"),
+    ("approx", "≈"),
+    ("equiv", "≡"),
+    ("sim", "∼"),
+    ("propto", "∝"),
+    ("infty", "∞"),
+    ("partial", "∂"),
+    ("nabla", "∇"),
+    ("sum", "∑"),
+    ("prod", "∏"),
+    ("int", "∫"),
+    ("oint", "∮"),
+    ("to", "→"),
+    ("rightarrow", "→"),
+    ("leftarrow", "←"),
+    ("Rightarrow", "⇒"),
+    ("Leftarrow", "⇐"),
+    ("iff", "⇔"),
+    ("in", "∈"),
+    ("notin", "∉"),
+    ("subset", "⊂"),
+    ("subseteq", "⊆"),
+    ("cup", "∪"),
+    ("cap", "∩"),
+    ("forall", "∀"),
+    ("exists", "∃"),
+    ("emptyset", "∅"),
+    ("hbar", "ℏ"),
+    ("ell", "ℓ"),
+    ("degree", "°"),
+    ("circ", "∘"),
+    ("ldots", "…"),
+    ("cdots", "⋯"),
+    ("prime", "′"),
+    ("langle", "⟨"),
+    ("rangle", "⟩"),
+];
+
+// mathml as a line of unicode, or its latex if it's only that
+pub fn math(n: Node) -> String {
+    let text = node(n);
+    if !text.trim().is_empty() {
+        return text.trim().to_string();
+    }
+    let tex = n
+        .descendants()
+        .find(|d| {
+            d.has_tag_name("annotation")
+                && d.attribute("encoding").is_some_and(|e| e.contains("tex"))
+        })
+        .and_then(|d| d.text())
+        .or_else(|| n.attribute("alttext"))
+        .unwrap_or_default();
+    latex(tex)
+}
+
+fn node(n: Node) -> String {
+    if n.is_text() {
+        return String::new();
+    }
+    let children: Vec<_> = n.children().filter(|c| c.is_element()).collect();
+    let arg = |i: usize| children.get(i).map_or(String::new(), |&c| node(c));
+    match n.tag_name().name() {
+        "mi" | "mn" | "mtext" | "ms" => text(n),
+        "mo" => operator(&text(n)),
+        "mspace" => String::from(" "),
+        "msup" | "mover" => arg(0) + &script(&arg(1), 0),
+        "msub" | "munder" => arg(0) + &script(&arg(1), 1),
+        "msubsup" | "munderover" => arg(0) + &script(&arg(1), 1) + &script(&arg(2), 0),
+        "mfrac" => format!("{}/{}", group(&arg(0)), group(&arg(1))),
+        "msqrt" => format!(
+            "√{}",
+            group(&children.iter().map(|&c| node(c)).collect::<String>())
+        ),
+        "mroot" => format!("{}√{}", script(&arg(1), 0), group(&arg(0))),
+        "mfenced" => {
+            let open = n.attribute("open").unwrap_or("(");
+            let close = n.attribute("close").unwrap_or(")");
+            let sep = n.attribute("separators").unwrap_or(",").trim();
+            let parts: Vec<_> = children.iter().map(|&c| node(c)).collect();
+            format!("{}{}{}", open, parts.join(&format!("{} ", sep)), close)
+        }
+        "mtable" => {
+            let rows: Vec<_> = children.iter().map(|&c| node(c)).collect();
+            format!("[{}]", rows.join("; "))
+        }
+        "mtr" | "mlabeledtr" => {
+            let cells: Vec<_> = children.iter().map(|&c| node(c)).collect();
+            cells.join("  ")
+        }
+        // the rendering, not the annotations
+        "semantics" => arg(0),
+        "annotation" | "annotation-xml" | "mphantom" => String::new(),
+        _ => children.iter().map(|&c| node(c)).collect(),
+    }
+}
+
+fn text(n: Node) -> String {
+    let text: String = n
+        .descendants()
+        .filter(|d| d.is_text())
+        .filter_map(|d| d.text())
+        .collect();
+    text.trim().to_string()
+}
+
+fn operator(op: &str) -> String {
+    match op {
+        "," | ";" => format!("{} ", op),
+        op if op.chars().count() == 1 && RELATIONS.contains(op) => format!(" {} ", op),
+        op => op.to_string(),
+    }
+}
+
+// parentheses around more than a number or variable
+fn group(s: &str) -> String {
+    let s = s.trim();
+    if s.chars().all(char::is_alphanumeric) || s.starts_with('(') && s.ends_with(')') {
+        s.to_string()
+    } else {
+        format!("({})", s)
+    }
+}
+
+fn latex(s: &str) -> String {
+    let mut chars = s.chars().peekable();
+    let mut out = String::new();
+    while let Some(t) = token(&mut chars) {
+        out.push_str(&t);
+    }
+    out.trim().to_string()
+}
+
+// the next command, group or char, converted. None at the end of input or of
+// a group
+fn token(chars: &mut Peekable<Chars>) -> Option<String> {
+    let c = chars.next()?;
+    let s = match c {
+        '}' => return None,
+        '{' => {
+            let mut s = String::new();
+            while let Some(t) = token(chars) {
+                s.push_str(&t);
+            }
+            s
+        }
+        '^' => script(&argument(chars), 0),
+        '_' => script(&argument(chars), 1),
+        // math mode ignores spaces
+        c if c.is_whitespace() => String::new(),
+        '\\' => command(chars),
+        c if RELATIONS.contains(c) => format!(" {} ", c),
+        c => c.to_string(),
+    };
+    Some(s)
+}
+
+fn argument(chars: &mut Peekable<Chars>) -> String {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+    token(chars).unwrap_or_default()
+}
+
+fn command(chars: &mut Peekable<Chars>) -> String {
+    let mut name = String::new();
+    while let Some(c) = chars.next_if(char::is_ascii_alphabetic) {
+        name.push(c);
+    }
+    if name.is_empty() {
+        // escaped chars and spacing like \, or \{
+        return match chars.next() {
+            Some(',' | ';' | ':' | ' ' | '!') | None => String::from(" "),
+            Some(c) => c.to_string(),
+        };
+    }
+    match name.as_str() {
+        "frac" | "dfrac" | "tfrac" => {
+            let a = argument(chars);
+            let b = argument(chars);
+            format!("{}/{}", group(&a), group(&b))
+        }
+        "sqrt" => {
+            let mut index = String::new();
+            if chars.next_if_eq(&'[').is_some() {
+                while let Some(c) = chars.next_if(|&c| c != ']') {
+                    index.push(c);
+                }
+                chars.next();
+            }
+            let root = if index.is_empty() {
+                String::new()
+            } else {
+                script(&index, 0)
+            };
+            format!("{}√{}", root, group(&argument(chars)))
+        }
+        // the delimiter follows
+        "left" | "right" | "big" | "Big" | "bigg" | "Bigg" => String::new(),
+        "quad" | "qquad" => String::from(" "),
+        "text" | "mathrm" | "mathit" | "mathbf" | "mathsf" | "operatorname" => argument(chars),
+        name => SYMBOLS
+            .iter()
+            .find(|s| s.0 == name)
+            .map_or_else(|| name.to_string(), |s| operator(s.1)),
+    }
+}
@@ -0,0 +1,173 @@
+use anyhow::{anyhow, Result};
+use crossterm::style::Attribute;
+use pulldown_cmark::{CodeBlockKind, Event, HeadingLevel, Options, Parser, Tag, TagEnd};
+use std::{fs, path::Path};
+
+use crate::{
+    charset,
+    epub::{Chapter, Layout},
+    Book,
+};
+
+pub fn open(path: &str, meta: bool, layout: Layout) -> Result<Book> {
+    let text = charset::decode(&fs::read(path)?);
+    let (front, text) = front_matter(&text);
+
+    let mut book = Book::default();
+    for line in front.lines() {
+        if let Some((key, value)) = line.split_once(':') {
+            let key = match key.trim() {
+                "author" => "creator",
+                key => key,
+            };
+            let value = value.trim().trim_matches('"');
+            if !value.is_empty() {
+                book.meta.push_str(&format!("{}: {}\n", key, value));
+            }
+        }
+    }
+    if !book.meta.starts_with("title:") && !book.meta.contains("\ntitle:") {
+        let stem = Path::new(path).file_stem().unwrap().to_string_lossy();
+        book.meta.insert_str(0, &format!("title: {}\n", stem));
+    }
+    if meta {
+        return Ok(book);
+    }
+
+    let mut c = Chapter::new(String::from("0"));
+    c.layout = layout;
+    let mut heading: Option<(usize, String)> = None;
+    let mut link = None;
+    let mut code = false;
+    for event in Parser::new_ext(text, Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TABLES) {
+        match event {
+            Event::Start(Tag::Heading { level, .. }) => {
+                // level 1 and 2 headings are the toc
+                if level <= HeadingLevel::H2 {
+                    if !c.text.trim().is_empty() {
+                        book.chapters.push(c);
+                    }
+                    c = Chapter::new(String::new());
+                    c.layout = layout;
+                }
+                c.text.push('\n');
+                heading = Some((c.text.len(), String::new()));
+                c.set(Attribute::Bold);
+            }
+            Event::End(TagEnd::Heading(level)) => {
+                c.unset(Attribute::Bold, Attribute::NormalIntensity);
+                c.text.push('\n');
+                let (start, title) = heading.take().unwrap();
+                c.frag.push((slug(&title), start));
+                if level <= HeadingLevel::H2 {
+                    c.title = title;
+                }
+            }
+            Event::Start(Tag::Paragraph) => c.paragraph_start(true),
+            Event::End(TagEnd::Paragraph) => c.text.push('\n'),
+            Event::Start(Tag::BlockQuote(_)) => {
+                c.text.push('\n');
+                c.indent_start(2, true);
+            }
+            Event::End(TagEnd::BlockQuote(_)) => {
+                c.indent_end();
+                c.text.push('\n');
+            }
+            Event::Start(Tag::Table(_)) => c.table_start(),
+            Event::End(TagEnd::Table) => c.table_end(),
+            Event::End(TagEnd::TableHead) => c.row_end(true),
+            Event::End(TagEnd::TableRow) => c.row_end(false),
+            Event::Start(Tag::TableCell) => c.cell_start(),
+            Event::End(TagEnd::TableCell) => c.cell_end(),
+            Event::Start(Tag::List(start)) => c.list_start(start),
+            Event::End(TagEnd::List(_)) => c.list_end(),
+            Event::Start(Tag::Item) => c.item_start(),
+            Event::End(TagEnd::Item) => c.item_end(),
+            Event::Start(Tag::Emphasis) => c.set(Attribute::Italic),
+            Event::End(TagEnd::Emphasis) => c.unset(Attribute::Italic, Attribute::NoItalic),
+            Event::Start(Tag::Strong) => c.set(Attribute::Bold),
+            Event::End(TagEnd::Strong) => c.unset(Attribute::Bold, Attribute::NormalIntensity),
+            Event::Start(Tag::Strikethrough) => c.set(Attribute::CrossedOut),
+            Event::End(TagEnd::Strikethrough) => {
+                c.unset(Attribute::CrossedOut, Attribute::NotCrossedOut)
+            }
+            Event::Start(Tag::CodeBlock(kind)) => {
+                code = true;
+                let lang = match &kind {
+                    CodeBlockKind::Fenced(info) => info.split_whitespace().next(),
+                    CodeBlockKind::Indented => None,
+                };
+                c.pre_start(lang);
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                code = false;
+                c.pre_end();
+            }
+            Event::Start(Tag::Link { dest_url, .. }) => {
+                c.set(Attribute::Underlined);
+                link = Some((c.text.len(), dest_url.to_string()));
+            }
+            Event::End(TagEnd::Link) => {
+                c.unset(Attribute::Underlined, Attribute::NoUnderline);
+                let (start, url) = link.take().unwrap();
+                c.links.push((start, c.text.len(), url));
+            }
+            Event::Start(Tag::Image { .. }) => c.image_start(None),
+            Event::End(TagEnd::Image) => c.image_end(),
+            Event::Code(code) => {
+                c.set(Attribute::Dim);
+                c.text.push_str(&code);
+                c.unset(Attribute::Dim, Attribute::NormalIntensity);
+            }
+            Event::Text(text) => {
+                if let Some((_, heading)) = &mut heading {
+                    heading.push_str(&text);
+                }
+                if code {
+                    c.pre_text(&text);
+                } else {
+                    c.push_text(&text);
+                }
+            }
+            Event::SoftBreak => c.text.push(' '),
+            Event::HardBreak => c.text.push('\n'),
+            Event::Rule => c.scene_break(),
+            _ => (),
+        }
+    }
+    if !c.text.trim().is_empty() {
+        book.chapters.push(c);
+    }
+    if book.chapters.is_empty() {
+        return Err(anyhow!("no readable text"));
+    }
+    for (i, c) in book.chapters.iter_mut().enumerate() {
+        for (id, pos) in c.frag.drain(..) {
+            book.links.insert(format!("#{}", id), (i, pos));
+        }
+    }
+    Ok(book)
+}
+
+// yaml style front matter between --- lines
+fn front_matter(text: &str) -> (&str, &str) {
+    if let Some(rest) = text.strip_prefix("---\n") {
+        if let Some(n) = rest.find("\n---\n") {
+            return (&rest[..n], &rest[n + 5..]);
+        }
+    }
+    ("", text)
+}
+
+// github style heading anchors
+fn slug(s: &str) -> String {
+    s.trim()
+        .to_lowercase()
+        .chars()
+        .filter_map(|c| match c {
+            ' ' => Some('-'),
+            c if c.is_alphanumeric() || c == '-' || c == '_' => Some(c),
+            _ => None,
+        })
+        .collect()
+}
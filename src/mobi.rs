@@ -0,0 +1,807 @@
+use anyhow::{anyhow, Result};
+use roxmltree::Document;
+use std::{collections::HashMap, fs};
+
+use crate::{
+    epub::{render, Chapter, Layout, Source},
+    html::{attr, tags, xhtml},
+    Book,
+};
+
+const NULL: usize = 0xffff_ffff;
+
+// https://wiki.mobileread.com/wiki/MOBI
+pub struct Pdb {
+    data: Vec<u8>,
+    offsets: Vec<usize>,
+}
+
+impl Pdb {
+    pub fn new(data: Vec<u8>) -> Result<Self> {
+        if data.len() < 78 {
+            return Err(anyhow!("not a palm database"));
+        }
+        let count = u16_at(&data, 76);
+        let offsets = (0..count)
+            .map(|i| u32_at(&data, 78 + i * 8))
+            .chain(std::iter::once(data.len()))
+            .collect::<Vec<_>>();
+        if offsets.windows(2).any(|w| w[0] > w[1]) {
+            return Err(anyhow!("corrupt record list"));
+        }
+        Ok(Pdb { data, offsets })
+    }
+    pub fn len(&self) -> usize {
+        self.offsets.len() - 1
+    }
+    pub fn record(&self, n: usize) -> &[u8] {
+        match (self.offsets.get(n), self.offsets.get(n + 1)) {
+            (Some(&start), Some(&end)) => &self.data[start..end],
+            _ => &[],
+        }
+    }
+}
+
+// out of bounds reads are zero rather than a panic on a truncated book
+pub fn u16_at(b: &[u8], off: usize) -> usize {
+    b.get(off..off + 2)
+        .map_or(0, |b| u16::from_be_bytes([b[0], b[1]]) as usize)
+}
+pub fn u32_at(b: &[u8], off: usize) -> usize {
+    b.get(off..off + 4)
+        .map_or(0, |b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize)
+}
+
+// the first record holds the palmdoc header, the mobi header and exth
+pub struct Header {
+    pub start: usize,
+    version: usize,
+    compression: usize,
+    text_len: usize,
+    text_records: usize,
+    encoding: usize,
+    huff: (usize, usize),
+    extra_flags: usize,
+    // kf8 indices, relative to start
+    fdst: Option<usize>,
+    ncx: Option<usize>,
+    frag: Option<usize>,
+    skel: Option<usize>,
+    pub exth: Vec<(usize, Vec<u8>)>,
+    pub name: String,
+}
+
+impl Header {
+    pub fn new(pdb: &Pdb, start: usize) -> Result<Self> {
+        let r = pdb.record(start);
+        if r.len() < 24 || &r[16..20] != b"MOBI" {
+            return Err(anyhow!("missing mobi header"));
+        }
+        if u16_at(r, 12) != 0 {
+            return Err(anyhow!("this book is DRM-protected"));
+        }
+        let len = u32_at(r, 20);
+        let extra_flags = if len >= 0xe4 { u16_at(r, 0xf2) } else { 0 };
+        let mut exth = Vec::new();
+        if u32_at(r, 0x80) & 0x40 != 0 {
+            let mut pos = 16 + len;
+            if r.get(pos..pos + 4) == Some(b"EXTH") {
+                let count = u32_at(r, pos + 8);
+                pos += 12;
+                for _ in 0..count {
+                    let kind = u32_at(r, pos);
+                    let size = u32_at(r, pos + 4);
+                    // a record is at least its kind and size
+                    if size < 8 {
+                        break;
+                    }
+                    let data = r
+                        .get(pos + 8..pos + size)
+                        .ok_or_else(|| anyhow!("corrupt exth record"))?;
+                    exth.push((kind, data.to_vec()));
+                    pos += size;
+                }
+            }
+        }
+        let encoding = u32_at(r, 0x1c);
+        let name_start = u32_at(r, 0x54);
+        let name_len = u32_at(r, 0x58);
+        let name = r
+            .get(name_start..name_start + name_len)
+            .ok_or_else(|| anyhow!("corrupt book name"))?;
+        let name = decode(name, encoding);
+        let record = |off: usize| {
+            Some(off)
+                .filter(|&off| len + 16 >= off + 4)
+                .map(|off| u32_at(r, off))
+                .filter(|&n| n != NULL)
+        };
+        Ok(Header {
+            start,
+            version: u32_at(r, 0x24),
+            compression: u16_at(r, 0),
+            text_len: u32_at(r, 4),
+            text_records: u16_at(r, 8),
+            encoding,
+            huff: (u32_at(r, 0x70), u32_at(r, 0x74)),
+            extra_flags,
+            fdst: record(0xd0),
+            ncx: record(0xf4),
+            frag: record(0xf8),
+            skel: record(0xfc),
+            exth,
+            name,
+        })
+    }
+    pub fn exth(&self, kind: usize) -> Option<&[u8]> {
+        self.exth
+            .iter()
+            .find(|(k, _)| *k == kind)
+            .map(|(_, v)| v.as_slice())
+    }
+    fn is_kf8(&self) -> bool {
+        self.version >= 8
+    }
+    pub fn decode(&self, b: &[u8]) -> String {
+        decode(b, self.encoding)
+    }
+    pub fn meta(&self) -> String {
+        let mut meta = format!("title: {}\n", self.name);
+        for &(kind, name) in &[
+            (100, "creator"),
+            (101, "publisher"),
+            (103, "description"),
+            (105, "subject"),
+            (106, "date"),
+            (524, "language"),
+        ] {
+            for (_, v) in self.exth.iter().filter(|(k, _)| *k == kind) {
+                meta.push_str(&format!("{}: {}\n", name, self.decode(v)));
+            }
+        }
+        meta
+    }
+    // concatenated and decompressed text records
+    pub fn text(&self, pdb: &Pdb) -> Result<Vec<u8>> {
+        let huff = match self.compression {
+            1 | 2 => None,
+            17480 => {
+                let (start, count) = self.huff;
+                Some(Huff::new(pdb, self.start + start, count)?)
+            }
+            n => return Err(anyhow!("unknown compression {}", n)),
+        };
+        let mut text = Vec::with_capacity(self.text_len);
+        for n in self.start + 1..=self.start + self.text_records {
+            let r = trim_record(pdb.record(n), self.extra_flags);
+            match &huff {
+                None if self.compression == 1 => text.extend_from_slice(r),
+                None => palmdoc(r, &mut text),
+                Some(huff) => huff.unpack(r, &mut text, 0),
+            }
+        }
+        text.truncate(self.text_len);
+        Ok(text)
+    }
+}
+
+fn decode(b: &[u8], encoding: usize) -> String {
+    if encoding == 1252 {
+        b.iter().map(|&c| cp1252(c)).collect()
+    } else {
+        String::from_utf8_lossy(b).into_owned()
+    }
+}
+
+fn cp1252(c: u8) -> char {
+    const HIGH: [char; 32] = [
+        '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8d}', 'Ž',
+        '\u{8f}', '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9d}',
+        'ž', 'Ÿ',
+    ];
+    match c {
+        0x80..=0x9f => HIGH[c as usize - 0x80],
+        _ => c as char,
+    }
+}
+
+// text records may have trailing entries, which are sized from the end
+fn trim_record(r: &[u8], flags: usize) -> &[u8] {
+    let mut end = r.len();
+    for bit in 1..16 {
+        if flags & (1 << bit) != 0 {
+            let mut size = 0;
+            let mut shift = 0;
+            for &b in r[end.saturating_sub(4)..end].iter().rev() {
+                size |= (b as usize & 0x7f) << shift;
+                shift += 7;
+                if b & 0x80 != 0 {
+                    break;
+                }
+            }
+            end = end.saturating_sub(size);
+        }
+    }
+    if flags & 1 != 0 && end > 0 {
+        end = end.saturating_sub((r[end - 1] as usize & 3) + 1);
+    }
+    &r[..end]
+}
+
+fn palmdoc(r: &[u8], out: &mut Vec<u8>) {
+    let mut i = 0;
+    while i < r.len() {
+        let c = r[i];
+        i += 1;
+        match c {
+            0x01..=0x08 => {
+                let end = (i + c as usize).min(r.len());
+                out.extend_from_slice(&r[i..end]);
+                i = end;
+            }
+            0x80..=0xbf if i < r.len() => {
+                let pair = (c as usize) << 8 | r[i] as usize;
+                i += 1;
+                let dist = (pair >> 3) & 0x7ff;
+                let len = (pair & 7) + 3;
+                if dist == 0 || dist > out.len() {
+                    continue;
+                }
+                // the source may overlap the output
+                for _ in 0..len {
+                    out.push(out[out.len() - dist]);
+                }
+            }
+            0xc0..=0xff => {
+                out.push(b' ');
+                out.push(c ^ 0x80);
+            }
+            _ => out.push(c),
+        }
+    }
+}
+
+// https://wiki.mobileread.com/wiki/Huff/CDIC
+struct Huff {
+    dict1: Vec<(u32, bool, u64)>,
+    mincode: Vec<u64>,
+    maxcode: Vec<u64>,
+    dict: Vec<(Vec<u8>, bool)>,
+}
+
+impl Huff {
+    fn new(pdb: &Pdb, start: usize, count: usize) -> Result<Self> {
+        let huff = pdb.record(start);
+        if count == 0 || !huff.starts_with(b"HUFF") {
+            return Err(anyhow!("missing huff record"));
+        }
+        let off1 = u32_at(huff, 8);
+        let off2 = u32_at(huff, 12);
+        let dict1 = (0..256)
+            .map(|i| {
+                let v = u32_at(huff, off1 + i * 4) as u64;
+                let len = (v & 0x1f) as u32;
+                let max = ((v >> 8) + 1).wrapping_shl(32 - len).wrapping_sub(1);
+                (len, v & 0x80 != 0, max)
+            })
+            .collect();
+        let mut mincode = vec![0];
+        let mut maxcode = vec![0];
+        for len in 1..=32 {
+            let min = u32_at(huff, off2 + (len - 1) * 8) as u64;
+            let max = u32_at(huff, off2 + (len - 1) * 8 + 4) as u64;
+            mincode.push(min << (32 - len));
+            maxcode.push(((max + 1) << (32 - len)) - 1);
+        }
+
+        let mut dict = Vec::new();
+        for n in start + 1..start + count {
+            let cdic = pdb.record(n);
+            if !cdic.starts_with(b"CDIC") {
+                return Err(anyhow!("missing cdic record"));
+            }
+            let phrases = u32_at(cdic, 8);
+            let bits = u32_at(cdic, 12);
+            let n = 1usize
+                .checked_shl(bits as u32)
+                .ok_or_else(|| anyhow!("corrupt cdic record"))?
+                .min(phrases.saturating_sub(dict.len()));
+            for i in 0..n {
+                let off = u16_at(cdic, 16 + i * 2);
+                let blen = u16_at(cdic, 16 + off);
+                let slice = cdic
+                    .get(18 + off..18 + off + (blen & 0x7fff))
+                    .ok_or_else(|| anyhow!("corrupt cdic record"))?;
+                dict.push((slice.to_vec(), blen & 0x8000 != 0));
+            }
+        }
+        Ok(Huff {
+            dict1,
+            mincode,
+            maxcode,
+            dict,
+        })
+    }
+    fn unpack(&self, data: &[u8], out: &mut Vec<u8>, depth: usize) {
+        // phrases may themselves be compressed; a sane book won't nest deeply
+        if depth > 32 {
+            return;
+        }
+        let mut bitsleft = data.len() as i64 * 8;
+        let mut padded = data.to_vec();
+        padded.extend_from_slice(&[0; 8]);
+        let word =
+            |pos: usize| (u32_at(&padded, pos) as u64) << 32 | u32_at(&padded, pos + 4) as u64;
+        let mut pos = 0;
+        let mut x = word(pos);
+        let mut n: i64 = 32;
+        loop {
+            if n <= 0 {
+                pos += 4;
+                x = word(pos);
+                n += 32;
+            }
+            let code = (x >> n) & 0xffff_ffff;
+            let (mut len, term, mut max) = self.dict1[(code >> 24) as usize];
+            if !term {
+                while (len as usize) < 32 && code < self.mincode[len as usize] {
+                    len += 1;
+                }
+                max = self.maxcode[len as usize];
+            }
+            n -= len as i64;
+            bitsleft -= len as i64;
+            if bitsleft < 0 || len == 0 {
+                break;
+            }
+            let r = (max.wrapping_sub(code) >> (32 - len)) as usize;
+            match self.dict.get(r) {
+                Some((slice, true)) => out.extend_from_slice(slice),
+                Some((slice, false)) => self.unpack(slice, out, depth + 1),
+                None => break,
+            }
+        }
+    }
+}
+
+fn header(pdb: &Pdb) -> Result<Header> {
+    let header = Header::new(pdb, 0)?;
+    // joint files carry a kf8 copy of the book after a boundary record
+    if let Some(boundary) = header.exth(121).map(|b| u32_at(b, 0)) {
+        if !header.is_kf8() && boundary < pdb.len() {
+            return Header::new(pdb, boundary);
+        }
+    }
+    Ok(header)
+}
+
+// the text split into the html of each part, and the toc
+fn parts(pdb: &Pdb, header: &Header) -> Result<(Vec<String>, Toc)> {
+    let text = header.text(pdb)?;
+    // the toc is a nicety, so don't fail the book over it
+    let ncx = header
+        .ncx
+        .and_then(|n| index(pdb, header, header.start + n).ok())
+        .unwrap_or_default();
+    if header.is_kf8() {
+        kf8(pdb, header, &text, &ncx)
+    } else {
+        Ok(mobi7(header, &text, &ncx))
+    }
+}
+
+// the markup of a part, read again for :source
+pub fn source(path: &str, part: usize) -> Result<String> {
+    let pdb = Pdb::new(fs::read(path)?)?;
+    let (parts, _) = parts(&pdb, &header(&pdb)?)?;
+    let part = parts.get(part).ok_or_else(|| anyhow!("no part {}", part))?;
+    Ok(xhtml(part))
+}
+
+pub fn open(path: &str, meta: bool, layout: Layout) -> Result<Book> {
+    let pdb = Pdb::new(fs::read(path)?)?;
+    let header = header(&pdb)?;
+    let mut book = Book {
+        meta: header.meta(),
+        ..Default::default()
+    };
+    if meta {
+        return Ok(book);
+    }
+
+    let (parts, toc) = parts(&pdb, &header)?;
+    let guide = parts
+        .first()
+        .and_then(|part| tags(part, "reference").find(|tag| attr(tag, "type") == Some("toc")))
+        .and_then(|tag| attr(tag, "href"))
+        .map(String::from);
+
+    let mut titles = Vec::new();
+    for (i, part) in parts.iter().enumerate() {
+        let xml = xhtml(part);
+        let doc = match Document::parse(&xml) {
+            Ok(doc) => doc,
+            Err(_) => continue,
+        };
+        let body = doc.root_element().last_element_child().unwrap();
+        let mut c = Chapter::new(String::new());
+        c.layout = layout;
+        render(body, &mut c);
+        if c.text.trim().is_empty() {
+            continue;
+        }
+        c.source = Some(Source::Mobi(path.to_string(), i));
+        for (id, pos) in c.frag.drain(..) {
+            book.links
+                .insert(format!("#{}", id), (book.chapters.len(), pos));
+        }
+        titles.push(None);
+        book.chapters.push(c);
+    }
+    if book.chapters.is_empty() {
+        return Err(anyhow!("no readable text"));
+    }
+
+    for (label, url) in toc {
+        if let Some(&(chapter, _)) = book.links.get(&url) {
+            titles[chapter].get_or_insert(label);
+        }
+    }
+    // otherwise label chapters with the link text of the toc page
+    if let Some(&(toc, _)) = guide.and_then(|url| book.links.get(&url)) {
+        let c = &book.chapters[toc];
+        for (start, end, url) in &c.links {
+            if let Some(&(chapter, _)) = book.links.get(url) {
+                if titles[chapter].is_none() {
+                    titles[chapter] = Some(c.text[*start..*end].trim().to_string());
+                }
+            }
+        }
+    }
+    for (i, (c, title)) in book.chapters.iter_mut().zip(titles).enumerate() {
+        c.title = title.unwrap_or_else(|| i.to_string());
+    }
+    Ok(book)
+}
+
+type Toc = Vec<(String, String)>;
+
+// mobi 6 books are a single html file split by pagebreaks
+fn mobi7(header: &Header, text: &[u8], ncx: &Index) -> (Vec<String>, Toc) {
+    let toc: Vec<_> = (0..ncx.entries.len())
+        .filter_map(|i| Some((ncx.label(i)?, ncx.tag(i, 1, 0)?)))
+        .collect();
+    let html = header.decode(&anchors(text, toc.iter().map(|&(_, pos)| pos)));
+    let parts = split_ignore_case(&html, "<mbp:pagebreak")
+        .into_iter()
+        .enumerate()
+        .map(|(i, part)| match part.find('>') {
+            // drop the remainder of the pagebreak tag
+            Some(n) if i > 0 => part[n + 1..].to_string(),
+            _ => part.to_string(),
+        })
+        .collect();
+    let toc = toc
+        .into_iter()
+        .map(|(label, pos)| (label, format!("#filepos{}", pos)))
+        .collect();
+    (parts, toc)
+}
+
+// kf8 files are stored as skeletons with fragments to be inserted into them
+// https://wiki.mobileread.com/wiki/KF8
+fn kf8(pdb: &Pdb, header: &Header, text: &[u8], ncx: &Index) -> Result<(Vec<String>, Toc)> {
+    let corrupt = || anyhow!("corrupt kf8 index");
+    // the first flow is the html, the rest are css and svg
+    let flow = match header.fdst {
+        Some(n) => {
+            let r = pdb.record(header.start + n);
+            if !r.starts_with(b"FDST") || u32_at(r, 8) == 0 {
+                return Err(anyhow!("missing fdst record"));
+            }
+            text.get(u32_at(r, 12)..u32_at(r, 16).min(text.len()))
+                .ok_or_else(corrupt)?
+        }
+        None => text,
+    };
+    let skel = index(pdb, header, header.start + header.skel.ok_or_else(corrupt)?)?;
+    let frag = index(pdb, header, header.start + header.frag.ok_or_else(corrupt)?)?;
+
+    let mut parts = Vec::new();
+    // the part and offset of each fragment
+    let mut starts = Vec::new();
+    let mut fid = 0;
+    for i in 0..skel.entries.len() {
+        let count = skel.tag(i, 1, 0).ok_or_else(corrupt)?;
+        let pos = skel.tag(i, 6, 0).ok_or_else(corrupt)?;
+        let mut base = pos + skel.tag(i, 6, 1).ok_or_else(corrupt)?;
+        let mut part = flow.get(pos..base).ok_or_else(corrupt)?.to_vec();
+        let first = starts.len();
+        for _ in 0..count {
+            let insert: usize = frag.entries.get(fid).ok_or_else(corrupt)?.0.parse()?;
+            let len = frag.tag(fid, 6, 1).ok_or_else(corrupt)?;
+            let at = insert.saturating_sub(pos).min(part.len());
+            for (_, start) in &mut starts[first..] {
+                if *start >= at {
+                    *start += len;
+                }
+            }
+            let fragment = flow.get(base..base + len).ok_or_else(corrupt)?;
+            part.splice(at..at, fragment.iter().copied());
+            starts.push((parts.len(), at));
+            base += len;
+            fid += 1;
+        }
+        parts.push(part);
+    }
+
+    let mut targets: Vec<_> = (0..ncx.entries.len())
+        .filter_map(|i| Some((ncx.tag(i, 6, 0)?, ncx.tag(i, 6, 1)?)))
+        .collect();
+    kindle(flow, |fid, off| {
+        targets.push((fid, off));
+        None
+    });
+    targets.sort_unstable();
+    targets.dedup();
+    let mut anchors = vec![Vec::new(); parts.len()];
+    for &(fid, off) in &targets {
+        if let Some(&(n, start)) = starts.get(fid) {
+            let part = &parts[n];
+            let mut at = (start + off).min(part.len());
+            // keep out of the middle of a tag
+            let lt = part[..at].iter().rposition(|&c| c == b'<');
+            let gt = part[..at].iter().rposition(|&c| c == b'>');
+            if let Some(lt) = lt.filter(|&lt| gt < Some(lt)) {
+                at = lt;
+            }
+            anchors[n].push((at, format!("<a id=\"pos{}-{}\"></a>", fid, off)));
+        }
+    }
+    let parts = parts
+        .into_iter()
+        .zip(anchors)
+        .map(|(mut part, mut anchors)| {
+            anchors.sort_by_key(|&(at, _)| std::cmp::Reverse(at));
+            for (at, anchor) in anchors {
+                part.splice(at..at, anchor.into_bytes());
+            }
+            let part =
+                kindle(&part, |fid, off| Some(format!("#pos{}-{}", fid, off))).unwrap_or(part);
+            header.decode(&part)
+        })
+        .collect();
+
+    let toc = (0..ncx.entries.len())
+        .filter_map(|i| {
+            let url = format!("#pos{}-{}", ncx.tag(i, 6, 0)?, ncx.tag(i, 6, 1)?);
+            Some((ncx.label(i)?, url))
+        })
+        .collect();
+    Ok((parts, toc))
+}
+
+// call f with each kindle:pos:fid:XXXX:off:XXXXXXXXXX link, replacing it with the result
+fn kindle<F>(text: &[u8], mut f: F) -> Option<Vec<u8>>
+where
+    F: FnMut(usize, usize) -> Option<String>,
+{
+    const PREFIX: &[u8] = b"kindle:pos:fid:";
+    let base32 = |b: &[u8]| usize::from_str_radix(std::str::from_utf8(b).ok()?, 32).ok();
+    let mut out = Vec::with_capacity(text.len());
+    let mut i = 0;
+    let mut replaced = false;
+    while i < text.len() {
+        if text[i..].starts_with(PREFIX) {
+            let j = i + PREFIX.len();
+            let link = text.get(j..j + 19).filter(|l| &l[4..9] == b":off:");
+            if let Some((fid, off)) = link.and_then(|l| Some((base32(&l[..4])?, base32(&l[9..])?)))
+            {
+                if let Some(s) = f(fid, off) {
+                    out.extend_from_slice(s.as_bytes());
+                    replaced = true;
+                    i = j + 19;
+                    continue;
+                }
+            }
+        }
+        out.push(text[i]);
+        i += 1;
+    }
+    if replaced {
+        Some(out)
+    } else {
+        None
+    }
+}
+
+// https://wiki.mobileread.com/wiki/MOBI#Index_Records
+#[derive(Default)]
+struct Index {
+    entries: Vec<(String, HashMap<u8, Vec<usize>>)>,
+    cncx: HashMap<usize, String>,
+}
+
+impl Index {
+    fn tag(&self, entry: usize, tag: u8, n: usize) -> Option<usize> {
+        self.entries[entry].1.get(&tag)?.get(n).copied()
+    }
+    fn label(&self, entry: usize) -> Option<String> {
+        self.cncx.get(&self.tag(entry, 3, 0)?).cloned()
+    }
+}
+
+fn index(pdb: &Pdb, header: &Header, n: usize) -> Result<Index> {
+    let r = pdb.record(n);
+    if !r.starts_with(b"INDX") {
+        return Err(anyhow!("missing index record"));
+    }
+    let records = u32_at(r, 24);
+    let cncx = u32_at(r, 52);
+    let tagx = &r[u32_at(r, 180).min(r.len())..];
+    if !tagx.starts_with(b"TAGX") {
+        return Err(anyhow!("missing tagx section"));
+    }
+    let control = u32_at(tagx, 8);
+    let tags: Vec<_> = (12..u32_at(tagx, 4).min(tagx.len() - 3))
+        .step_by(4)
+        .map(|i| [tagx[i], tagx[i + 1], tagx[i + 2], tagx[i + 3]])
+        .collect();
+
+    let mut index = Index::default();
+    for i in n + 1..=n + records {
+        let r = pdb.record(i);
+        let idxt = u32_at(r, 20);
+        let mut pos: Vec<_> = (0..u32_at(r, 24))
+            .map(|j| u16_at(r, idxt + 4 + j * 2))
+            .collect();
+        pos.push(idxt);
+        for w in pos.windows(2) {
+            let entry = r.get(w[0]..w[1]).unwrap_or_default();
+            let len = entry.first().map_or(0, |&n| n as usize + 1);
+            let name = match entry.get(1..len) {
+                Some(name) => String::from_utf8_lossy(name).into_owned(),
+                None => return Err(anyhow!("corrupt index entry")),
+            };
+            index
+                .entries
+                .push((name, tag_map(control, &tags, &entry[len..])));
+        }
+    }
+    let mut base = 0;
+    for i in n + records + 1..=n + records + cncx {
+        let r = pdb.record(i);
+        let mut pos = 0;
+        while pos < r.len() {
+            let (len, used) = decint(&r[pos..]);
+            let end = (pos + used + len).min(r.len());
+            index
+                .cncx
+                .insert(base + pos, header.decode(&r[pos + used..end]));
+            pos = end;
+        }
+        base += 0x10000;
+    }
+    Ok(index)
+}
+
+fn tag_map(control: usize, tags: &[[u8; 4]], data: &[u8]) -> HashMap<u8, Vec<usize>> {
+    let (control, mut data) = data.split_at(control.min(data.len()));
+    let mut byte = 0;
+    let mut pending = Vec::new();
+    for &[tag, values, mask, eof] in tags {
+        if eof == 1 {
+            byte += 1;
+            continue;
+        }
+        let value = control.get(byte).copied().unwrap_or(0) & mask;
+        if value == 0 {
+            continue;
+        }
+        // all bits set in a multi bit mask means the byte length follows
+        if value == mask && mask.count_ones() > 1 {
+            let (bytes, used) = decint(data);
+            data = &data[used..];
+            pending.push((tag, None, bytes));
+        } else {
+            let count = (value >> mask.trailing_zeros()) as usize * values as usize;
+            pending.push((tag, Some(count), 0));
+        }
+    }
+    let mut map = HashMap::new();
+    for (tag, count, bytes) in pending {
+        let mut v = Vec::new();
+        let mut used = 0;
+        while !data.is_empty() && count.map_or(used < bytes, |count| v.len() < count) {
+            let (n, len) = decint(data);
+            data = &data[len..];
+            used += len;
+            v.push(n);
+        }
+        map.insert(tag, v);
+    }
+    map
+}
+
+fn decint(b: &[u8]) -> (usize, usize) {
+    let mut n = 0;
+    for (i, &c) in b.iter().enumerate() {
+        n = n << 7 | (c & 0x7f) as usize;
+        if c & 0x80 != 0 {
+            return (n, i + 1);
+        }
+    }
+    (n, b.len())
+}
+
+// links address byte offsets in the text, so insert an anchor at each target
+fn anchors<I>(text: &[u8], extra: I) -> Vec<u8>
+where
+    I: Iterator<Item = usize>,
+{
+    let mut targets: Vec<_> = extra.collect();
+    filepos(text, |pos| {
+        targets.push(pos);
+        None
+    });
+    targets.sort_unstable();
+    targets.dedup();
+    let mut text = text.to_vec();
+    let len = text.len();
+    for &pos in targets.iter().rev().filter(|&&pos| pos <= len) {
+        let anchor = format!("<a id=\"filepos{}\"></a>", pos);
+        text.splice(pos..pos, anchor.into_bytes());
+    }
+    filepos(&text, |pos| Some(format!("href=\"#filepos{}\"", pos))).unwrap_or(text)
+}
+
+// call f with each filepos attribute, replacing it with the result
+fn filepos<F>(text: &[u8], mut f: F) -> Option<Vec<u8>>
+where
+    F: FnMut(usize) -> Option<String>,
+{
+    let quote = |c: &u8| *c == b'"' || *c == b'\'';
+    let mut out = Vec::with_capacity(text.len());
+    let mut i = 0;
+    let mut replaced = false;
+    while i < text.len() {
+        if text[i..].starts_with(b"filepos=") {
+            let mut j = i + 8;
+            j += text[j..].iter().take_while(|c| quote(c)).count();
+            let digits = text[j..].iter().take_while(|c| c.is_ascii_digit()).count();
+            if digits > 0 {
+                let pos = std::str::from_utf8(&text[j..j + digits])
+                    .unwrap()
+                    .parse()
+                    .unwrap_or(0);
+                j += digits;
+                j += text[j..].iter().take_while(|c| quote(c)).count();
+                if let Some(s) = f(pos) {
+                    out.extend_from_slice(s.as_bytes());
+                    replaced = true;
+                    i = j;
+                    continue;
+                }
+            }
+        }
+        out.push(text[i]);
+        i += 1;
+    }
+    if replaced {
+        Some(out)
+    } else {
+        None
+    }
+}
+
+fn split_ignore_case<'a>(s: &'a str, pat: &str) -> Vec<&'a str> {
+    let lower = s.to_ascii_lowercase();
+    let mut parts = Vec::new();
+    let mut start = 0;
+    for (i, _) in lower.match_indices(pat) {
+        parts.push(&s[start..i]);
+        start = i + pat.len();
+    }
+    parts.push(&s[start..]);
+    parts
+}
@@ -0,0 +1,111 @@
+use anyhow::{anyhow, Result};
+use pdf_extract::{decode_text_string, output_doc_page, Document, Object, PlainTextOutput};
+use std::{collections::HashMap, path::Path};
+
+use crate::{epub::Chapter, Book};
+
+// only the text layer, scans without ocr have nothing to show.
+// a chapter for each top level outline entry, or for each page without one
+pub fn open(path: &str, meta: bool) -> Result<Book> {
+    let mut doc = Document::load(path)?;
+    if doc.is_encrypted() {
+        // most are only restricted, with an empty user password
+        doc.decrypt("")
+            .map_err(|_| anyhow!("encrypted with a password"))?;
+    }
+
+    let mut book = Book::default();
+    let info = doc
+        .trailer
+        .get(b"Info")
+        .and_then(Object::as_reference)
+        .and_then(|id| doc.get_dictionary(id));
+    if let Ok(info) = info {
+        for (key, name) in &[
+            (&b"Title"[..], "title"),
+            (b"Author", "creator"),
+            (b"Subject", "description"),
+            (b"Keywords", "subject"),
+            (b"CreationDate", "date"),
+        ] {
+            if let Ok(text) = info.get(key).and_then(decode_text_string) {
+                let text = text.trim();
+                if !text.is_empty() {
+                    book.meta.push_str(&format!("{}: {}\n", name, text));
+                }
+            }
+        }
+    }
+    if !book.meta.starts_with("title:") {
+        let stem = Path::new(path).file_stem().unwrap();
+        let title = format!("title: {}\n", stem.to_string_lossy());
+        book.meta.insert_str(0, &title);
+    }
+    let pages = doc.get_pages();
+    book.meta.push_str(&format!("pages: {}\n", pages.len()));
+    if meta {
+        return Ok(book);
+    }
+
+    // page -> title
+    let mut outline: HashMap<u32, String> = HashMap::new();
+    if let Ok(toc) = doc.get_toc() {
+        for entry in toc.toc.into_iter().filter(|t| t.level == 1) {
+            outline.entry(entry.page as u32).or_insert(entry.title);
+        }
+    }
+
+    let by_page = outline.is_empty();
+    let mut c = Chapter::new(String::from("front"));
+    for &n in pages.keys() {
+        if by_page || outline.contains_key(&n) {
+            let title = outline.remove(&n).unwrap_or_else(|| format!("page {}", n));
+            push(&mut book.chapters, c);
+            c = Chapter::new(title);
+        }
+        let mut text = String::new();
+        // a bad page shouldn't lose the book
+        if output_doc_page(&doc, &mut PlainTextOutput::new(&mut text), n).is_err() {
+            continue;
+        }
+        c.text.push_str(&reflow(&text));
+    }
+    push(&mut book.chapters, c);
+
+    if book.chapters.is_empty() {
+        return Err(anyhow!("no text layer"));
+    }
+    Ok(book)
+}
+
+fn push(chapters: &mut Vec<Chapter>, c: Chapter) {
+    if !c.text.trim().is_empty() {
+        chapters.push(c);
+    }
+}
+
+// join the hard wrapped lines of each paragraph. a blank line, from a
+// larger vertical gap, ends a paragraph
+pub fn reflow(text: &str) -> String {
+    let mut out = String::new();
+    for para in text.split("\n\n") {
+        let mut lines = para.lines().map(str::trim).filter(|l| !l.is_empty());
+        let first = match lines.next() {
+            Some(l) => l,
+            None => continue,
+        };
+        out.push('\n');
+        out.push_str(first);
+        for line in lines {
+            // rejoin words broken across lines
+            if out.ends_with('-') && line.starts_with(char::is_lowercase) {
+                out.pop();
+            } else {
+                out.push(' ');
+            }
+            out.push_str(line);
+        }
+        out.push('\n');
+    }
+    out
+}
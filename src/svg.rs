@@ -0,0 +1,13 @@
+use resvg::{tiny_skia, usvg};
+
+// an svg as png, at its own size, for the image subsystem. text is left out,
+// having no fonts, but it's in the alt text
+pub fn rasterize(data: &[u8]) -> Option<Vec<u8>> {
+    let tree = usvg::Tree::from_data(data, &usvg::Options::default()).ok()?;
+    let size = tree.size().to_int_size();
+    let mut pixmap = tiny_skia::Pixmap::new(size.width(), size.height())?;
+    // transparency would be black
+    pixmap.fill(tiny_skia::Color::WHITE);
+    resvg::render(&tree, tiny_skia::Transform::default(), &mut pixmap.as_mut());
+    pixmap.encode_png().ok()
+}
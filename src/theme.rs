@@ -0,0 +1,251 @@
+use anyhow::{anyhow, Result};
+use crossterm::style::{Attribute, Attributes, Color, SetBackgroundColor, SetForegroundColor};
+use std::convert::TryFrom;
+
+// Color::Reset leaves it to the terminal. without selection and search
+// colors those are shown reversed, without dim it's the dim attribute
+#[derive(Clone, Copy)]
+pub struct Theme {
+    pub fg: Color,
+    pub bg: Color,
+    // images, toc entries the book left out, code that isn't highlighted
+    pub dim: Color,
+    // headings
+    pub accent: Color,
+    // the selected toc entry
+    pub selection: Color,
+    // search matches
+    pub search: Color,
+    // text the reader highlighted
+    pub highlight: Color,
+}
+
+const fn rgb(hex: u32) -> Color {
+    Color::Rgb {
+        r: (hex >> 16) as u8,
+        g: (hex >> 8) as u8,
+        b: hex as u8,
+    }
+}
+
+pub const THEMES: [(&str, Theme); 4] = [
+    (
+        "default",
+        Theme {
+            fg: Color::Reset,
+            bg: Color::Reset,
+            dim: Color::Reset,
+            accent: Color::Reset,
+            selection: Color::Reset,
+            search: Color::Reset,
+            highlight: Color::DarkYellow,
+        },
+    ),
+    (
+        "dark",
+        Theme {
+            fg: rgb(0xd0d0d0),
+            bg: rgb(0x1c1c1c),
+            dim: rgb(0x808080),
+            accent: rgb(0x87afd7),
+            selection: rgb(0x3a3a3a),
+            search: rgb(0x5f5f00),
+            highlight: rgb(0x005f5f),
+        },
+    ),
+    (
+        "light",
+        Theme {
+            fg: rgb(0x1c1c1c),
+            bg: rgb(0xfafafa),
+            dim: rgb(0x8a8a8a),
+            accent: rgb(0x005f87),
+            selection: rgb(0xdadada),
+            search: rgb(0xffdf5f),
+            highlight: rgb(0xafd7ff),
+        },
+    ),
+    (
+        "sepia",
+        Theme {
+            fg: rgb(0x5b4636),
+            bg: rgb(0xf4ecd8),
+            dim: rgb(0xa08c74),
+            accent: rgb(0x8b4513),
+            selection: rgb(0xe0d2b4),
+            search: rgb(0xe8c37a),
+            highlight: rgb(0xc9dbb2),
+        },
+    ),
+];
+
+impl Theme {
+    // a name, then colors to change, eg "dark,accent=#ffaf00" or
+    // "fg=black,bg=#fdf6e3"
+    pub fn parse(spec: &str) -> Result<Theme> {
+        let mut theme = THEMES[0].1;
+        for part in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (key, value) = match part.split_once('=') {
+                Some(kv) => kv,
+                None => {
+                    theme = THEMES
+                        .iter()
+                        .find(|t| t.0 == part)
+                        .ok_or_else(|| anyhow!("unknown theme: {}", part))?
+                        .1;
+                    continue;
+                }
+            };
+            let slot = match key.trim() {
+                "fg" => &mut theme.fg,
+                "bg" => &mut theme.bg,
+                "dim" => &mut theme.dim,
+                "accent" => &mut theme.accent,
+                "selection" => &mut theme.selection,
+                "search" => &mut theme.search,
+                "highlight" => &mut theme.highlight,
+                key => return Err(anyhow!("unknown theme color: {}", key)),
+            };
+            *slot = color(value.trim()).ok_or_else(|| anyhow!("bad color: {}", value))?;
+        }
+        Ok(theme)
+    }
+    // clear the styles, back to the theme's colors
+    pub fn reset(&self) -> String {
+        format!(
+            "{}{}{}",
+            Attribute::Reset,
+            SetForegroundColor(self.fg),
+            SetBackgroundColor(self.bg)
+        )
+    }
+    // an attribute, or the color standing in for it. Reverse is a search match
+    pub fn attr(&self, attr: Attribute) -> String {
+        match attr {
+            Attribute::Dim if self.dim != Color::Reset => SetForegroundColor(self.dim).to_string(),
+            Attribute::NormalIntensity if self.dim != Color::Reset => {
+                format!("{}{}", attr, SetForegroundColor(self.fg))
+            }
+            Attribute::Reverse if self.search != Color::Reset => {
+                SetBackgroundColor(self.search).to_string()
+            }
+            Attribute::NoReverse if self.search != Color::Reset => {
+                SetBackgroundColor(self.bg).to_string()
+            }
+            _ => attr.to_string(),
+        }
+    }
+    // the background of selected or highlighted text
+    pub fn paint(&self, selected: bool) -> String {
+        match (selected, self.selection) {
+            (true, Color::Reset) => Attribute::Reverse.to_string(),
+            (true, color) => SetBackgroundColor(color).to_string(),
+            (false, _) => SetBackgroundColor(self.highlight).to_string(),
+        }
+    }
+    pub fn select(&self, line: &str) -> String {
+        format!("{}{}{}", self.paint(true), line, self.reset())
+    }
+}
+
+// added to how a kind of element is drawn
+#[derive(Clone, Copy, Default)]
+pub struct Style {
+    pub attrs: Attributes,
+    pub color: Option<Color>,
+}
+
+impl Style {
+    pub fn is_set(&self) -> bool {
+        !self.attrs.is_empty() || self.color.is_some()
+    }
+}
+
+#[derive(Default)]
+pub struct Styles {
+    pub heading: Style,
+    pub quote: Style,
+    pub emphasis: Style,
+    pub strong: Style,
+    pub link: Style,
+    pub code: Style,
+}
+
+impl Styles {
+    // elements and their attributes and colors, eg
+    // "heading=bold,#ffaf00;link=cyan;quote=italic,dim"
+    pub fn parse(spec: &str) -> Result<Styles> {
+        let mut styles = Styles::default();
+        for rule in spec.split(';').map(str::trim).filter(|r| !r.is_empty()) {
+            let (name, values) = rule
+                .split_once('=')
+                .ok_or_else(|| anyhow!("style without =: {}", rule))?;
+            let style = match name.trim() {
+                "heading" | "h" => &mut styles.heading,
+                "quote" | "blockquote" => &mut styles.quote,
+                "emphasis" | "em" => &mut styles.emphasis,
+                "strong" => &mut styles.strong,
+                "link" | "a" => &mut styles.link,
+                "code" | "pre" => &mut styles.code,
+                name => return Err(anyhow!("unknown element: {}", name)),
+            };
+            for value in values.split(',').map(str::trim) {
+                let attr = match value {
+                    "bold" => Attribute::Bold,
+                    "dim" => Attribute::Dim,
+                    "italic" => Attribute::Italic,
+                    "underline" => Attribute::Underlined,
+                    "strike" => Attribute::CrossedOut,
+                    value => {
+                        style.color =
+                            Some(color(value).ok_or_else(|| anyhow!("bad style: {}", value))?);
+                        continue;
+                    }
+                };
+                style.attrs.set(attr);
+            }
+        }
+        Ok(styles)
+    }
+}
+
+// for T to cycle through: those given, split by ;, or the one given then the
+// other named themes
+pub fn themes(spec: &str) -> Result<Vec<Theme>> {
+    let specs: Vec<_> = spec
+        .split(';')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .collect();
+    let mut themes = specs
+        .iter()
+        .map(|s| Theme::parse(s))
+        .collect::<Result<Vec<_>>>()?;
+    match specs[..] {
+        [] => themes.extend(THEMES.iter().map(|t| t.1)),
+        [spec] => {
+            let name = spec.split(',').next().unwrap().trim();
+            themes.extend(THEMES.iter().filter(|t| t.0 != name).map(|t| t.1));
+        }
+        _ => (),
+    }
+    Ok(themes)
+}
+
+// a name crossterm knows, or #rgb or #rrggbb
+fn color(s: &str) -> Option<Color> {
+    if let Some(hex) = s.strip_prefix('#') {
+        let n = u32::from_str_radix(hex, 16).ok()?;
+        return match hex.len() {
+            6 => Some(rgb(n)),
+            3 => Some(rgb((n >> 8 & 0xf) * 0x110000
+                + (n >> 4 & 0xf) * 0x1100
+                + (n & 0xf) * 0x11)),
+            _ => None,
+        };
+    }
+    match s {
+        "default" => Some(Color::Reset),
+        s => Color::try_from(s).ok(),
+    }
+}
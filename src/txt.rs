@@ -0,0 +1,187 @@
+use anyhow::{anyhow, Result};
+use crossterm::style::Attribute;
+use std::{fs, iter, path::Path};
+
+use crate::{
+    charset,
+    epub::{Chapter, Layout},
+    Book,
+};
+
+pub fn open(path: &str, meta: bool, layout: Layout) -> Result<Book> {
+    let text = charset::decode(&fs::read(path)?);
+    let (head, text) = gutenberg(&text);
+
+    let mut book = Book::default();
+    for line in head.lines() {
+        for &(key, name) in &[
+            ("Title:", "title"),
+            ("Author:", "creator"),
+            ("Language:", "language"),
+            ("Release Date:", "date"),
+        ] {
+            if let Some(value) = line.strip_prefix(key) {
+                book.meta.push_str(&format!("{}: {}\n", name, value.trim()));
+            }
+        }
+    }
+    if book.meta.is_empty() {
+        let stem = Path::new(path).file_stem().unwrap().to_string_lossy();
+        book.meta = format!("title: {}\n", stem);
+    }
+    if meta {
+        return Ok(book);
+    }
+
+    // each paragraph, and whether blank lines come before and after it
+    let mut paragraphs = Vec::new();
+    let mut p = Vec::new();
+    let mut blank = false;
+    let mut after_blank = false;
+    for line in text.lines() {
+        if line.trim().is_empty() {
+            if !p.is_empty() {
+                paragraphs.push((p, after_blank));
+                p = Vec::new();
+            }
+            blank = true;
+        } else {
+            if p.is_empty() {
+                after_blank = blank;
+            }
+            p.push(line.trim_end());
+        }
+    }
+    if !p.is_empty() {
+        paragraphs.push((p, false));
+    }
+
+    // fall back to scene breaks if there are no headings
+    let headings = paragraphs.iter().any(|(p, alone)| is_heading(p, *alone));
+    let mut c = Chapter::new(String::from("0"));
+    c.layout = layout;
+    for (lines, alone) in paragraphs {
+        let p: Vec<_> = lines.iter().map(|line| line.trim_start()).collect();
+        let heading = headings && is_heading(&p, alone);
+        if heading || (!headings && is_break(&p)) {
+            if !c.text.trim().is_empty() {
+                book.chapters.push(c);
+            }
+            let title = if heading {
+                p.join(" ")
+            } else {
+                book.chapters.len().to_string()
+            };
+            c = Chapter::new(title);
+            c.layout = layout;
+            if !heading {
+                continue;
+            }
+        }
+        if is_break(&p) {
+            c.scene_break();
+            continue;
+        }
+        if !heading && is_verse(&lines) {
+            c.stanza_start();
+            c.verse_start();
+            // keep the indents past the shallowest line
+            let margin = lines.iter().map(|line| indent(line)).min().unwrap();
+            for line in &lines {
+                let cols = indent(line) - margin;
+                c.text.extend(iter::repeat_n('\u{a0}', cols));
+                c.push_text(line.trim_start());
+                c.text.push('\n');
+            }
+            c.verse_end();
+            continue;
+        }
+        c.paragraph_start(!heading);
+        if heading {
+            c.set(Attribute::Bold);
+            c.push_text(&p.join("\n"));
+            c.unset(Attribute::Bold, Attribute::NormalIntensity);
+        } else {
+            c.push_text(&p.join(" "));
+        }
+        c.text.push('\n');
+    }
+    if !c.text.trim().is_empty() {
+        book.chapters.push(c);
+    }
+    if book.chapters.is_empty() {
+        return Err(anyhow!("no readable text"));
+    }
+    Ok(book)
+}
+
+// split off the project gutenberg header and license
+fn gutenberg(text: &str) -> (&str, &str) {
+    let start = match text.find("*** START OF") {
+        Some(n) => n,
+        None => return ("", text),
+    };
+    let head = &text[..start];
+    let text = &text[start..];
+    let text = text.find('\n').map_or("", |n| &text[n + 1..]);
+    let text = text.find("*** END OF").map_or(text, |n| &text[..n]);
+    (head, text)
+}
+
+fn indent(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+// hard wrapped prose fills its lines, verse is indented or short
+fn is_verse(p: &[&str]) -> bool {
+    p.len() > 1 && (p.iter().all(|line| indent(line) > 0) || p.iter().all(|line| line.len() < 48))
+}
+
+// a short paragraph starting with a word like chapter and then its number
+// or nothing, a roman numeral, or a line in capitals. alone is whether blank
+// lines come before and after the paragraph, as a line in capitals needs
+fn is_heading(p: &[&str], alone: bool) -> bool {
+    let line = p[0].trim_start();
+    if p.len() > 3 || line.len() > 60 {
+        return false;
+    }
+    let mut words = line.split_whitespace();
+    let word = words
+        .next()
+        .unwrap()
+        .trim_end_matches(|c: char| c.is_ascii_punctuation())
+        .to_lowercase();
+    let keyword = matches!(
+        word.as_str(),
+        "chapter" | "book" | "part" | "prologue" | "epilogue" | "preface" | "introduction"
+    );
+    // not "part of me" or "book a table"
+    let numbered = match (words.next(), words.next()) {
+        (None, _) => true,
+        (Some(n), rest) => {
+            let bare = n.trim_end_matches(|c: char| c.is_ascii_punctuation());
+            let digits = !bare.is_empty() && bare.chars().all(|c| c.is_ascii_digit());
+            // a lone I is more often the pronoun, "part I wanted"
+            let roman =
+                is_roman(&bare.to_uppercase()) && (bare != "I" || n != bare || rest.is_none());
+            digits || roman
+        }
+    };
+    let letters = line.chars().filter(|c| c.is_alphabetic()).count();
+    // a shouted "NO!" isn't one
+    let shout = line
+        .trim_end_matches(|c: char| !c.is_alphanumeric() && c != '!' && c != '?')
+        .ends_with(['!', '?']);
+    let capitals = letters >= 2 && !shout && !line.chars().any(char::is_lowercase);
+    (keyword && numbered)
+        || is_roman(line.trim_end_matches('.'))
+        || (p.len() == 1 && alone && capitals)
+}
+
+fn is_roman(s: &str) -> bool {
+    !s.is_empty() && s.len() < 8 && s.chars().all(|c| "IVXLC".contains(c))
+}
+
+fn is_break(p: &[&str]) -> bool {
+    p.len() == 1 && p[0].matches('*').count() >= 3 && p[0].chars().all(|c| c == '*' || c == ' ')
+}
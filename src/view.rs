@@ -1,18 +1,26 @@
 use crossterm::{
-    event::{
-        KeyCode::{self, *},
-        MouseEvent,
-    },
-    style::Attribute,
+    cursor::{MoveRight, MoveToColumn},
+    event::{KeyCode::*, MouseEvent},
+    style::{Attribute, Color, SetForegroundColor},
 };
-use std::cmp::{min, Ordering};
+use std::{
+    cmp::{max, min, Ordering, Reverse},
+    iter, mem,
+};
+use unicode_segmentation::UnicodeSegmentation;
 use unicode_width::UnicodeWidthChar;
 
-use crate::{get_line, Bk, Direction, SearchArgs};
+use crate::{
+    copy, get_line, grapheme_width, img,
+    keys::{Action, Key, Mode},
+    text_width,
+    theme::Style,
+    visible, wrap, Bk, Direction, SearchArgs, GAP,
+};
 
 pub trait View {
     fn render(&self, bk: &Bk) -> Vec<String>;
-    fn on_key(&self, bk: &mut Bk, kc: KeyCode);
+    fn on_key(&self, bk: &mut Bk, key: Key);
     fn on_mouse(&self, _: &mut Bk, _: MouseEvent) {}
     fn on_resize(&self, _: &mut Bk) {}
 }
@@ -20,8 +28,8 @@ pub trait View {
 // TODO render something useful?
 struct Mark;
 impl View for Mark {
-    fn on_key(&self, bk: &mut Bk, kc: KeyCode) {
-        if let Char(c) = kc {
+    fn on_key(&self, bk: &mut Bk, key: Key) {
+        if let (Char(c), false) = (key.code, key.ctrl) {
             bk.mark(c)
         }
         bk.view = Some(&Page)
@@ -33,11 +41,9 @@ impl View for Mark {
 
 struct Jump;
 impl View for Jump {
-    fn on_key(&self, bk: &mut Bk, kc: KeyCode) {
-        if let Char(c) = kc {
-            if let Some(&pos) = bk.mark.get(&c) {
-                bk.jump(pos);
-            }
+    fn on_key(&self, bk: &mut Bk, key: Key) {
+        if let (Char(c), false) = (key.code, key.ctrl) {
+            bk.jump_mark(c);
         }
         bk.view = Some(&Page);
     }
@@ -48,8 +54,8 @@ impl View for Jump {
 
 struct Metadata;
 impl View for Metadata {
-    fn on_key(&self, bk: &mut Bk, _: KeyCode) {
-        bk.view = Some(&Page);
+    fn on_key(&self, bk: &mut Bk, _: Key) {
+        bk.view = Some(bk.page());
     }
     fn render(&self, bk: &Bk) -> Vec<String> {
         let lines: Vec<usize> = bk.chapters.iter().map(|c| c.lines.len()).collect();
@@ -57,10 +63,12 @@ impl View for Metadata {
         let total = lines.iter().sum::<usize>();
         let progress = current as f32 / total as f32 * 100.0;
 
-        let pages = lines[bk.chapter] / bk.rows;
-        let page = bk.line / bk.rows;
+        let pages = lines[bk.chapter] / bk.page_rows();
+        let page = bk.line / bk.page_rows();
 
+        let (book_page, book_pages) = bk.page_number();
         let mut vec = vec![
+            format!("page: {}/{}", book_page + 1, book_pages),
             format!("chapter: {}/{}", page, pages),
             format!("total: {:.0}%", progress),
             String::new(),
@@ -71,59 +79,200 @@ impl View for Metadata {
 }
 
 struct Help;
+impl Help {
+    fn start(&self, bk: &mut Bk) {
+        bk.help = 0;
+        bk.view = Some(&Help);
+    }
+    // the keys of each view as they are bound, under its title
+    fn lines(bk: &Bk) -> Vec<String> {
+        // keys right aligned, then what they do
+        let indent = |line: &str| format!("{:26}{}", "", line);
+        let mut arr = vec![String::new()];
+        for (mode, groups) in &bk.keys.modes {
+            arr.push(format!("{:>24}", mode.title()));
+            arr.push(String::new());
+            for (bindings, note) in groups {
+                for (action, keys) in bindings.iter().filter(|(_, keys)| !keys.is_empty()) {
+                    let keys: Vec<_> = keys.iter().map(Key::name).collect();
+                    let mut help = action.help().lines();
+                    arr.push(format!("{:>24}  {}", keys.join(" "), help.next().unwrap()));
+                    arr.extend(help.map(indent));
+                }
+                arr.extend(note.lines().map(indent));
+                arr.push(String::new());
+            }
+        }
+        arr
+    }
+    fn scroll(&self, bk: &mut Bk, top: usize) {
+        let rows = Help::lines(bk).len();
+        bk.help = min(top, rows.saturating_sub(bk.rows));
+    }
+}
 impl View for Help {
-    fn on_key(&self, bk: &mut Bk, _: KeyCode) {
-        bk.view = Some(&Page);
+    fn on_mouse(&self, bk: &mut Bk, e: MouseEvent) {
+        match e {
+            MouseEvent::ScrollDown(_, _, _) => self.scroll(bk, bk.help + 3),
+            MouseEvent::ScrollUp(_, _, _) => self.scroll(bk, bk.help.saturating_sub(3)),
+            _ => (),
+        }
     }
-    fn render(&self, _: &Bk) -> Vec<String> {
-        let text = r#"
-                   Esc q  Quit
-                      Fn  Help
-                     Tab  Table of Contents
-                       i  Progress and Metadata
-
-PageDown Right Space f l  Page Down
-         PageUp Left b h  Page Up
-                       d  Half Page Down
-                       u  Half Page Up
-                  Down j  Line Down
-                    Up k  Line Up
-                  Home g  Chapter Start
-                   End G  Chapter End
-                       [  Previous Chapter
-                       ]  Next Chapter
-
-                       /  Search Forward
-                       ?  Search Backward
-                       n  Repeat search forward
-                       N  Repeat search backward
-                      mx  Set mark x
-                      'x  Jump to mark x
-                   "#;
-
-        text.lines().map(String::from).collect()
+    fn on_key(&self, bk: &mut Bk, key: Key) {
+        let top = bk.help;
+        match bk.keys.action(Mode::Help, key) {
+            Some(Action::LineDown) => self.scroll(bk, top + 1),
+            Some(Action::LineUp) => self.scroll(bk, top.saturating_sub(1)),
+            Some(Action::HalfDown) => self.scroll(bk, top + bk.rows / 2),
+            Some(Action::HalfUp) => self.scroll(bk, top.saturating_sub(bk.rows / 2)),
+            Some(Action::NextPage) => self.scroll(bk, top + bk.rows),
+            Some(Action::PrevPage) => self.scroll(bk, top.saturating_sub(bk.rows)),
+            Some(Action::Top) => self.scroll(bk, 0),
+            Some(Action::Bottom) => self.scroll(bk, usize::MAX),
+            // any other key closes it, as it always has
+            _ => bk.view = Some(bk.page()),
+        }
+    }
+    fn render(&self, bk: &Bk) -> Vec<String> {
+        Help::lines(bk)
+            .into_iter()
+            .skip(bk.help)
+            .take(bk.rows)
+            .collect()
     }
 }
 
+// the toc as a tree, deeper levels start collapsed
 pub struct Nav;
 impl Nav {
+    // select the entry for the current position, and show it
+    pub fn start(&self, bk: &mut Bk) {
+        bk.mark('\'');
+        bk.toc_filter = None;
+        bk.toc_index = bk.toc_entry().unwrap_or(0);
+        let mut i = bk.toc_index;
+        while let Some(parent) = self.parent(bk, i) {
+            bk.expanded.insert(parent);
+            i = parent;
+        }
+        self.cursor(bk);
+        bk.view = Some(&Nav);
+    }
+    // indices of entries whose ancestors are all expanded, or of those
+    // matching the filter, best first
+    fn visible(&self, bk: &Bk) -> Vec<usize> {
+        if let Some(filter) = bk.toc_filter.as_deref().filter(|f| !f.is_empty()) {
+            let mut scored: Vec<_> = (0..bk.toc.len())
+                .filter_map(|i| Some((fuzzy(filter, &bk.toc[i].title)?, i)))
+                .collect();
+            scored.sort_by_key(|&(score, _)| Reverse(score));
+            return scored.into_iter().map(|(_, i)| i).collect();
+        }
+        let mut v = Vec::new();
+        let mut hide = usize::MAX;
+        for (i, e) in bk.toc.iter().enumerate() {
+            if e.level > hide {
+                continue;
+            }
+            v.push(i);
+            hide = if bk.expanded.contains(&i) {
+                usize::MAX
+            } else {
+                e.level
+            };
+        }
+        v
+    }
+    fn parent(&self, bk: &Bk, i: usize) -> Option<usize> {
+        let level = bk.toc[i].level;
+        bk.toc[..i].iter().rposition(|e| e.level < level)
+    }
+    // how much of entry i was read, up to the next entry in its chapter
+    fn read(&self, bk: &Bk, i: usize) -> f32 {
+        let e = &bk.toc[i];
+        let end = bk
+            .toc
+            .get(i + 1)
+            .filter(|n| n.chapter == e.chapter && n.byte > e.byte)
+            .map_or(bk.chapters[e.chapter].text.len(), |n| n.byte);
+        // the bytes of the ranges seen that fall in the entry
+        let seen: usize = bk.seen[e.chapter]
+            .iter()
+            .map(|&(start, stop)| min(stop, end).saturating_sub(max(start, e.byte)))
+            .sum();
+        if seen >= end - e.byte {
+            return 1.0;
+        }
+        seen as f32 / (end - e.byte) as f32
+    }
+    fn has_children(&self, bk: &Bk, i: usize) -> bool {
+        bk.toc.get(i + 1).is_some_and(|e| e.level > bk.toc[i].level)
+    }
     fn prev(&self, bk: &mut Bk, n: usize) {
-        bk.chapter = bk.chapter.saturating_sub(n);
+        let visible = self.visible(bk);
+        if visible.is_empty() {
+            return;
+        }
+        let pos = visible.iter().position(|&i| i == bk.toc_index).unwrap_or(0);
+        bk.toc_index = visible[pos.saturating_sub(n)];
         self.cursor(bk);
     }
     fn next(&self, bk: &mut Bk, n: usize) {
-        bk.chapter = min(bk.chapters.len() - 1, bk.chapter + n);
+        let visible = self.visible(bk);
+        if visible.is_empty() {
+            return;
+        }
+        let pos = visible.iter().position(|&i| i == bk.toc_index).unwrap_or(0);
+        bk.toc_index = visible[min(visible.len() - 1, pos + n)];
         self.cursor(bk);
     }
     fn cursor(&self, bk: &mut Bk) {
-        bk.cursor = min(bk.rows / 2, bk.chapter);
+        let visible = self.visible(bk);
+        let pos = visible.iter().position(|&i| i == bk.toc_index).unwrap_or(0);
+        bk.cursor = min(bk.rows / 2, pos);
+    }
+    // select the best match for the filter, or without one the entry
+    // selected before filtering
+    fn filter(&self, bk: &mut Bk) {
+        if bk.toc_filter.as_deref().is_none_or(str::is_empty) {
+            bk.toc_index = bk.toc_unfiltered;
+        } else if let Some(&i) = self.visible(bk).first() {
+            bk.toc_index = i;
+        }
+        self.cursor(bk);
+    }
+    fn open(&self, bk: &mut Bk) {
+        bk.toc_filter = None;
+        let e = &bk.toc[bk.toc_index];
+        let (chapter, byte) = (e.chapter, e.byte);
+        bk.chapter = chapter;
+        bk.line = get_line(&bk.chap().lines, byte);
+        bk.cursor = 0;
+        bk.view = Some(bk.page());
+    }
+    fn expand(&self, bk: &mut Bk) {
+        let i = bk.toc_index;
+        if self.has_children(bk, i) && !bk.expanded.contains(&i) {
+            bk.expanded.insert(i);
+        } else {
+            self.open(bk);
+        }
+    }
+    fn collapse(&self, bk: &mut Bk) {
+        let i = bk.toc_index;
+        if !bk.expanded.remove(&i) {
+            if let Some(parent) = self.parent(bk, i) {
+                bk.toc_index = parent;
+            }
+        }
+        self.cursor(bk);
     }
     fn click(&self, bk: &mut Bk, row: usize) {
-        let start = bk.chapter - bk.cursor;
-        if start + row < bk.chapters.len() {
-            bk.chapter = start + row;
-            bk.line = 0;
-            bk.view = Some(&Page);
+        let visible = self.visible(bk);
+        let pos = visible.iter().position(|&i| i == bk.toc_index).unwrap_or(0);
+        if let Some(&i) = visible.get(pos - bk.cursor + row) {
+            bk.toc_index = i;
+            self.open(bk);
         }
     }
 }
@@ -139,76 +288,939 @@ impl View for Nav {
             _ => (),
         }
     }
-    fn on_key(&self, bk: &mut Bk, kc: KeyCode) {
-        match kc {
-            Esc | Tab | Left | Char('h') | Char('q') => {
-                bk.jump_reset();
+    fn on_key(&self, bk: &mut Bk, key: Key) {
+        let empty = self.visible(bk).is_empty();
+        if bk.toc_filter.is_some() {
+            match bk.keys.action(Mode::TocFilter, key) {
+                Some(Action::Close) => {
+                    bk.toc_filter = None;
+                    self.cursor(bk);
+                }
+                Some(Action::Open) if !empty => self.open(bk),
+                Some(Action::LineDown) => self.next(bk, 1),
+                Some(Action::LineUp) => self.prev(bk, 1),
+                Some(_) => (),
+                None => {
+                    let filter = bk.toc_filter.as_mut().unwrap();
+                    match key.code {
+                        Backspace => {
+                            if filter.pop().is_none() {
+                                bk.toc_filter = None;
+                            }
+                            self.filter(bk);
+                        }
+                        Char(c) if !key.ctrl => {
+                            filter.push(c);
+                            self.filter(bk);
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            return;
+        }
+        match bk.keys.action(Mode::Toc, key) {
+            Some(Action::Close) => {
+                bk.cursor = 0;
+                bk.view = Some(bk.page());
+            }
+            Some(Action::Filter) => {
+                bk.toc_unfiltered = bk.toc_index;
+                bk.toc_filter = Some(String::new());
+            }
+            Some(Action::Open) => self.open(bk),
+            Some(Action::Expand) => self.expand(bk),
+            Some(Action::Collapse) => self.collapse(bk),
+            Some(Action::LineDown) => self.next(bk, 1),
+            Some(Action::LineUp) => self.prev(bk, 1),
+            Some(Action::Top) => self.prev(bk, bk.toc.len()),
+            Some(Action::Bottom) => self.next(bk, bk.toc.len()),
+            Some(Action::NextPage) => self.next(bk, bk.rows),
+            Some(Action::PrevPage) => self.prev(bk, bk.rows),
+            Some(Action::HalfDown) => self.next(bk, bk.rows / 2),
+            Some(Action::HalfUp) => self.prev(bk, bk.rows / 2),
+            _ => (),
+        }
+    }
+    fn render(&self, bk: &Bk) -> Vec<String> {
+        let visible = self.visible(bk);
+        let pos = visible.iter().position(|&i| i == bk.toc_index).unwrap_or(0);
+        let start = pos - bk.cursor;
+        let end = min(visible.len(), start + bk.rows);
+        let width = min(bk.cols, bk.max_width) as usize;
+
+        let mut arr = Vec::new();
+        for &i in &visible[start..end] {
+            let e = &bk.toc[i];
+            let marker = if !self.has_children(bk, i) {
+                ' '
+            } else if bk.expanded.contains(&i) {
+                '-'
+            } else {
+                '+'
+            };
+            // finished, or the percent read
+            let read = match self.read(bk, i) {
+                r if r >= 1.0 => String::from("✓"),
+                r if r > 0.0 => format!("{:.0}%", r * 100.0),
+                _ => String::new(),
+            };
+            let line = format!("{}{} {}", "  ".repeat(e.level), marker, e.title);
+            let room = width.saturating_sub(5);
+            let mut line: String = line.chars().take(room).collect();
+            if !read.is_empty() {
+                let gap = width.saturating_sub(text_width(&line) + text_width(&read));
+                line = format!(
+                    "{}{}{}{}{}",
+                    line,
+                    " ".repeat(gap),
+                    bk.theme.attr(Attribute::Dim),
+                    read,
+                    bk.theme.attr(Attribute::NormalIntensity)
+                );
+            }
+            if e.synthetic {
+                line = format!(
+                    "{}{}{}",
+                    bk.theme.attr(Attribute::Dim),
+                    line,
+                    bk.theme.attr(Attribute::NormalIntensity)
+                );
+            }
+            if i == bk.toc_index {
+                line = bk.theme.select(&line);
+            }
+            arr.push(line);
+        }
+        if let Some(filter) = &bk.toc_filter {
+            if visible.is_empty() {
+                arr.push(String::from("No matches"));
+            }
+            return prompt(arr, bk, format!("/{}", filter));
+        }
+        arr
+    }
+}
+
+// a score for the chars of query in order in text, ignoring case, and
+// higher for runs and the starts of words
+fn fuzzy(query: &str, text: &str) -> Option<i32> {
+    let text: Vec<_> = text.chars().flat_map(char::to_lowercase).collect();
+    let mut score = 0;
+    let mut at = 0;
+    for q in query.chars().flat_map(char::to_lowercase) {
+        let i = at + text[at..].iter().position(|&c| c == q)?;
+        score += 1;
+        if i > 0 && i == at {
+            score += 5;
+        }
+        if i == 0 || !text[i - 1].is_alphanumeric() {
+            score += 3;
+        }
+        score -= min(i - at, 5) as i32;
+        at = i + 1;
+    }
+    Some(score)
+}
+
+// an index into the bookmarks, highlights or annotations
+#[derive(Clone, Copy)]
+enum Saved {
+    Bookmark(usize),
+    Highlight(usize),
+    Note(usize),
+}
+
+// saved places, in book order
+struct Bookmarks;
+impl Bookmarks {
+    // chapter and byte of each, sorted
+    fn list(bk: &Bk) -> Vec<(usize, usize, Saved)> {
+        let bookmarks = bk.bookmarks.iter().enumerate();
+        let highlights = bk.highlights.iter().enumerate();
+        let notes = bk.annotations.iter().enumerate();
+        let mut list: Vec<_> = bookmarks
+            .map(|(i, b)| (b.0, b.1, Saved::Bookmark(i)))
+            .chain(highlights.map(|(i, h)| (h.0, h.1, Saved::Highlight(i))))
+            .chain(notes.map(|(i, n)| (n.0, n.1, Saved::Note(i))))
+            .collect();
+        // stable, so a bookmark comes before a note at the same place
+        list.sort_by_key(|s| (s.0, s.1));
+        list
+    }
+    // select the last one before the current position
+    fn start(&self, bk: &mut Bk) {
+        let pos = (bk.chapter, bk.chap().lines[bk.line].0);
+        let i = Bookmarks::list(bk)
+            .iter()
+            .rposition(|b| (b.0, b.1) <= pos)
+            .unwrap_or(0);
+        self.select(bk, i);
+        bk.view = Some(&Bookmarks);
+    }
+    fn select(&self, bk: &mut Bk, i: usize) {
+        let len = Bookmarks::list(bk).len();
+        bk.bookmark = min(i, len.saturating_sub(1));
+        bk.cursor = min(bk.rows / 2, bk.bookmark);
+    }
+}
+impl View for Bookmarks {
+    fn on_key(&self, bk: &mut Bk, key: Key) {
+        let list = Bookmarks::list(bk);
+        match bk.keys.action(Mode::Bookmarks, key) {
+            Some(Action::Close) => {
                 bk.cursor = 0;
                 bk.view = Some(&Page);
             }
-            Enter | Right | Char('l') => {
+            Some(Action::Open) if !list.is_empty() => {
+                let (chapter, byte, _) = list[bk.bookmark];
+                let line = get_line(&bk.chapters[chapter].lines, byte);
+                bk.jump((chapter, line));
                 bk.cursor = 0;
-                bk.line = 0;
                 bk.view = Some(&Page);
             }
-            Down | Char('j') => self.next(bk, 1),
-            Up | Char('k') => self.prev(bk, 1),
-            Home | Char('g') => self.prev(bk, bk.chapters.len()),
-            End | Char('G') => self.next(bk, bk.chapters.len()),
-            PageDown | Char('f') => self.next(bk, bk.rows),
-            PageUp | Char('b') => self.prev(bk, bk.rows),
-            Char('d') => self.next(bk, bk.rows / 2),
-            Char('u') => self.prev(bk, bk.rows / 2),
+            Some(Action::Remove) if !list.is_empty() => {
+                match list[bk.bookmark].2 {
+                    Saved::Bookmark(i) => {
+                        bk.bookmarks.remove(i);
+                    }
+                    Saved::Highlight(i) => {
+                        bk.highlights.remove(i);
+                    }
+                    Saved::Note(i) => {
+                        bk.annotations.remove(i);
+                    }
+                }
+                self.select(bk, bk.bookmark);
+            }
+            Some(Action::LineDown) => self.select(bk, bk.bookmark + 1),
+            Some(Action::LineUp) => self.select(bk, bk.bookmark.saturating_sub(1)),
+            Some(Action::Top) => self.select(bk, 0),
+            Some(Action::Bottom) => self.select(bk, usize::MAX),
             _ => (),
         }
     }
     fn render(&self, bk: &Bk) -> Vec<String> {
-        let start = bk.chapter - bk.cursor;
-        let end = min(bk.chapters.len(), start + bk.rows);
+        let list = Bookmarks::list(bk);
+        if list.is_empty() {
+            let keys = bk.keys.keys(Mode::Page, Action::Bookmark);
+            if keys.is_empty() {
+                return vec![String::from("No bookmarks, highlights or notes")];
+            }
+            return vec![format!(
+                "No bookmarks, highlights or notes, {} adds a bookmark",
+                keys
+            )];
+        }
+        let start = bk.bookmark - bk.cursor;
+        let end = min(list.len(), start + bk.rows);
+        let width = min(bk.cols, bk.max_width) as usize;
+        let mut arr = Vec::new();
+        for (i, &(chapter, _, saved)) in list.iter().enumerate().take(end).skip(start) {
+            let c = &bk.chapters[chapter];
+            let title = &c.title;
+            let line = match saved {
+                // at the start of a chapter the snippet is its heading
+                Saved::Bookmark(i) if &bk.bookmarks[i].2 == title => title.clone(),
+                Saved::Bookmark(i) => format!("{}: {}", title, bk.bookmarks[i].2),
+                Saved::Highlight(i) => {
+                    let (_, a, b) = bk.highlights[i];
+                    let text = visible(&c.text[a..b]).replace('\n', " ");
+                    format!("{}: “{}”", title, text)
+                }
+                Saved::Note(i) => format!("{}: ✎ {}", title, bk.annotations[i].2),
+            };
+            let line: String = line.replace('\n', " ").chars().take(width).collect();
+            if i == bk.bookmark {
+                arr.push(bk.theme.select(&line));
+            } else {
+                arr.push(line);
+            }
+        }
+        arr
+    }
+}
 
-        let mut arr = bk.chapters[start..end]
-            .iter()
-            .map(|c| c.title.clone())
-            .collect::<Vec<String>>();
-        arr[bk.cursor] = format!(
-            "{}{}{}",
-            Attribute::Reverse,
-            arr[bk.cursor],
-            Attribute::Reset
-        );
+// the book's cover, start of the text, index and such
+struct Landmarks;
+impl Landmarks {
+    fn select(&self, bk: &mut Bk, i: usize) {
+        bk.landmark = min(i, bk.landmarks.len().saturating_sub(1));
+        bk.cursor = min(bk.rows / 2, bk.landmark);
+    }
+}
+impl View for Landmarks {
+    fn on_key(&self, bk: &mut Bk, key: Key) {
+        match bk.keys.action(Mode::Landmarks, key) {
+            Some(Action::Close) => {
+                bk.cursor = 0;
+                bk.view = Some(&Page);
+            }
+            Some(Action::Open) if !bk.landmarks.is_empty() => {
+                let (_, chapter, byte) = bk.landmarks[bk.landmark];
+                let line = get_line(&bk.chapters[chapter].lines, byte);
+                bk.jump((chapter, line));
+                bk.cursor = 0;
+                bk.view = Some(&Page);
+            }
+            Some(Action::LineDown) => self.select(bk, bk.landmark + 1),
+            Some(Action::LineUp) => self.select(bk, bk.landmark.saturating_sub(1)),
+            Some(Action::Top) => self.select(bk, 0),
+            Some(Action::Bottom) => self.select(bk, usize::MAX),
+            _ => (),
+        }
+    }
+    fn render(&self, bk: &Bk) -> Vec<String> {
+        if bk.landmarks.is_empty() {
+            return vec![String::from("No landmarks")];
+        }
+        let start = bk.landmark - bk.cursor;
+        let end = min(bk.landmarks.len(), start + bk.rows);
+        let width = min(bk.cols, bk.max_width) as usize;
+        let mut arr = Vec::new();
+        for (i, (title, _, _)) in bk.landmarks.iter().enumerate().take(end).skip(start) {
+            let line: String = title.chars().take(width).collect();
+            if i == bk.landmark {
+                arr.push(bk.theme.select(&line));
+            } else {
+                arr.push(line);
+            }
+        }
         arr
     }
 }
 
-pub struct Page;
-impl View for Page {
+// :source, the chapter's markup with the row at the reading position reversed
+struct Source;
+impl Source {
+    // the source cut into rows of the screen's width, with the byte each starts at
+    fn rows(bk: &Bk) -> Vec<(usize, String)> {
+        let width = max(bk.cols, 1) as usize;
+        let mut rows = Vec::new();
+        let mut start = 0;
+        for line in bk.markup.split_inclusive('\n') {
+            let mut row = String::new();
+            let mut at = start;
+            let mut cols = 0;
+            for (i, c) in line.char_indices() {
+                let c = match c {
+                    '\t' => ' ',
+                    '\n' | '\r' => continue,
+                    c => c,
+                };
+                let w = c.width().unwrap_or(0);
+                if cols + w > width {
+                    rows.push((at, mem::take(&mut row)));
+                    at = start + i;
+                    cols = 0;
+                }
+                row.push(c);
+                cols += w;
+            }
+            rows.push((at, row));
+            start += line.len();
+        }
+        rows
+    }
+    fn start(&self, bk: &mut Bk) -> anyhow::Result<()> {
+        bk.markup = match &bk.chap().source {
+            Some(source) => source.read()?,
+            None => anyhow::bail!("this book has no markup to show"),
+        };
+        let c = bk.chap();
+        let pos = c.lines[bk.line].0;
+        // the text node the top line starts in, and as far into it
+        let i = c.offsets.partition_point(|o| o.0 <= pos);
+        let at = match i.checked_sub(1).map(|i| c.offsets[i]) {
+            Some((text, source)) => {
+                let end = c.offsets.get(i).map_or(bk.markup.len(), |o| o.1);
+                min(source + pos - text, end.saturating_sub(1))
+            }
+            None => 0,
+        };
+        let row = Source::rows(bk)
+            .partition_point(|r| r.0 <= at)
+            .saturating_sub(1);
+        bk.source = (row.saturating_sub(bk.rows / 2), at);
+        bk.view = Some(&Source);
+        Ok(())
+    }
+    fn scroll(&self, bk: &mut Bk, top: usize) {
+        let rows = Source::rows(bk).len();
+        bk.source.0 = min(top, rows.saturating_sub(bk.rows));
+    }
+}
+impl View for Source {
     fn on_mouse(&self, bk: &mut Bk, e: MouseEvent) {
         match e {
-            MouseEvent::Down(_, col, row, _) => {
-                let c = bk.chap();
-                let line = bk.line + row as usize;
-
-                if col < bk.pad() || line >= c.lines.len() {
-                    return;
-                }
-                let (start, end) = c.lines[line];
-                let line_col = (col - bk.pad()) as usize;
-
-                let mut cols = 0;
-                let mut found = false;
-                let mut byte = start;
-                for (i, c) in c.text[start..end].char_indices() {
-                    cols += c.width().unwrap();
-                    if cols > line_col {
-                        byte += i;
-                        found = true;
+            MouseEvent::ScrollDown(_, _, _) => self.scroll(bk, bk.source.0 + 3),
+            MouseEvent::ScrollUp(_, _, _) => self.scroll(bk, bk.source.0.saturating_sub(3)),
+            _ => (),
+        }
+    }
+    fn on_key(&self, bk: &mut Bk, key: Key) {
+        let top = bk.source.0;
+        match bk.keys.action(Mode::Source, key) {
+            Some(Action::Close) => {
+                bk.markup.clear();
+                bk.view = Some(&Page);
+            }
+            Some(Action::LineDown) => self.scroll(bk, top + 1),
+            Some(Action::LineUp) => self.scroll(bk, top.saturating_sub(1)),
+            Some(Action::HalfDown) => self.scroll(bk, top + bk.rows / 2),
+            Some(Action::HalfUp) => self.scroll(bk, top.saturating_sub(bk.rows / 2)),
+            Some(Action::NextPage) => self.scroll(bk, top + bk.rows),
+            Some(Action::PrevPage) => self.scroll(bk, top.saturating_sub(bk.rows)),
+            Some(Action::Top) => self.scroll(bk, 0),
+            Some(Action::Bottom) => self.scroll(bk, usize::MAX),
+            _ => (),
+        }
+    }
+    fn render(&self, bk: &Bk) -> Vec<String> {
+        let rows = Source::rows(bk);
+        let (top, at) = bk.source;
+        let here = rows.partition_point(|r| r.0 <= at).saturating_sub(1);
+        rows.into_iter()
+            .enumerate()
+            .skip(top)
+            .take(bk.rows)
+            .map(|(i, (_, row))| {
+                if i == here {
+                    bk.theme.select(&row)
+                } else {
+                    row
+                }
+            })
+            .collect()
+    }
+}
+
+// every match of the search, with the text around it
+struct Results;
+impl Results {
+    // select the first match from the current position
+    fn start(&self, bk: &mut Bk) {
+        let chapters = if bk.chapter_search {
+            bk.chapter..bk.chapter + 1
+        } else {
+            0..bk.chapters.len()
+        };
+        bk.results = chapters
+            .flat_map(|c| bk.matches(c).into_iter().map(move |(a, b)| (c, a, b)))
+            .collect();
+        let pos = (bk.chapter, bk.chap().lines[bk.line].0);
+        let i = bk.results.partition_point(|r| (r.0, r.1) < pos);
+        self.select(bk, i);
+        bk.view = Some(&Results);
+    }
+    fn select(&self, bk: &mut Bk, i: usize) {
+        bk.result = min(i, bk.results.len().saturating_sub(1));
+        // the count takes a row
+        bk.cursor = min(bk.rows.saturating_sub(1) / 2, bk.result);
+    }
+}
+impl View for Results {
+    fn on_key(&self, bk: &mut Bk, key: Key) {
+        match bk.keys.action(Mode::Results, key) {
+            Some(Action::Close) => {
+                bk.cursor = 0;
+                bk.view = Some(&Page);
+            }
+            Some(Action::Open) if !bk.results.is_empty() => {
+                let (chapter, byte, _) = bk.results[bk.result];
+                let line = get_line(&bk.chapters[chapter].lines, byte);
+                bk.jump((chapter, line));
+                bk.cursor = 0;
+                bk.view = Some(&Page);
+            }
+            Some(Action::LineDown) => self.select(bk, bk.result + 1),
+            Some(Action::LineUp) => self.select(bk, bk.result.saturating_sub(1)),
+            Some(Action::NextPage) => self.select(bk, bk.result + bk.rows),
+            Some(Action::PrevPage) => self.select(bk, bk.result.saturating_sub(bk.rows)),
+            Some(Action::Top) => self.select(bk, 0),
+            Some(Action::Bottom) => self.select(bk, usize::MAX),
+            _ => (),
+        }
+    }
+    fn render(&self, bk: &Bk) -> Vec<String> {
+        let width = min(bk.cols, bk.max_width) as usize;
+        let mut arr = vec![match bk.results.len() {
+            1 => format!("1 match for {}", bk.query),
+            n => format!("{} matches for {}", n, bk.query),
+        }];
+        let start = bk.result - bk.cursor;
+        let end = min(bk.results.len(), start + bk.rows.saturating_sub(1));
+        for (i, &(chapter, a, b)) in bk.results.iter().enumerate().take(end).skip(start) {
+            let c = &bk.chapters[chapter];
+            // flattened to one row
+            let flat = |s: &str| visible(s).replace('\n', " ");
+            // some words before the match, and the rest of the row after
+            let before: String = {
+                let graphemes: Vec<_> = c.text[..a].graphemes(true).rev().take(20).collect();
+                flat(&graphemes.into_iter().rev().collect::<String>())
+            };
+            let title = format!("{}: ", c.title);
+            let mut room = width.saturating_sub(text_width(&title) + text_width(&before));
+            let mut fit = |s: &str| {
+                let mut out = String::new();
+                for g in s.graphemes(true) {
+                    let w = grapheme_width(g);
+                    if w > room {
                         break;
                     }
+                    room -= w;
+                    out.push_str(g);
+                }
+                out
+            };
+            // no more than a row of either, not the rest of the chapter
+            let row = |s: &str| s.graphemes(true).take(width).collect::<String>();
+            let matched = fit(&flat(&row(&c.text[a..b])));
+            let after = fit(&flat(&row(&c.text[b..])));
+            if i == bk.result {
+                arr.push(
+                    bk.theme
+                        .select(&format!("{}{}{}{}", title, before, matched, after)),
+                );
+            } else {
+                // the match itself reversed, as in the page
+                arr.push(format!(
+                    "{}{}{}{}{}{}",
+                    title,
+                    before,
+                    bk.theme.attr(Attribute::Reverse),
+                    matched,
+                    bk.theme.attr(Attribute::NoReverse),
+                    after
+                ));
+            }
+        }
+        arr
+    }
+}
+
+// v: the keyboard moves the end of the selection, y copies it
+struct Visual;
+impl Visual {
+    // at the first word on the page
+    fn start(&self, bk: &mut Bk) {
+        let text = &bk.chap().text;
+        let top = bk.chap().lines[bk.line].0;
+        let byte = text[top..]
+            .find(|c: char| !c.is_whitespace())
+            .map_or(top, |n| top + n);
+        bk.selection = Some((bk.chapter, byte, byte));
+        bk.link = None;
+        bk.visual = true;
+        bk.view = Some(&Visual);
+    }
+    fn stop(&self, bk: &mut Bk) {
+        bk.selection = None;
+        bk.visual = false;
+        bk.view = Some(&Page);
+    }
+    // the end of the selection at byte, scrolling to it
+    fn head(&self, bk: &mut Bk, byte: usize) {
+        if let Some(selection) = &mut bk.selection {
+            selection.2 = byte;
+        }
+        let line = get_line(&bk.chap().lines, byte);
+        if line < bk.line {
+            bk.line = line;
+        } else if line >= bk.line + bk.page_rows() {
+            bk.line = line + 1 - bk.page_rows();
+        }
+    }
+    // the grapheme in line i about as far across as byte is in its line
+    fn across(&self, bk: &Bk, byte: usize, i: usize) -> usize {
+        let c = bk.chap();
+        let start = c.lines[get_line(&c.lines, byte)].0;
+        let cols = text_width(&c.text[start..byte]);
+        let (a, b) = c.lines[i];
+        let mut width = 0;
+        for (n, g) in c.text[a..b].grapheme_indices(true) {
+            width += grapheme_width(g);
+            if width > cols {
+                return a + n;
+            }
+        }
+        last_grapheme(&c.text, a, b)
+    }
+}
+impl View for Visual {
+    fn on_key(&self, bk: &mut Bk, key: Key) {
+        let (_, anchor, head) = match bk.selection {
+            Some(s) => s,
+            None => return self.stop(bk),
+        };
+        let c = bk.chap();
+        let text = &c.text;
+        let line = get_line(&c.lines, head);
+        let (start, end) = c.lines[line];
+        let byte = match bk.keys.action(Mode::Visual, key) {
+            Some(Action::Close) => return self.stop(bk),
+            Some(Action::Copy) => {
+                if let Some((a, b)) = bk.selected() {
+                    let _ = copy(&visible(&bk.chap().text[a..b]));
                 }
+                return self.stop(bk);
+            }
+            Some(Action::Highlight) => {
+                bk.toggle_highlight();
+                return self.stop(bk);
+            }
+            Some(Action::SwapEnds) => {
+                bk.selection = Some((bk.chapter, head, anchor));
+                anchor
+            }
+            Some(Action::PrevChar) => text[..head]
+                .grapheme_indices(true)
+                .next_back()
+                .map_or(head, |(i, _)| i),
+            Some(Action::NextChar) => text[head..]
+                .grapheme_indices(true)
+                .nth(1)
+                .map_or(head, |(i, _)| head + i),
+            Some(a @ (Action::NextSentence | Action::PrevSentence)) => {
+                c.sentence(head, a == Action::NextSentence).unwrap_or(head)
+            }
+            Some(a @ (Action::NextParagraph | Action::PrevParagraph)) => c
+                .paragraph(head, a == Action::NextParagraph)
+                .unwrap_or(head),
+            Some(Action::NextWord) => text[head..]
+                .split_word_bound_indices()
+                .skip(1)
+                .find(|(_, w)| w.chars().any(char::is_alphanumeric))
+                .map_or(head, |(i, _)| head + i),
+            Some(Action::PrevWord) => text[..head]
+                .split_word_bound_indices()
+                .rev()
+                .find(|(_, w)| w.chars().any(char::is_alphanumeric))
+                .map_or(head, |(i, _)| i),
+            Some(Action::LineDown) if line + 1 < c.lines.len() => self.across(bk, head, line + 1),
+            Some(Action::LineUp) if line > 0 => self.across(bk, head, line - 1),
+            Some(Action::LineStart) => start,
+            Some(Action::LineEnd) => last_grapheme(text, start, end),
+            _ => head,
+        };
+        self.head(bk, byte);
+    }
+    fn render(&self, bk: &Bk) -> Vec<String> {
+        let buf = Page.render(bk);
+        if bk.message.is_some() {
+            return buf;
+        }
+        prompt(buf, bk, String::from("-- VISUAL --"))
+    }
+}
+
+// the start of the last grapheme before end, not counting the line break
+fn last_grapheme(text: &str, start: usize, end: usize) -> usize {
+    text[start..end]
+        .trim_end_matches('\n')
+        .grapheme_indices(true)
+        .next_back()
+        .map_or(start, |(i, _)| start + i)
+}
 
-                if !found {
-                    return;
+pub struct Page;
+impl Page {
+    // what the key is bound to, with the count typed before it
+    fn act(&self, bk: &mut Bk, key: Key) {
+        let count = mem::take(&mut bk.count);
+        // how many times to move, and lines for j and k
+        let times = max(count, 1);
+        let lines = if count > 0 { count } else { 3 };
+        if key
+            == (Key {
+                code: Esc,
+                ctrl: false,
+            })
+            && (bk.selection.is_some() || bk.link.is_some())
+        {
+            bk.selection = None;
+            bk.link = None;
+            return;
+        }
+        let action = match bk.keys.action(Mode::Page, key) {
+            Some(action) => action,
+            None => return,
+        };
+        match action {
+            Action::Percent if count > 0 => bk.goto_percent(min(count, 100)),
+            Action::Percent => (),
+            Action::ChapterEnd if count > 0 => bk.goto_page(count - 1),
+            Action::Visual => Visual.start(bk),
+            Action::Command => {
+                bk.command.clear();
+                bk.view = Some(&Command);
+            }
+            Action::Back => bk.go_back(),
+            Action::NextLink => self.pick_link(bk, true),
+            Action::PrevLink => self.pick_link(bk, false),
+            Action::CopyLink => {
+                if let Some((chapter, i)) = bk.link {
+                    let url = &bk.chapters[chapter].links[i].2;
+                    if external(url) {
+                        let _ = copy(url);
+                    }
+                }
+            }
+            Action::FollowLink => {
+                if let Some((chapter, i)) = bk.link.take() {
+                    if chapter == bk.chapter {
+                        self.follow(bk, i);
+                    }
+                }
+            }
+            Action::JumpBack => bk.jump_back(),
+            Action::JumpForward => bk.jump_forward(),
+            Action::NextBook => bk.switch_to((bk.book + 1) % bk.books.len()),
+            Action::PrevBook => bk.switch_to((bk.book + bk.books.len() - 1) % bk.books.len()),
+            Action::Quit => bk.view = None,
+            Action::Toc => {
+                Nav.start(bk);
+            }
+            Action::Help => Help.start(bk),
+            Action::SetMark => bk.view = Some(&Mark),
+            Action::Bookmark => bk.add_bookmark(),
+            Action::Bookmarks => Bookmarks.start(bk),
+            Action::Landmarks => {
+                Landmarks.select(bk, 0);
+                bk.view = Some(&Landmarks);
+            }
+            Action::Highlight => bk.toggle_highlight(),
+            Action::Annotate => {
+                // a missing editor just leaves the note as it was
+                let _ = bk.annotate();
+            }
+            Action::JumpToMark => bk.view = Some(&Jump),
+            Action::Metadata => bk.view = Some(&Metadata),
+            Action::Justify => bk.justify = !bk.justify,
+            Action::Theme => bk.next_theme(),
+            Action::Status => bk.status = !bk.status,
+            Action::SearchBackward => bk.start_search(Direction::Prev),
+            Action::SearchForward => bk.start_search(Direction::Next),
+            Action::PrevMatch | Action::NextMatch => {
+                for _ in 0..times {
+                    // n keeps the direction of the search, N turns it around
+                    let dir = match (&bk.dir, action == Action::NextMatch) {
+                        (Direction::Next, true) | (Direction::Prev, false) => Direction::Next,
+                        _ => Direction::Prev,
+                    };
+                    let args = SearchArgs { dir, skip: true };
+                    if !bk.search(args) {
+                        bk.message = Some(String::from("no more matches"));
+                        break;
+                    }
+                }
+            }
+            Action::ChapterEnd => {
+                bk.mark('\'');
+                bk.line = bk.chap().lines.len().saturating_sub(bk.page_rows());
+            }
+            Action::ChapterStart => {
+                bk.mark('\'');
+                bk.line = 0;
+            }
+            Action::HalfDown => {
+                for _ in 0..times {
+                    bk.scroll_down(bk.page_rows() / 2);
+                }
+            }
+            Action::HalfUp => {
+                for _ in 0..times {
+                    bk.scroll_up(bk.page_rows() / 2);
+                }
+            }
+            Action::LineUp => bk.scroll_up(lines),
+            Action::PrevPage => {
+                for _ in 0..times {
+                    bk.scroll_up(bk.page_step());
+                }
+            }
+            Action::LineDown => bk.scroll_down(lines),
+            Action::NextPage => {
+                for _ in 0..times {
+                    bk.scroll_down(bk.page_step());
+                }
+            }
+            Action::PrevChapter => {
+                for _ in 0..times {
+                    bk.prev_chapter();
+                }
+            }
+            Action::NextChapter => {
+                for _ in 0..times {
+                    bk.next_chapter();
+                }
+            }
+            Action::NextSentence
+            | Action::PrevSentence
+            | Action::NextParagraph
+            | Action::PrevParagraph => {
+                let paragraph = matches!(action, Action::NextParagraph | Action::PrevParagraph);
+                let forward = matches!(action, Action::NextSentence | Action::NextParagraph);
+                for _ in 0..times {
+                    bk.move_by(paragraph, forward);
+                }
+            }
+            Action::NextHeading | Action::PrevHeading => {
+                let mut line = bk.line;
+                for _ in 0..times {
+                    match bk.heading(line, action == Action::NextHeading) {
+                        Some(l) => line = l,
+                        None => break,
+                    }
+                }
+                if line != bk.line {
+                    bk.jump((bk.chapter, line));
                 }
+            }
+            // those of the other views
+            _ => (),
+        }
+    }
+    // a link of this chapter. a footnote shows over the page, and a link
+    // out of the book opens in the browser
+    fn follow(&self, bk: &mut Bk, i: usize) {
+        let c = bk.chap();
+        let (start, end, url) = &c.links[i];
+        // by the markup, or for books that never say, a short link
+        let note = c.notes.contains(start)
+            || (bk.chapters.iter().all(|c| c.notes.is_empty()) && noteref(&c.text[*start..*end]));
+        match bk.links.get(url) {
+            Some(&(chapter, byte)) if note => {
+                bk.note = (chapter, byte, i);
+                bk.view = Some(&Footnote);
+            }
+            Some(&target) => bk.follow_link(i, target),
+            None if external(url) => {
+                // nothing to show a failed browser on
+                let _ = bk.open_url(url);
+            }
+            None => (),
+        }
+    }
+    // pick the next or previous link on the page, around to the other end
+    fn pick_link(&self, bk: &mut Bk, forward: bool) {
+        let c = bk.chap();
+        let first = c.lines[bk.line].0;
+        let last = c.lines[min(bk.line + bk.page_rows(), c.lines.len()) - 1].1;
+        let links: Vec<_> = (0..c.links.len())
+            .filter(|&i| first <= c.links[i].0 && c.links[i].0 < last)
+            .collect();
+        let picked = bk
+            .link
+            .filter(|l| l.0 == bk.chapter)
+            .and_then(|l| links.iter().position(|&i| i == l.1));
+        let next = match (picked, forward) {
+            (Some(n), true) => links.get(n + 1).or(links.first()),
+            (Some(n), false) => n.checked_sub(1).and_then(|n| links.get(n)).or(links.last()),
+            (None, true) => links.first(),
+            (None, false) => links.last(),
+        };
+        bk.link = next.map(|&i| (bk.chapter, i));
+    }
+    // the byte of the grapheme drawn at a screen cell
+    fn byte_at(&self, bk: &Bk, col: u16, row: u16) -> Option<usize> {
+        let c = bk.chap();
+        // nothing on the blank rows between lines, or the header
+        let top = self.top(bk) + bk.header as usize;
+        let rows = bk.column_rows();
+        // the second column
+        let (x, line) = if bk.spread() && col >= bk.pad() + bk.max_width + GAP {
+            (bk.pad() + bk.max_width + GAP, bk.line + rows)
+        } else {
+            (bk.pad(), bk.line)
+        };
+        let top = if line == bk.line {
+            top
+        } else {
+            bk.header as usize
+        };
+        let n = (0..rows).find(|&n| top + bk.row(n) == row as usize)?;
+        let line = line + n;
+
+        if col < x || line >= c.lines.len() {
+            return None;
+        }
+        let (start, end) = c.lines[line];
+        let width = min(bk.cols, bk.max_width) as usize;
+        let line_col = ((col - x) as usize).checked_sub(c.indent(line, width))?;
+
+        // graphemes in display order
+        let runs = c.visual(line).unwrap_or_else(|| vec![(start, end, false)]);
+        let graphemes = runs.into_iter().flat_map(|(a, b, rtl)| {
+            let mut v: Vec<_> = c.text[a..b]
+                .grapheme_indices(true)
+                .map(|(i, g)| (a + i, g))
+                .collect();
+            if rtl {
+                v.reverse();
+            }
+            v
+        });
+        let gaps = if bk.justify {
+            c.justify(line, width)
+        } else {
+            Vec::new()
+        };
+        let mut gaps = gaps.into_iter();
+        let mut cols = 0;
+        for (i, g) in graphemes {
+            cols += text_width(g);
+            if g == " " {
+                cols += gaps.next().unwrap_or(0);
+            }
+            if cols > line_col {
+                return Some(i);
+            }
+        }
+        None
+    }
+    // rows above a title page to center it, if it fits
+    fn top(&self, bk: &Bk) -> usize {
+        let c = bk.chap();
+        if !c.title_page || bk.line > 0 || c.lines.len() > bk.column_rows() {
+            return 0;
+        }
+        let mut lines = (0..c.lines.len()).filter(|&n| !c.blank(n));
+        let first = match lines.next() {
+            Some(first) => first,
+            None => return 0,
+        };
+        let last = lines.next_back().unwrap_or(first);
+        let height = bk.row(last) - bk.row(first) + 1;
+        (bk.text_rows().saturating_sub(height) / 2).saturating_sub(bk.row(first))
+    }
+    // the escape drawing the image at byte, in at most rows
+    fn image(&self, bk: &Bk, byte: usize, rows: usize) -> Option<String> {
+        let c = bk.chap();
+        let i = c.image_at(byte)?;
+        let width = min(bk.cols, bk.max_width) as usize;
+        let (cols, max) = c.image_size(bk.sizes[i]?, byte, width);
+        let rows = min(max as usize, rows) as u16;
+        let (s, w, _) = img::draw(&bk.image(i).ok()?, bk.protocol, cols, rows).ok()?;
+        let gap = (width - c.indent_at(byte, width)).saturating_sub(w as usize) / 2;
+        if gap > 0 {
+            return Some(format!("{}{}", MoveRight(gap as u16), s));
+        }
+        Some(s)
+    }
+}
+impl View for Page {
+    fn on_mouse(&self, bk: &mut Bk, e: MouseEvent) {
+        match e {
+            MouseEvent::Down(_, col, row, _) => {
+                let byte = match self.byte_at(bk, col, row) {
+                    Some(byte) => byte,
+                    None => {
+                        bk.selection = None;
+                        return;
+                    }
+                };
+                let c = bk.chap();
 
                 let r = c.links.binary_search_by(|&(start, end, _)| {
                     if start > byte {
@@ -220,11 +1232,16 @@ impl View for Page {
                     }
                 });
 
-                if let Ok(i) = r {
-                    let url = &c.links[i].2;
-                    let &(chapter, byte) = bk.links.get(url).unwrap();
-                    let line = get_line(&bk.chapters[chapter].lines, byte);
-                    bk.jump((chapter, line));
+                match r {
+                    Ok(i) => self.follow(bk, i),
+                    // not a link, so the start of a selection
+                    Err(_) => bk.selection = Some((bk.chapter, byte, byte)),
+                }
+            }
+            MouseEvent::Drag(_, col, row, _) => {
+                let byte = self.byte_at(bk, col, row);
+                if let (Some(byte), Some(selection)) = (byte, &mut bk.selection) {
+                    selection.2 = byte;
                 }
             }
             MouseEvent::ScrollDown(_, _, _) => bk.scroll_down(3),
@@ -232,52 +1249,15 @@ impl View for Page {
             _ => (),
         }
     }
-    fn on_key(&self, bk: &mut Bk, kc: KeyCode) {
-        match kc {
-            Esc | Char('q') => bk.view = None,
-            Tab => {
-                bk.mark('\'');
-                Nav.cursor(bk);
-                bk.view = Some(&Nav);
-            }
-            F(_) => bk.view = Some(&Help),
-            Char('m') => bk.view = Some(&Mark),
-            Char('\'') => bk.view = Some(&Jump),
-            Char('i') => bk.view = Some(&Metadata),
-            Char('?') => bk.start_search(Direction::Prev),
-            Char('/') => bk.start_search(Direction::Next),
-            Char('N') => {
-                bk.search(SearchArgs {
-                    dir: Direction::Prev,
-                    skip: true,
-                });
-            }
-            Char('n') => {
-                bk.search(SearchArgs {
-                    dir: Direction::Next,
-                    skip: true,
-                });
+    fn on_key(&self, bk: &mut Bk, key: Key) {
+        if let (Char(c @ '0'..='9'), false) = (key.code, key.ctrl) {
+            if c != '0' || bk.count > 0 {
+                let digit = c.to_digit(10).unwrap() as usize;
+                bk.count = bk.count.saturating_mul(10).saturating_add(digit);
+                return;
             }
-            End | Char('G') => {
-                bk.mark('\'');
-                bk.line = bk.chap().lines.len().saturating_sub(bk.rows);
-            }
-            Home | Char('g') => {
-                bk.mark('\'');
-                bk.line = 0;
-            }
-            Char('d') => bk.scroll_down(bk.rows / 2),
-            Char('u') => bk.scroll_up(bk.rows / 2),
-            Up | Char('k') => bk.scroll_up(3),
-            Left | PageUp | Char('b') | Char('h') => {
-                bk.scroll_up(bk.rows);
-            }
-            Down | Char('j') => bk.scroll_down(3),
-            Right | PageDown | Char('f') | Char('l') | Char(' ') => bk.scroll_down(bk.rows),
-            Char('[') => bk.prev_chapter(),
-            Char(']') => bk.next_chapter(),
-            _ => (),
         }
+        self.act(bk, key);
     }
     fn on_resize(&self, bk: &mut Bk) {
         // lazy
@@ -285,19 +1265,21 @@ impl View for Page {
     }
     fn render(&self, bk: &Bk) -> Vec<String> {
         let c = bk.chap();
-        let line_end = min(bk.line + bk.rows, c.lines.len());
+        let line_end = min(bk.line + bk.page_rows(), c.lines.len());
 
         let attrs = {
             let text_start = c.lines[bk.line].0;
             let text_end = c.lines[line_end - 1].1;
 
-            let qlen = bk.query.len();
             let mut search = Vec::new();
-            if qlen > 0 {
-                for (pos, _) in c.text[text_start..text_end].match_indices(&bk.query) {
-                    search.push((text_start + pos, Attribute::Reverse));
-                    search.push((text_start + pos + qlen, Attribute::NoReverse));
-                }
+            let matches = if bk.hlsearch || bk.searching {
+                bk.matches_in(bk.chapter, text_start, text_end)
+            } else {
+                Vec::new()
+            };
+            for (a, b) in matches {
+                search.push((a, Attribute::Reverse));
+                search.push((b, Attribute::NoReverse));
             }
             let mut search_iter = search.into_iter().peekable();
 
@@ -320,6 +1302,12 @@ impl View for Page {
             if attr.has(Attribute::Underlined) {
                 merged.push((text_start, Attribute::Underlined));
             }
+            if attr.has(Attribute::CrossedOut) {
+                merged.push((text_start, Attribute::CrossedOut));
+            }
+            if attr.has(Attribute::Dim) {
+                merged.push((text_start, Attribute::Dim));
+            }
             let mut attrs_iter = attrs_iter
                 .map(|&(pos, a, _)| (pos, a))
                 .take_while(|(pos, _)| pos <= &text_end)
@@ -352,21 +1340,633 @@ impl View for Page {
             merged
         };
 
-        let mut buf = Vec::new();
+        let theme = &bk.theme;
+        let width = min(bk.cols, bk.max_width) as usize;
+        let spread = bk.spread();
+        let rows = bk.column_rows();
+        let mut columns = [Vec::new(), Vec::new()];
         let mut iter = attrs.into_iter().peekable();
-        for &(mut start, end) in &c.lines[bk.line..line_end] {
-            let mut s = String::new();
-            while let Some(&(pos, attr)) = iter.peek() {
-                if pos > end {
-                    break;
+        // in a search match
+        let mut reverse = false;
+        let first = c.lines[bk.line].0;
+        let last = c.lines[line_end - 1].1;
+        let mut changes = restyles(bk, first, last).into_iter().peekable();
+        for (i, &(mut start, end)) in c.lines[bk.line..line_end].iter().enumerate() {
+            // the second column starts over at the top
+            let (buf, n) = (&mut columns[i / rows], i % rows);
+            let top = if i < rows { self.top(bk) } else { 0 };
+            // where the column starts
+            let x = bk.pad() + if i < rows { 0 } else { bk.max_width + GAP };
+            // blank rows for the line height
+            buf.resize(top + bk.row(n), String::new());
+            if c.ruby_lines.binary_search(&(bk.line + i)).is_ok() {
+                buf.push(ruby_line(bk, bk.line + i + 1, reverse));
+                continue;
+            }
+            // an image replaces its placeholder, keeping the styles
+            let image = self.image(bk, start, bk.text_rows() - top - bk.row(n));
+            let mut s = image.clone().unwrap_or_default();
+            // the rows of the columns are drawn in turn, so each line sets
+            // its own styles
+            if spread || i == 0 && (styled(bk, first).is_set() || painted(bk, first).is_some()) {
+                s.push_str(&style(bk, start, reverse));
+            }
+            let visual = match image {
+                Some(_) => None,
+                None => c.visual(bk.line + i),
+            };
+            if let Some(runs) = visual {
+                let mut toggles = Vec::new();
+                while let Some(&(pos, attr)) = iter.peek() {
+                    if pos > end {
+                        break;
+                    }
+                    toggles.push((pos, attr));
+                    iter.next();
+                }
+                let mut restyled = Vec::new();
+                while let Some(pos) = changes.next_if(|&pos| pos <= end) {
+                    restyled.push(pos);
+                }
+                let reverse_at = |byte| {
+                    toggles.iter().filter(|&&(pos, _)| pos <= byte).fold(
+                        reverse,
+                        |r, &(_, attr)| match attr {
+                            Attribute::Reverse => true,
+                            Attribute::NoReverse => false,
+                            _ => r,
+                        },
+                    )
+                };
+                // styles are toggles in logical order, so restyle each piece
+                for (a, b, rtl) in runs {
+                    let mut cuts: Vec<_> = toggles
+                        .iter()
+                        .map(|t| t.0)
+                        .chain(restyled.iter().copied())
+                        .filter(|&pos| a < pos && pos < b)
+                        .collect();
+                    cuts.sort_unstable();
+                    cuts.dedup();
+                    let mut pieces: Vec<_> = iter::once(a)
+                        .chain(cuts.iter().copied())
+                        .zip(cuts.iter().copied().chain(iter::once(b)))
+                        .collect();
+                    if rtl {
+                        pieces.reverse();
+                    }
+                    for (a, b) in pieces {
+                        s.push_str(&style(bk, a, reverse_at(a)));
+                        if rtl {
+                            s.extend(c.text[a..b].graphemes(true).rev());
+                        } else {
+                            s.push_str(&c.text[a..b]);
+                        }
+                    }
                 }
-                s.push_str(&c.text[start..pos]);
-                s.push_str(&attr.to_string());
-                start = pos;
-                iter.next();
+                reverse = reverse_at(end);
+                s.push_str(&style(bk, end, reverse));
+            } else {
+                let gaps = if bk.justify && image.is_none() {
+                    c.justify(bk.line + i, width)
+                } else {
+                    Vec::new()
+                };
+                let mut gaps = gaps.into_iter();
+                // text, widening the spaces
+                let mut push = |s: &mut String, text: &str| {
+                    for (i, word) in text.split(' ').enumerate() {
+                        if i > 0 {
+                            s.push(' ');
+                            s.extend(iter::repeat_n(' ', gaps.next().unwrap_or(0)));
+                        }
+                        s.push_str(word);
+                    }
+                };
+                loop {
+                    let attr = iter.peek().map(|a| a.0).filter(|&pos| pos <= end);
+                    let change = changes.peek().copied().filter(|&pos| pos <= end);
+                    let (pos, code) = match (attr, change) {
+                        (None, None) => break,
+                        // after toggles at the same byte, which could end what
+                        // a style adds
+                        (a, Some(pos)) if a.is_none_or(|a| pos < a) => {
+                            changes.next();
+                            (pos, style(bk, pos, reverse))
+                        }
+                        _ => {
+                            let (pos, attr) = iter.next().unwrap();
+                            match attr {
+                                Attribute::Reverse => reverse = true,
+                                Attribute::NoReverse => reverse = false,
+                                _ => (),
+                            }
+                            // a match ending inside a highlight
+                            match attr {
+                                Attribute::NoReverse if painted(bk, pos).is_some() => {
+                                    (pos, style(bk, pos, false))
+                                }
+                                _ => (pos, theme.attr(attr)),
+                            }
+                        }
+                    };
+                    if image.is_none() {
+                        push(&mut s, &c.text[start..pos]);
+                    }
+                    s.push_str(&code);
+                    start = pos;
+                }
+                if image.is_none() {
+                    push(&mut s, &c.text[start..end]);
+                }
+            }
+            if let Some(mark) = c.end_mark(bk.line + i) {
+                s.push(mark);
+            }
+            let line = c.table_line(bk.line + i, width, visible(&s));
+            let note = bk
+                .annotations
+                .iter()
+                .any(|a| a.0 == bk.chapter && get_line(&c.lines, a.1) == bk.line + i);
+            // in the gutter, or else after the text
+            let (gutter, after) = match (note, x) {
+                (false, _) => (String::new(), ""),
+                (true, x) if x >= 2 => (format!("{}✎ ", MoveToColumn(x - 1)), ""),
+                (true, _) => (String::new(), " ✎"),
+            };
+            buf.push(format!(
+                "{}{}{}{}",
+                gutter,
+                c.margin(bk.line + i, width),
+                line,
+                after
+            ));
+        }
+        let [mut buf, right] = columns;
+        if !right.is_empty() {
+            buf.resize(right.len(), String::new());
+            let x = bk.pad() + bk.max_width + GAP;
+            for (row, line) in buf.iter_mut().zip(right) {
+                // clear the styles of the first column before the gap
+                row.push_str(&theme.reset());
+                row.push_str(&format!("{}{}", MoveToColumn(x + 1), line));
+            }
+        }
+        if bk.header {
+            buf.insert(0, header(bk));
+        }
+        if let Some(message) = &bk.message {
+            return prompt(buf, bk, message.clone());
+        }
+        if bk.status {
+            buf.resize(bk.rows.saturating_sub(1), String::new());
+            buf.push(status(bk));
+        }
+        buf
+    }
+}
+
+// the title of the toc entry or chapter, dimmed and centered
+fn header(bk: &Bk) -> String {
+    let title = match bk.toc_entry() {
+        Some(i) => &bk.toc[i].title,
+        None => &bk.chap().title,
+    };
+    let mut width = min(bk.cols, bk.max_width) as usize;
+    if bk.spread() {
+        width = 2 * width + GAP as usize;
+    }
+    let mut fit = String::new();
+    for c in title.chars() {
+        if text_width(&fit) + text_width(&c.to_string()) >= width {
+            fit.push('…');
+            break;
+        }
+        fit.push(c);
+    }
+    let gap = width.saturating_sub(text_width(&fit)) / 2;
+    format!(
+        "{}{}{}{}",
+        " ".repeat(gap),
+        bk.theme.attr(Attribute::Dim),
+        fit,
+        bk.theme.reset()
+    )
+}
+
+// the book and chapter titles, then the chapter, progress and time
+fn status(bk: &Bk) -> String {
+    let total: usize = bk.chapters.iter().map(|c| c.lines.len()).sum();
+    let current = bk.book_line();
+    let (page, pages) = bk.page_number();
+    let (location, locations) = bk.location();
+    // the time left at the reader's pace
+    let remaining = |lines: usize| match bk.pace.minutes(lines) {
+        Some(m) if m >= 60 => format!(" ({}h{:02}m)", m / 60, m % 60),
+        Some(m) => format!(" ({}m)", m),
+        None => String::new(),
+    };
+    let found = match bk.match_index() {
+        _ if !bk.hlsearch && !bk.searching => String::new(),
+        Some((_, 0)) => String::from("no matches  "),
+        Some((i, n)) => format!("match {} of {}  ", i, n),
+        None => String::new(),
+    };
+    let print = match bk.print_page() {
+        Some(label) => format!("  print p {}", label),
+        None => String::new(),
+    };
+    let right = format!(
+        "{}ch {}/{}{}  p {}/{}{}  loc {}/{}  {:.0}%{}  {}",
+        found,
+        bk.chapter + 1,
+        bk.chapters.len(),
+        remaining(bk.chap().lines.len().saturating_sub(bk.line)),
+        page + 1,
+        pages,
+        print,
+        location,
+        locations,
+        bk.progress(),
+        remaining(total - current),
+        clock()
+    );
+
+    // the toc entries down to this one, or else the chapter
+    let mut path = Vec::new();
+    let mut entry = bk.toc_entry();
+    while let Some(i) = entry {
+        path.push(bk.toc[i].title.as_str());
+        entry = Nav.parent(bk, i);
+    }
+    path.reverse();
+    let section = if path.is_empty() {
+        bk.chap().title.clone()
+    } else {
+        path.join(" › ")
+    };
+    let title = bk.meta.iter().find_map(|m| m.strip_prefix("title: "));
+    let titles: Vec<_> = title
+        .into_iter()
+        .chain(iter::once(section.as_str()))
+        .filter(|t| !t.is_empty())
+        .collect();
+    let width = min(bk.cols, bk.max_width) as usize;
+    let room = width.saturating_sub(text_width(&right) + 2);
+    let mut left = String::new();
+    for c in titles.join(" · ").chars() {
+        if text_width(&left) + text_width(&c.to_string()) >= room {
+            left.push('…');
+            break;
+        }
+        left.push(c);
+    }
+    let gap = width.saturating_sub(text_width(&left) + text_width(&right));
+    format!(
+        "{}{}{}{}{}",
+        bk.theme.attr(Attribute::Dim),
+        left,
+        " ".repeat(gap),
+        right,
+        bk.theme.reset()
+    )
+}
+
+// hours and minutes of the local time
+#[cfg(unix)]
+fn clock() -> String {
+    // localtime_r only writes to the tm it's given
+    let tm = unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm = mem::zeroed::<libc::tm>();
+        libc::localtime_r(&now, &mut tm);
+        tm
+    };
+    format!("{:02}:{:02}", tm.tm_hour, tm.tm_min)
+}
+
+// utc, without the time zone
+#[cfg(not(unix))]
+fn clock() -> String {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    format!("{:02}:{:02}", secs / 3600 % 24, secs / 60 % 60)
+}
+
+// a link out of the book, eg https: or mailto:
+fn external(url: &str) -> bool {
+    url.split_once(':').is_some_and(|(scheme, _)| {
+        scheme.len() > 1 && scheme.chars().all(|c| c.is_ascii_alphabetic())
+    })
+}
+
+// a footnote reference by its text: a short link like 1, [2] or *
+fn noteref(text: &str) -> bool {
+    let text = text.trim().trim_matches(['[', ']', '(', ')']);
+    !text.is_empty() && text.chars().count() <= 3
+}
+
+// a footnote over the page, the text at its target up to a blank line or the
+// next anchor
+struct Footnote;
+impl Footnote {
+    fn text<'a>(bk: &'a Bk) -> &'a str {
+        let (chapter, byte, _) = bk.note;
+        let text = &bk.chapters[chapter].text;
+        let start = byte + (text[byte..].len() - text[byte..].trim_start().len());
+        let next = bk
+            .links
+            .values()
+            .filter(|&&(c, b)| c == chapter && b > start)
+            .map(|&(_, b)| b)
+            .min()
+            .unwrap_or(text.len());
+        let end = text[start..next].find("\n\n").map_or(next, |n| start + n);
+        text[start..end].trim_end()
+    }
+}
+impl View for Footnote {
+    fn on_mouse(&self, bk: &mut Bk, e: MouseEvent) {
+        if let MouseEvent::Down(_, _, _, _) = e {
+            bk.view = Some(&Page);
+        }
+    }
+    fn on_key(&self, bk: &mut Bk, key: Key) {
+        match bk.keys.action(Mode::Footnote, key) {
+            Some(Action::Close) => bk.view = Some(&Page),
+            // go to the note
+            Some(Action::Open) => {
+                let (chapter, byte, i) = bk.note;
+                bk.follow_link(i, (chapter, byte));
+                bk.view = Some(&Page);
+            }
+            _ => (),
+        }
+    }
+    fn render(&self, bk: &Bk) -> Vec<String> {
+        let mut buf = Page::render(&Page, bk);
+        buf.resize(bk.rows, String::new());
+        let width = min(bk.cols, bk.max_width) as usize;
+        let inner = width.saturating_sub(4).max(1);
+        let text = Footnote::text(bk);
+        let mut lines: Vec<_> = wrap(text, inner, None, |_| 0)
+            .into_iter()
+            .map(|(a, b)| &text[a..b])
+            .collect();
+        let max = (bk.rows * 2 / 3).saturating_sub(2).max(1);
+        if lines.len() > max {
+            lines.truncate(max);
+            lines[max - 1] = "…";
+        }
+        let border = "─".repeat(inner + 2);
+        let mut boxed = vec![format!("┌{}┐", border)];
+        for line in lines {
+            let pad = inner.saturating_sub(text_width(line));
+            boxed.push(format!("│ {}{} │", visible(line), " ".repeat(pad)));
+        }
+        boxed.push(format!("└{}┘", border));
+        let top = bk.rows.saturating_sub(boxed.len());
+        for (row, line) in buf[top..].iter_mut().zip(boxed) {
+            *row = format!("{}{}", bk.theme.reset(), line);
+        }
+        buf
+    }
+}
+
+// the ruby over a line, centered on their base text where there's room
+fn ruby_line(bk: &Bk, line: usize, reverse: bool) -> String {
+    let c = bk.chap();
+    let width = min(bk.cols, bk.max_width) as usize;
+    let (start, end) = c.lines[line];
+    let mut s = String::new();
+    let mut col = 0;
+    for (a, b, ruby) in c.rubies.iter().filter(|r| start <= r.0 && r.0 < end) {
+        let base = text_width(&c.text[start..*a]);
+        let w = text_width(&c.text[*a..min(*b, end)]);
+        let at = max(col, (base + w / 2).saturating_sub(text_width(ruby) / 2));
+        s.push_str(&" ".repeat(at - col));
+        s.push_str(ruby);
+        col = at + text_width(ruby);
+    }
+    // then back to the styles of the line below
+    format!(
+        "{}{}{}{}{}",
+        bk.theme.reset(),
+        c.margin(line, width),
+        bk.theme.attr(Attribute::Dim),
+        s,
+        style(bk, start, reverse)
+    )
+}
+
+// reset, then the styles at byte
+fn style(bk: &Bk, byte: usize, reverse: bool) -> String {
+    let c = bk.chap();
+    let theme = &bk.theme;
+    let n = c.attrs.partition_point(|a| a.0 <= byte);
+    let state = c.attrs[n.max(1) - 1].2;
+    let extra = styled(bk, byte);
+    let mut s = theme.reset();
+    if let Some(color) = extra.color {
+        s.push_str(&SetForegroundColor(color).to_string());
+    }
+    for attr in Attribute::iterator()
+        .filter(|&a| a != Attribute::Reset && (state.has(a) || extra.attrs.has(a)))
+    {
+        s.push_str(&theme.attr(attr));
+    }
+    if reverse {
+        s.push_str(&theme.attr(Attribute::Reverse));
+    } else if let Some(selected) = painted(bk, byte) {
+        s.push_str(&theme.paint(selected));
+    }
+    s
+}
+
+// whether byte is selected, or else highlighted
+fn painted(bk: &Bk, byte: usize) -> Option<bool> {
+    if bk.selected().is_some_and(|(a, b)| a <= byte && byte < b) {
+        return Some(true);
+    }
+    if picked_link(bk).is_some_and(|(a, b)| a <= byte && byte < b) {
+        return Some(true);
+    }
+    bk.highlights
+        .iter()
+        .any(|h| h.0 == bk.chapter && h.1 <= byte && byte < h.2)
+        .then_some(false)
+}
+
+// the bytes of the link picked with the keyboard
+fn picked_link(bk: &Bk) -> Option<(usize, usize)> {
+    let (chapter, i) = bk.link?;
+    let link = bk.chapters[chapter].links.get(i)?;
+    Some((link.0, link.1)).filter(|_| chapter == bk.chapter)
+}
+
+// what the theme accent and --style add at byte. the more specific element
+// wins the color, and highlighted code over all
+fn styled(bk: &Bk, byte: usize) -> Style {
+    let c = bk.chap();
+    let n = c.attrs.partition_point(|a| a.0 <= byte);
+    let state = c.attrs[n.max(1) - 1].2;
+    let heading = c.headings.iter().any(|h| h.0 <= byte && byte < h.1);
+    let link = c.links.iter().any(|l| l.0 <= byte && byte < l.1);
+    let kinds = [
+        (c.in_quote(byte), bk.styles.quote),
+        (heading, bk.styles.heading),
+        (state.has(Attribute::Italic), bk.styles.emphasis),
+        (state.has(Attribute::Bold) && !heading, bk.styles.strong),
+        (link, bk.styles.link),
+        (c.in_pre(byte), bk.styles.code),
+    ];
+    let mut style = Style::default();
+    if heading && bk.theme.accent != Color::Reset {
+        style.color = Some(bk.theme.accent);
+    }
+    for (_, kind) in kinds.iter().filter(|k| k.0) {
+        style.attrs.extend(kind.attrs);
+        style.color = kind.color.or(style.color);
+    }
+    if let Some((r, g, b)) = c.color_at(byte) {
+        style.color = Some(Color::Rgb { r, g, b });
+    }
+    style
+}
+
+// bytes after first up to last where styled may change
+fn restyles(bk: &Bk, first: usize, last: usize) -> Vec<usize> {
+    let c = bk.chap();
+    let styles = &bk.styles;
+    let mut v: Vec<_> = c.colors.iter().map(|x| x.0).collect();
+    v.extend(
+        bk.highlights
+            .iter()
+            .filter(|h| h.0 == bk.chapter)
+            .flat_map(|h| [h.1, h.2]),
+    );
+    v.extend(bk.selected().into_iter().flat_map(|(a, b)| [a, b]));
+    v.extend(picked_link(bk).into_iter().flat_map(|(a, b)| [a, b]));
+    if styles.heading.is_set() || bk.theme.accent != Color::Reset {
+        v.extend(c.headings.iter().flat_map(|h| [h.0, h.1]));
+    }
+    if styles.link.is_set() {
+        v.extend(c.links.iter().flat_map(|l| [l.0, l.1]));
+    }
+    if styles.quote.is_set() {
+        v.extend(
+            c.indents
+                .iter()
+                .filter(|i| i.3.is_some())
+                .flat_map(|i| [i.0, i.1]),
+        );
+    }
+    if styles.code.is_set() {
+        v.extend(c.pres.iter().flat_map(|p| [p.0, p.1]));
+    }
+    // toggles can end what a style adds, or start emphasis
+    let kinds = [
+        styles.heading,
+        styles.quote,
+        styles.emphasis,
+        styles.strong,
+        styles.link,
+        styles.code,
+    ];
+    if kinds.iter().any(Style::is_set) {
+        v.extend(c.attrs.iter().map(|a| a.0));
+    }
+    v.retain(|&pos| first < pos && pos <= last);
+    v.sort_unstable();
+    v.dedup();
+    v
+}
+
+// full screen images, one per chapter
+pub struct Picture;
+impl View for Picture {
+    fn on_mouse(&self, bk: &mut Bk, e: MouseEvent) {
+        match e {
+            MouseEvent::ScrollDown(_, _, _) => bk.next_chapter(),
+            MouseEvent::ScrollUp(_, _, _) => bk.prev_chapter(),
+            _ => (),
+        }
+    }
+    fn on_key(&self, bk: &mut Bk, key: Key) {
+        match bk.keys.action(Mode::Picture, key) {
+            Some(Action::Quit) => bk.view = None,
+            Some(Action::Toc) => {
+                Nav.start(bk);
+            }
+            Some(Action::Help) => Help.start(bk),
+            Some(Action::Metadata) => bk.view = Some(&Metadata),
+            Some(Action::Theme) => bk.next_theme(),
+            Some(Action::Bottom) => {
+                bk.mark('\'');
+                bk.chapter = bk.chapters.len() - 1;
+            }
+            Some(Action::Top) => {
+                bk.mark('\'');
+                bk.chapter = 0;
+            }
+            Some(Action::PrevPage) => bk.prev_chapter(),
+            Some(Action::NextPage) => bk.next_chapter(),
+            _ => (),
+        }
+    }
+    fn render(&self, bk: &Bk) -> Vec<String> {
+        let c = bk.chap();
+        let mut buf = vec![String::new(); bk.rows.saturating_sub(1)];
+        let rows = buf.len() as u16;
+        let drawn = match c.images.first() {
+            Some(&(_, i)) => bk
+                .image(i)
+                .and_then(|data| img::draw(&data, bk.protocol, bk.cols, rows)),
+            None => Err(anyhow::anyhow!("no image")),
+        };
+        match drawn {
+            Ok((s, cols, _)) if !buf.is_empty() => {
+                // center on the screen, ignoring the text width.
+                // this crossterm passes the column through, and the escape is 1-based
+                let col = (bk.cols - cols) / 2 + 1;
+                buf[0] = format!("{}{}", MoveToColumn(col), s);
+            }
+            Ok(_) => (),
+            Err(e) => buf.insert(0, e.to_string()),
+        }
+        buf.push(format!("{} / {}", bk.chapter + 1, bk.chapters.len()));
+        buf
+    }
+}
+
+// the cover, centered, until any key
+pub struct Cover;
+impl View for Cover {
+    fn on_mouse(&self, bk: &mut Bk, e: MouseEvent) {
+        if let MouseEvent::Down(..) = e {
+            bk.view = Some(bk.page());
+        }
+    }
+    fn on_key(&self, bk: &mut Bk, _: Key) {
+        bk.view = Some(bk.page());
+    }
+    fn render(&self, bk: &Bk) -> Vec<String> {
+        let mut buf = vec![String::new(); bk.rows];
+        let drawn = match bk.cover {
+            Some(i) => bk
+                .image(i)
+                .and_then(|data| img::draw(&data, bk.protocol, bk.cols, bk.rows as u16)),
+            None => return buf,
+        };
+        match drawn {
+            Ok((s, cols, rows)) if !buf.is_empty() => {
+                let col = (bk.cols - cols) / 2 + 1;
+                let row = (bk.rows - rows as usize) / 2;
+                buf[row] = format!("{}{}", MoveToColumn(col), s);
             }
-            s.push_str(&c.text[start..end]);
-            buf.push(s);
+            Ok(_) => (),
+            Err(e) => buf[0] = e.to_string(),
         }
         buf
     }
@@ -374,16 +1974,23 @@ impl View for Page {
 
 pub struct Search;
 impl View for Search {
-    fn on_key(&self, bk: &mut Bk, kc: KeyCode) {
-        match kc {
-            Esc => {
+    fn on_key(&self, bk: &mut Bk, key: Key) {
+        match (bk.keys.action(Mode::Search, key), key.code) {
+            (Some(Action::Close), _) => {
+                bk.searching = false;
                 bk.jump_reset();
                 bk.view = Some(&Page);
             }
-            Enter => {
+            (Some(Action::Accept), _) if !bk.list_results => {
+                bk.searching = false;
                 bk.view = Some(&Page);
             }
-            Backspace => {
+            (Some(Action::Accept | Action::ListMatches), _) => {
+                bk.searching = false;
+                Results.start(bk);
+            }
+            (Some(_), _) => (),
+            (None, Backspace) => {
                 bk.query.pop();
                 bk.jump_reset();
                 bk.search(SearchArgs {
@@ -391,7 +1998,7 @@ impl View for Search {
                     skip: false,
                 });
             }
-            Char(c) => {
+            (None, Char(c)) if !key.ctrl => {
                 bk.query.push(c);
                 let args = SearchArgs {
                     dir: bk.dir.clone(),
@@ -405,19 +2012,238 @@ impl View for Search {
         }
     }
     fn render(&self, bk: &Bk) -> Vec<String> {
-        let mut buf = Page::render(&Page, bk);
-        if buf.len() == bk.rows {
-            buf.pop();
-        } else {
-            for _ in buf.len()..bk.rows - 1 {
-                buf.push(String::new());
-            }
-        }
         let prefix = match bk.dir {
             Direction::Next => '/',
             Direction::Prev => '?',
         };
-        buf.push(format!("{}{}", prefix, bk.query));
-        buf
+        let prefix = if bk.chapter_search {
+            format!("(chapter) {}", prefix)
+        } else {
+            prefix.to_string()
+        };
+        let found = match bk.match_index() {
+            Some((_, 0)) => String::from("  no matches"),
+            Some((i, n)) => format!("  match {} of {}", i, n),
+            None => String::new(),
+        };
+        let found = match &bk.message {
+            Some(message) => format!("{}  {}", found, message),
+            None => found,
+        };
+        prompt(
+            Page::render(&Page, bk),
+            bk,
+            format!("{}{}{}", prefix, bk.query, found),
+        )
+    }
+}
+
+// line in the last row, over the page
+fn prompt(mut buf: Vec<String>, bk: &Bk, line: String) -> Vec<String> {
+    buf.resize(bk.rows.saturating_sub(1), String::new());
+    buf.push(line);
+    buf
+}
+
+const COMMANDS: &[&str] = &[
+    "b", "bn", "bp", "chapter", "goto", "jump", "loc", "ls", "mark", "page", "q", "set", "source",
+    "toc", "w", "wq",
+];
+const OPTIONS: &[&str] = &[
+    "chaptersearch",
+    "columns",
+    "fold",
+    "header",
+    "hlsearch",
+    "justify",
+    "line-height=",
+    "nochaptersearch",
+    "nocolumns",
+    "nofold",
+    "noheader",
+    "nohlsearch",
+    "nojustify",
+    "noregex",
+    "noresults",
+    "nostatus",
+    "noword",
+    "nowrapscan",
+    "overlap=",
+    "regex",
+    "results",
+    "status",
+    "width=",
+    "word",
+    "wrapscan",
+];
+
+// ex commands, typed after :
+pub struct Command;
+impl Command {
+    fn run(&self, bk: &mut Bk, line: &str) -> anyhow::Result<()> {
+        let (name, arg) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+        let arg = arg.trim();
+        let number = || match arg.parse::<usize>() {
+            Ok(n) if n > 0 => Ok(n),
+            _ => Err(anyhow::anyhow!("{} needs a number", name)),
+        };
+        match name {
+            "" => (),
+            "toc" => Nav.start(bk),
+            "chapter" => bk.jump((min(number()?, bk.chapters.len()) - 1, 0)),
+            // the print edition's page, where the book lists them
+            "page" if !bk.page_list.is_empty() => {
+                if !bk.goto_print_page(arg) {
+                    anyhow::bail!("no page {}", arg);
+                }
+            }
+            "page" => bk.goto_page(number()? - 1),
+            "loc" => bk.goto_location(number()?),
+            "source" => Source.start(bk)?,
+            "goto" => match arg.trim_end_matches('%').parse::<usize>() {
+                Ok(n) => bk.goto_percent(min(n, 100)),
+                Err(_) => anyhow::bail!("goto needs a percent"),
+            },
+            "mark" => match *arg.chars().collect::<Vec<_>>() {
+                [] => anyhow::bail!("mark needs a letter or a name"),
+                [c] => bk.mark(c),
+                _ => {
+                    let pos = (bk.chapter, bk.chap().lines[bk.line].0);
+                    bk.named.insert(arg.to_string(), pos);
+                }
+            },
+            "jump" if !bk.jump_named(arg) => anyhow::bail!("no mark {}", arg),
+            "jump" => (),
+            "set" => self.set(bk, arg)?,
+            "b" if number()? > bk.books.len() => anyhow::bail!("no book {}", arg),
+            "b" => bk.switch_to(number()? - 1),
+            "bn" | "bp" if bk.books.len() < 2 => anyhow::bail!("only one book is open"),
+            "bn" => bk.switch_to((bk.book + 1) % bk.books.len()),
+            "bp" => bk.switch_to((bk.book + bk.books.len() - 1) % bk.books.len()),
+            "ls" => {
+                let books: Vec<_> = bk
+                    .books
+                    .iter()
+                    .enumerate()
+                    .map(|(i, name)| {
+                        let current = if i == bk.book { "*" } else { "" };
+                        format!("{}{} {}", i + 1, current, name)
+                    })
+                    .collect();
+                bk.message = Some(books.join("  "));
+            }
+            "w" => bk.write = true,
+            // quitting saves anyway
+            "q" | "wq" => bk.view = None,
+            _ => anyhow::bail!("not a command: {}", name),
+        }
+        Ok(())
+    }
+    fn set(&self, bk: &mut Bk, arg: &str) -> anyhow::Result<()> {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name.trim(), Some(value.trim())),
+            None => (arg, None),
+        };
+        match (name, value) {
+            ("justify", None) => bk.justify = true,
+            ("nojustify", None) => bk.justify = false,
+            ("status", None) => bk.status = true,
+            ("nostatus", None) => bk.status = false,
+            ("header" | "noheader", None) => {
+                bk.header = name == "header";
+                bk.paginate();
+            }
+            ("columns" | "nocolumns", None) => {
+                bk.columns = name == "columns";
+                bk.paginate();
+            }
+            ("hlsearch", None) => bk.hlsearch = true,
+            ("nohlsearch", None) => bk.hlsearch = false,
+            ("fold" | "nofold", None) => {
+                bk.fold = name == "fold";
+                bk.refold();
+            }
+            ("chaptersearch", None) => bk.chapter_search = true,
+            ("nochaptersearch", None) => bk.chapter_search = false,
+            ("wrapscan", None) => bk.wrapscan = true,
+            ("nowrapscan", None) => bk.wrapscan = false,
+            ("word", None) => bk.word = true,
+            ("noword", None) => bk.word = false,
+            ("regex", None) => bk.regex = true,
+            ("noregex", None) => bk.regex = false,
+            ("results", None) => bk.list_results = true,
+            ("noresults", None) => bk.list_results = false,
+            ("width", Some(value)) => match value.parse() {
+                Ok(width) if width >= 10 => {
+                    bk.max_width = width;
+                    bk.rewrap();
+                }
+                _ => anyhow::bail!("width is a number from 10"),
+            },
+            ("overlap", Some(value)) => match value.parse() {
+                Ok(overlap) => bk.overlap = overlap,
+                _ => anyhow::bail!("overlap is a number of lines"),
+            },
+            ("line-height", Some(value)) => match value.parse::<f32>() {
+                Ok(height) if height >= 1.0 => {
+                    bk.line_height = height;
+                    bk.paginate();
+                }
+                _ => anyhow::bail!("line-height is a number from 1"),
+            },
+            _ => anyhow::bail!("can't set {}", arg),
+        }
+        Ok(())
+    }
+    // the command or option name, as far as it's the only one
+    fn complete(&self, bk: &mut Bk) {
+        let (done, word, names) = match bk.command.rsplit_once(' ') {
+            None => (0, bk.command.as_str(), COMMANDS),
+            Some(("set", word)) => (4, word, OPTIONS),
+            Some(_) => return,
+        };
+        let matches: Vec<_> = names.iter().filter(|n| n.starts_with(word)).collect();
+        let first = match matches.first() {
+            Some(first) => first,
+            None => return,
+        };
+        let common = matches.iter().fold(first.len(), |len, m| {
+            first
+                .bytes()
+                .zip(m.bytes())
+                .take(len)
+                .take_while(|(a, b)| a == b)
+                .count()
+        });
+        let mut line = format!("{}{}", &bk.command[..done], &first[..common]);
+        if matches.len() == 1 && done == 0 {
+            line.push(' ');
+        }
+        bk.command = line;
+    }
+}
+impl View for Command {
+    fn on_key(&self, bk: &mut Bk, key: Key) {
+        match (bk.keys.action(Mode::Command, key), key.code) {
+            (Some(Action::Close), _) => bk.view = Some(&Page),
+            (Some(Action::Accept), _) => {
+                bk.view = Some(&Page);
+                let line = mem::take(&mut bk.command);
+                if let Err(e) = self.run(bk, &line) {
+                    bk.message = Some(e.to_string());
+                }
+            }
+            (Some(Action::Complete), _) => self.complete(bk),
+            (Some(_), _) => (),
+            (None, Backspace) if bk.command.is_empty() => bk.view = Some(&Page),
+            (None, Backspace) => {
+                bk.command.pop();
+            }
+            (None, Char(c)) if !key.ctrl => bk.command.push(c),
+            _ => (),
+        }
+    }
+    fn render(&self, bk: &Bk) -> Vec<String> {
+        prompt(Page::render(&Page, bk), bk, format!(":{}", bk.command))
     }
 }